@@ -0,0 +1,89 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::dma::{AdcDma, Channels, Half};
+
+use crate::hal::{prelude::*, serial::Serial, stm32};
+
+use cortex_m_rt::entry;
+
+use core::fmt::Write;
+
+// ADC channels of the internal temperature sensor and voltage reference
+const VTEMP: u8 = 16;
+const VREF: u8 = 17;
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let (mut tx, mut transfer) = cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+            let gpioa = p.GPIOA.split(&mut rcc);
+
+            // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+            let tx = gpioa.pa2.into_alternate_af1(cs);
+            let rx = gpioa.pa15.into_alternate_af1(cs);
+            let (tx, _) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+            // Initialise ADC and the internal sensors
+            let mut adc = hal::adc::Adc::new(p.ADC, &mut rcc);
+            hal::adc::VTemp::new().enable(&mut adc);
+            hal::adc::VRef::new().enable(&mut adc);
+
+            // The internal sensors need a long sampling time anyway
+            adc.set_sample_time(hal::adc::AdcSampleTime::T_239);
+
+            // Each half holds 8 pairs of temperature and reference readings
+            let buffer = cortex_m::singleton!(: [[u16; 16]; 2] = [[0; 16]; 2]).unwrap();
+
+            let dma = Channels::new(p.DMA1, &mut rcc);
+            let transfer = AdcDma::new(adc, dma.ch1, &[VTEMP, VREF]).circ_read(buffer);
+
+            (tx, transfer)
+        });
+
+        let mut halves: u32 = 0;
+        let mut overruns: u32 = 0;
+
+        loop {
+            let res = transfer.peek(|samples, half| {
+                // Samples alternate between the two channels in ascending channel order
+                let (temp, vref) = samples.chunks(2).fold((0u32, 0u32), |(t, v), pair| {
+                    (t + u32::from(pair[0]), v + u32::from(pair[1]))
+                });
+                (temp / 8, vref / 8, half)
+            });
+
+            match res {
+                Ok(Some((temp, vref, half))) => {
+                    halves += 1;
+
+                    // Printing takes longer than filling a half, so only do it now and then
+                    if halves % 64 == 0 {
+                        let half = if half == Half::First { 1 } else { 2 };
+                        writeln!(
+                            tx,
+                            "Half {}: VTemp raw {}, VRef raw {}, {} overruns\r",
+                            half, temp, vref, overruns
+                        )
+                        .ok();
+                    }
+                }
+                Ok(None) => {}
+                Err(_) => {
+                    overruns += 1;
+                    transfer.clear_overrun();
+                }
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
@@ -0,0 +1,80 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    capture::{self, Capture},
+    pwm::Pwm,
+};
+
+use crate::hal::{
+    prelude::*,
+    serial::Serial,
+    stm32::{self, interrupt},
+};
+
+use cortex_m_rt::entry;
+
+use core::fmt::Write;
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+        let gpioa = p.GPIOA.split(&mut rcc);
+        let gpiob = p.GPIOB.split(&mut rcc);
+
+        let (tx, rx, output, input) = cortex_m::interrupt::free(|cs| {
+            (
+                // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+                gpioa.pa2.into_alternate_af1(cs),
+                gpioa.pa15.into_alternate_af1(cs),
+                // Connect D9 to D12 to measure our own signal
+                gpioa.pa8.into_alternate_af2(cs),
+                gpiob.pb4.into_alternate_af1(cs),
+            )
+        });
+
+        let (mut tx, _) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+        // 1 kHz with 30% duty cycle on D9
+        let (mut tim1, channels) = Pwm::new(p.TIM1, 1.khz(), &mut rcc);
+        let mut output = tim1.output(output, channels.c1);
+        output.set_duty_percent(30);
+        output.enable();
+
+        // Measure on D12
+        let mut capture = Capture::new(p.TIM3, input, &mut rcc);
+
+        loop {
+            if let Some(measurement) = capture.read() {
+                let mhz = measurement.millihertz();
+                let duty = measurement.duty_per_mille();
+                writeln!(
+                    tx,
+                    "{}.{:03} Hz, period {} ns, duty {}.{}%, missed {}\r",
+                    mhz / 1000,
+                    mhz % 1000,
+                    measurement.period_ns(),
+                    duty / 10,
+                    duty % 10,
+                    capture.missed()
+                )
+                .ok();
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+#[interrupt]
+fn TIM3() {
+    capture::on_interrupt::<stm32::TIM3>();
+}
@@ -0,0 +1,103 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    encoder::{self, Encoder, IndexMode},
+    exti::{Exti, Level, Trigger},
+    time::{Duration, Monotonic, Periodic},
+};
+
+use crate::hal::{
+    prelude::*,
+    serial::Serial,
+    stm32::{self, interrupt},
+};
+
+use cortex_m_rt::entry;
+
+use core::fmt::Write;
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+        let gpioa = p.GPIOA.split(&mut rcc);
+
+        let (tx, rx, a, b, index) = cortex_m::interrupt::free(|cs| {
+            (
+                // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+                gpioa.pa2.into_alternate_af1(cs),
+                gpioa.pa15.into_alternate_af1(cs),
+                // Encoder signals A and B on A5 and A6, the index pulse on D2
+                gpioa.pa6.into_alternate_af1(cs),
+                gpioa.pa7.into_alternate_af1(cs),
+                gpioa.pa12.into_pull_up_input(cs),
+            )
+        });
+
+        let (mut tx, _) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+        // The clock is needed for the velocity
+        Monotonic::tim2(p.TIM2, &mut rcc);
+
+        let mut encoder =
+            Encoder::new(p.TIM3, (a, b), &mut rcc).with_velocity_window(Duration::from_millis(200));
+
+        // Home at the first index pulse
+        encoder.set_index_mode(IndexMode::ResetOnce);
+        let (mut exti, lines) = Exti::new(p.EXTI, p.SYSCFG, &mut rcc);
+        let _index = exti.attach(index, lines.line12, Trigger::RisingEdge, index_pulse);
+
+        let mut report = Periodic::new(Duration::from_millis(20));
+        let mut reports = 0_u32;
+
+        loop {
+            if report.poll() {
+                let velocity = encoder.velocity();
+
+                reports += 1;
+                if reports % 25 == 0 {
+                    writeln!(
+                        tx,
+                        "position {}, {} counts/s, {} index pulses\r",
+                        encoder.position(),
+                        velocity,
+                        encoder.index_count()
+                    )
+                    .ok();
+                }
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+// Reset the position at the index pulse
+fn index_pulse(_level: Level) {
+    encoder::on_index::<stm32::TIM3>();
+}
+
+#[interrupt]
+fn EXTI4_15() {
+    Exti::on_interrupt();
+}
+
+// Extend the count of the encoder
+#[interrupt]
+fn TIM3() {
+    encoder::on_interrupt::<stm32::TIM3>();
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
@@ -0,0 +1,91 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    exti::{Exti, Level, Trigger},
+    header::D13,
+    time::{Duration, Monotonic},
+};
+
+use crate::hal::{
+    gpio::{Output, PushPull},
+    prelude::*,
+    stm32::{self, interrupt},
+};
+
+use cortex_m::{
+    interrupt::Mutex,
+    peripheral::{syst::SystClkSource::Core, Peripherals},
+};
+use cortex_m_rt::{entry, exception};
+
+use core::{cell::RefCell, ops::DerefMut};
+
+// Make the LED available to the button handler
+static LED: Mutex<RefCell<Option<D13<Output<PushPull>>>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    if let (Some(mut p), Some(cp)) = (stm32::Peripherals::take(), Peripherals::take()) {
+        cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(8.mhz()).freeze(&mut p.FLASH);
+            let gpioa = p.GPIOA.split(&mut rcc);
+            let gpiob = p.GPIOB.split(&mut rcc);
+
+            // The clock is needed for debouncing
+            Monotonic::tim2(p.TIM2, &mut rcc);
+
+            *LED.borrow(cs).borrow_mut() = Some(gpiob.pb3.into_push_pull_output(cs));
+
+            // A push button between D2 and GND
+            let button = gpioa.pa12.into_pull_up_input(cs);
+
+            let (mut exti, lines) = Exti::new(p.EXTI, p.SYSCFG, &mut rcc);
+            let _button = exti
+                .attach(button, lines.line12, Trigger::FallingEdge, toggle_led)
+                .with_debounce(&mut exti, Duration::from_millis(20));
+
+            // Sample the debounced button every 5 ms, i.e. 8 MHz/40000 counts
+            let mut syst = cp.SYST;
+            syst.set_clock_source(Core);
+            syst.set_reload(40_000 - 1);
+            syst.enable_counter();
+            syst.enable_interrupt();
+        });
+    }
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+// Toggle the LED on every press of the button
+fn toggle_led(_level: Level) {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(ref mut led) = LED.borrow(cs).borrow_mut().deref_mut() {
+            led.toggle().ok();
+        }
+    });
+}
+
+#[interrupt]
+fn EXTI4_15() {
+    Exti::on_interrupt();
+}
+
+// Report the button once it has settled
+#[exception]
+fn SysTick() {
+    Exti::poll();
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
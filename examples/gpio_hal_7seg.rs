@@ -6,13 +6,12 @@ use panic_halt;
 
 use stm32f0xx_hal as hal;
 
+use nucleo_f042k6::seven_segment::Multiplexed;
+
 use cortex_m_rt::{entry, exception};
 
 use crate::hal::{
-    gpio::gpioa::{PA11, PA8},
-    gpio::gpiob::{PB0, PB1, PB4, PB5, PB6, PB7},
-    gpio::gpiof::{PF0, PF1},
-    gpio::{Output, PushPull},
+    gpio::{Output, Pin, PushPull},
     prelude::*,
     stm32,
 };
@@ -24,32 +23,10 @@ use cortex_m::{
 
 use core::cell::RefCell;
 
-use sevensegment::*;
-
-// Define the Mutex so we can share our display with the interrupt handler, blerk
-static DISPLAY: Mutex<
-    RefCell<
-        Option<
-            SevenSeg<
-                PB4<Output<PushPull>>,
-                PB5<Output<PushPull>>,
-                PA11<Output<PushPull>>,
-                PA8<Output<PushPull>>,
-                PF1<Output<PushPull>>,
-                PF0<Output<PushPull>>,
-                PB1<Output<PushPull>>,
-            >,
-        >,
-    >,
-> = Mutex::new(RefCell::new(None));
-
-// The pins we use for the 3 digits on this display
-static ONE: Mutex<RefCell<Option<PB0<Output<PushPull>>>>> = Mutex::new(RefCell::new(None));
-static TWO: Mutex<RefCell<Option<PB7<Output<PushPull>>>>> = Mutex::new(RefCell::new(None));
-static THREE: Mutex<RefCell<Option<PB6<Output<PushPull>>>>> = Mutex::new(RefCell::new(None));
-
-// The number we want to display
-static STATE: Mutex<RefCell<u16>> = Mutex::new(RefCell::new(0));
+type Display = Multiplexed<Pin<Output<PushPull>>, Pin<Output<PushPull>>, 3>;
+
+// Define the Mutex so we can share our display with the interrupt handler
+static DISPLAY: Mutex<RefCell<Option<Display>>> = Mutex::new(RefCell::new(None));
 
 #[entry]
 fn main() -> ! {
@@ -64,24 +41,28 @@ fn main() -> ! {
 
             // The GPIOs we use to drive the display, conveniently located at one side of the Nucleo
             // breadboard connector
-            let one = gpiob.pb0.into_push_pull_output_hs(cs);
-            let two = gpiob.pb7.into_push_pull_output_hs(cs);
-            let three = gpiob.pb6.into_push_pull_output_hs(cs);
-            let seg_a = gpiob.pb4.into_push_pull_output_hs(cs);
-            let seg_b = gpiob.pb5.into_push_pull_output_hs(cs);
-            let seg_c = gpioa.pa11.into_push_pull_output_hs(cs);
-            let seg_d = gpioa.pa8.into_push_pull_output_hs(cs);
-            let seg_e = gpiof.pf1.into_push_pull_output_hs(cs);
-            let seg_f = gpiof.pf0.into_push_pull_output_hs(cs);
-            let seg_g = gpiob.pb1.into_push_pull_output_hs(cs);
+            let digits = [
+                gpiob.pb0.into_push_pull_output_hs(cs).downgrade(),
+                gpiob.pb7.into_push_pull_output_hs(cs).downgrade(),
+                gpiob.pb6.into_push_pull_output_hs(cs).downgrade(),
+            ];
+            let segments = [
+                gpiob.pb4.into_push_pull_output_hs(cs).downgrade(),
+                gpiob.pb5.into_push_pull_output_hs(cs).downgrade(),
+                gpioa.pa11.into_push_pull_output_hs(cs).downgrade(),
+                gpioa.pa8.into_push_pull_output_hs(cs).downgrade(),
+                gpiof.pf1.into_push_pull_output_hs(cs).downgrade(),
+                gpiof.pf0.into_push_pull_output_hs(cs).downgrade(),
+                gpiob.pb1.into_push_pull_output_hs(cs).downgrade(),
+            ];
 
             let mut syst = cp.SYST;
 
             // Set source for SysTick counter, here full operating frequency (== 8MHz)
             syst.set_clock_source(Core);
 
-            // Set reload value, i.e. timer delay 8 MHz/counts
-            syst.set_reload(60_000 - 1);
+            // Refresh 3 digits with 8 brightness steps each at 100 Hz, i.e. 8 MHz/2400 counts
+            syst.set_reload(3_333 - 1);
 
             // Start SysTick counter
             syst.enable_counter();
@@ -89,24 +70,25 @@ fn main() -> ! {
             // Start SysTick interrupt generation
             syst.enable_interrupt();
 
-            // Assign the segments of the 7 segments display to driver
-            let sevenseg = SevenSeg::new(seg_a, seg_b, seg_c, seg_d, seg_e, seg_f, seg_g);
-
-            // Move driver handle and digit enable pins into Mutexes
-            *DISPLAY.borrow(cs).borrow_mut() = Some(sevenseg);
-            *ONE.borrow(cs).borrow_mut() = Some(one);
-            *TWO.borrow(cs).borrow_mut() = Some(two);
-            *THREE.borrow(cs).borrow_mut() = Some(three);
+            // Move the display into the Mutex
+            *DISPLAY.borrow(cs).borrow_mut() = Some(Multiplexed::new(segments, digits));
         });
 
         // Increase a counter that will be displayed
-        let mut counter = 0;
+        let mut counter: u32 = 0;
         loop {
             counter += 1;
 
             if counter % 65536 == 0 {
-                cortex_m::interrupt::free(move |cs| {
-                    *STATE.borrow(cs).borrow_mut() += 1;
+                let value = counter / 65536;
+
+                cortex_m::interrupt::free(|cs| {
+                    if let Some(display) = DISPLAY.borrow(cs).borrow_mut().as_mut() {
+                        display.show_hex(value % 0x1000).ok();
+
+                        // Slowly cycle through the brightness levels
+                        display.set_brightness(1 + (value / 16 % 8) as u8);
+                    }
                 });
             }
         }
@@ -119,47 +101,9 @@ fn main() -> ! {
 
 #[exception]
 fn SysTick() {
-    static mut DIGIT: u8 = 0;
-    use core::ops::{Deref, DerefMut};
-
-    // Enter critical section
     cortex_m::interrupt::free(|cs| {
-        let num = *STATE.borrow(cs).borrow().deref();
-        let display = &*DISPLAY.borrow(cs);
-        let one = &*ONE.borrow(cs);
-        let two = &*TWO.borrow(cs);
-        let three = &*THREE.borrow(cs);
-        if let (Some(ref mut display), Some(ref mut one), Some(ref mut two), Some(ref mut three)) = (
-            display.borrow_mut().deref_mut(),
-            one.borrow_mut().deref_mut(),
-            two.borrow_mut().deref_mut(),
-            three.borrow_mut().deref_mut(),
-        ) {
-            three.set_low().ok();
-            two.set_low().ok();
-            one.set_low().ok();
-
-            *DIGIT = match DIGIT {
-                0 => {
-                    let lsb = num % 16;
-                    three.set_high().ok();
-                    display.display(lsb as u8).ok();
-                    1
-                }
-                1 => {
-                    let middle = (num / 16) % 16;
-                    two.set_high().ok();
-                    display.display(middle as u8).ok();
-                    2
-                }
-                2 => {
-                    let msb = (num / 256) % 16;
-                    one.set_high().ok();
-                    display.display(msb as u8).ok();
-                    0
-                }
-                _ => 0,
-            };
+        if let Some(display) = DISPLAY.borrow(cs).borrow_mut().as_mut() {
+            display.refresh();
         }
     });
 }
@@ -6,6 +6,8 @@ use panic_halt;
 
 use stm32f0xx_hal as hal;
 
+use nucleo_f042k6::{i2c_timeout::TimeoutI2c, time::Duration};
+
 use cortex_m_rt::entry;
 use ssd1306::{mode::TerminalMode, Builder};
 
@@ -34,6 +36,9 @@ fn main() -> ! {
             // Setup I2C1
             let i2c = I2c::i2c1(p.I2C1, (scl, sda), 400.khz(), &mut rcc);
 
+            // Give up instead of hanging if the display misbehaves
+            let i2c = TimeoutI2c::new(i2c, &rcc.clocks, Duration::from_millis(25));
+
             use ssd1306::displayrotation::DisplayRotation;
             let mut disp: TerminalMode<_> =
                 Builder::new().with_i2c_addr(0x3c).connect_i2c(i2c).into();
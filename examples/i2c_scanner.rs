@@ -6,10 +6,12 @@ use panic_halt;
 
 use stm32f0xx_hal as hal;
 
+use nucleo_f042k6::i2c_tools;
+
 use cortex_m_rt::entry;
 
 use crate::hal::{
-    gpio::{gpioa::PA15, gpioa::PA2, gpiof::PF0, gpiof::PF1, Alternate, AF1},
+    gpio::{gpioa::PA15, gpioa::PA2, Alternate, AF1},
     i2c::*,
     prelude::*,
     serial::Serial,
@@ -22,7 +24,8 @@ use core::{cell::RefCell, fmt::Write, ops::DerefMut};
 
 // Make some peripherals globally available
 struct Shared {
-    i2c: hal::i2c::I2c<stm32::I2C1, PF1<Alternate<AF1>>, PF0<Alternate<AF1>>>,
+    i2c: i2c_tools::BoardI2c,
+    clocks: hal::rcc::Clocks,
     serial: hal::serial::Serial<stm32::USART2, PA2<Alternate<AF1>>, PA15<Alternate<AF1>>>,
 }
 
@@ -72,7 +75,12 @@ fn main() -> ! {
                 .ok();
 
             // Move all components under Mutex supervision
-            *SHARED.borrow(cs).borrow_mut() = Some(Shared { serial, i2c });
+            let clocks = rcc.clocks;
+            *SHARED.borrow(cs).borrow_mut() = Some(Shared {
+                serial,
+                i2c,
+                clocks,
+            });
         });
     }
 
@@ -94,29 +102,33 @@ fn USART2() {
             /* Read the character that triggered the interrupt from the USART */
             while serial.read().is_ok() {}
 
-            /* Output address schema for tried addresses */
             let _ = serial.write_str("\r\n");
-            let _ = serial.write_str(
-                "0               1               2               3               4               5               6               7\r\n",
-                );
-            let _ = serial.write_str(
-                "0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF\r\n",
-                );
-
-            // Execute scanning once for each valid I2C address
-            for addr in 0..=0x7f {
-                let res = i2c.write(addr, &[0]);
-
-                // If we received a NACK there's no device on the attempted address
-                let _ = serial.write_str(match res {
-                    Err(Error::NACK) => ".",
-                    _ => "Y",
-                });
+
+            let mut res = i2c_tools::scan(i2c);
+
+            // Try to free a stuck bus once before giving up
+            if let Err(i2c_tools::Error::SdaStuckLow) = res {
+                let _ = serial.write_str("SDA is stuck low, trying to recover the bus...\r\n");
+                if i2c_tools::recover(i2c, &shared.clocks) == i2c_tools::BusState::Idle {
+                    res = i2c_tools::scan(i2c);
+                }
+            }
+
+            match res {
+                Ok(result) => {
+                    let _ = write!(serial, "{}", result);
+                    let _ = write!(
+                        serial,
+                        "\r\nScan done, found {} device(s).\r\n",
+                        result.count()
+                    );
+                }
+                Err(e) => {
+                    let _ = write!(serial, "Scan failed: {:?}\r\n", e);
+                }
             }
 
-            let _ = serial.write_str(
-                "\r\n\r\nScan done.\r\n'Y' means a device was found on the I2C address above.\r\n'.' means no device found on that address.\r\nPlease enter any character to start a new scan.\r\n",
-                );
+            let _ = serial.write_str("Please enter any character to start a new scan.\r\n");
         }
 
         // Clear interrupt flag
@@ -0,0 +1,92 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    i2c_tools::BoardI2c,
+    power::{PowerMonitor, DEFAULT_ADDRESS},
+    shared_bus::SharedBus,
+    time::{Duration, Monotonic},
+};
+
+use cortex_m_rt::entry;
+use ssd1306::{displayrotation::DisplayRotation, mode::TerminalMode, Builder};
+
+use crate::hal::{
+    i2c::*,
+    prelude::*,
+    stm32::{self, interrupt},
+};
+
+use core::fmt::Write;
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let bus = cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+            let gpiof = p.GPIOF.split(&mut rcc);
+
+            let scl = gpiof
+                .pf1
+                .into_alternate_af1(cs)
+                .internal_pull_up(cs, true)
+                .set_open_drain(cs);
+            let sda = gpiof
+                .pf0
+                .into_alternate_af1(cs)
+                .internal_pull_up(cs, true)
+                .set_open_drain(cs);
+
+            // Setup I2C1
+            let i2c = I2c::i2c1(p.I2C1, (scl, sda), 400.khz(), &mut rcc);
+
+            // Start the system clock used for scheduling the measurements
+            Monotonic::tim2(p.TIM2, &mut rcc);
+
+            // Share the bus between the display and the power monitor
+            cortex_m::singleton!(: SharedBus<BoardI2c> = SharedBus::new(i2c)).unwrap()
+        });
+
+        let mut disp: TerminalMode<_> = Builder::new()
+            .with_i2c_addr(0x3c)
+            .connect_i2c(bus.i2c())
+            .into();
+
+        disp.set_rotation(DisplayRotation::Rotate180).ok();
+        disp.init().unwrap();
+        disp.clear().ok();
+
+        // INA260 with A0 and A1 tied to GND, measured every 100 ms
+        let mut monitor =
+            PowerMonitor::new(bus.i2c(), DEFAULT_ADDRESS, Duration::from_millis(100)).unwrap();
+
+        loop {
+            if let Ok(Some(sample)) = monitor.poll() {
+                // Go back to the top left corner and overwrite the previous reading
+                disp.set_position(0, 0).ok();
+                write!(
+                    disp,
+                    "{:6}mV {:6}mA",
+                    sample.voltage / 1000,
+                    sample.current / 1000
+                )
+                .ok();
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
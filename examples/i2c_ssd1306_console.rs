@@ -0,0 +1,91 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    display::{Console, StatusPosition, TerminalDisplay},
+    time::{Duration, Monotonic, Periodic},
+};
+
+use cortex_m_rt::entry;
+use ssd1306::{displayrotation::DisplayRotation, mode::TerminalMode, Builder};
+
+use crate::hal::{
+    i2c::*,
+    prelude::*,
+    stm32::{self, interrupt},
+};
+
+use core::fmt::Write;
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let disp = cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+            let gpiof = p.GPIOF.split(&mut rcc);
+
+            let scl = gpiof
+                .pf1
+                .into_alternate_af1(cs)
+                .internal_pull_up(cs, true)
+                .set_open_drain(cs);
+            let sda = gpiof
+                .pf0
+                .into_alternate_af1(cs)
+                .internal_pull_up(cs, true)
+                .set_open_drain(cs);
+
+            // Setup I2C1
+            let i2c = I2c::i2c1(p.I2C1, (scl, sda), 400.khz(), &mut rcc);
+
+            // Start the system clock
+            Monotonic::tim2(p.TIM2, &mut rcc);
+
+            let mut disp: TerminalMode<_> =
+                Builder::new().with_i2c_addr(0x3c).connect_i2c(i2c).into();
+
+            disp.set_rotation(DisplayRotation::Rotate180).ok();
+            disp.init().unwrap();
+            disp.clear().ok();
+
+            disp
+        });
+
+        // 128x64 pixels give 16x8 characters, keep 32 lines of history below a status bar
+        let mut console: Console<_, 16, 32> =
+            Console::new(TerminalDisplay::new(disp, 8)).with_status_bar(1, StatusPosition::Top);
+
+        let mut status = Periodic::new(Duration::from_secs(1));
+        let mut log = Periodic::new(Duration::from_millis(700));
+        let mut count = 0;
+
+        loop {
+            if status.poll() {
+                let uptime = Monotonic::now().as_micros() / 1_000_000;
+                console.set_status(0, format_args!("up {:5}s #{}", uptime, count));
+                console.flush().ok();
+            }
+
+            if log.poll() {
+                // Long lines are wrapped, old lines scroll off the top
+                count += 1;
+                writeln!(console, "event {} happened", count).ok();
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
@@ -0,0 +1,121 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::i2c_target::{Direction, I2cTarget, RegisterMap};
+
+use cortex_m_rt::entry;
+
+use crate::hal::{
+    gpio::{gpiof::PF0, gpiof::PF1, Alternate, AF1},
+    i2c::*,
+    prelude::*,
+    stm32::{self, interrupt},
+};
+
+use cortex_m::interrupt::Mutex;
+
+use core::cell::RefCell;
+
+// A minimal temperature sensor: an ID register, a configuration register and a 16-bit reading
+// which is latched when a read transfer starts
+struct Sensor {
+    config: u8,
+    temperature: u16,
+    latched: u16,
+    reads: u16,
+}
+
+impl RegisterMap for Sensor {
+    fn read(&mut self, _address: u8, register: u8) -> u8 {
+        match register {
+            0x00 => 0xa5,
+            0x01 => self.config,
+            0x02 => (self.latched >> 8) as u8,
+            0x03 => self.latched as u8,
+            _ => 0xff,
+        }
+    }
+
+    fn write(&mut self, _address: u8, register: u8, value: u8) {
+        if register == 0x01 {
+            self.config = value;
+        }
+    }
+
+    fn address_match(&mut self, _address: u8, direction: Direction) {
+        if direction == Direction::Read {
+            self.latched = self.temperature;
+            self.reads = self.reads.wrapping_add(1);
+        }
+    }
+}
+
+type Target = I2cTarget<PF1<Alternate<AF1>>, PF0<Alternate<AF1>>, Sensor>;
+
+static TARGET: Mutex<RefCell<Option<Target>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+            let gpiof = p.GPIOF.split(&mut rcc);
+
+            let scl = gpiof
+                .pf1
+                .into_alternate_af1(cs)
+                .internal_pull_up(cs, true)
+                .set_open_drain(cs);
+            let sda = gpiof
+                .pf0
+                .into_alternate_af1(cs)
+                .internal_pull_up(cs, true)
+                .set_open_drain(cs);
+
+            // Setup I2C1, the speed only matters for the timing of the data hold time here
+            let i2c = I2c::i2c1(p.I2C1, (scl, sda), 100.khz(), &mut rcc);
+
+            let sensor = Sensor {
+                config: 0,
+                temperature: 0,
+                latched: 0,
+                reads: 0,
+            };
+
+            // Answer as sensor at 0x48, as well as at 0x49 like a second sensor would
+            let target = I2cTarget::new(i2c, 0x48, sensor).with_second_address(0x49);
+
+            *TARGET.borrow(cs).borrow_mut() = Some(target);
+        });
+
+        // Simulate a slowly rising temperature
+        loop {
+            cortex_m::asm::delay(4_800_000);
+
+            cortex_m::interrupt::free(|cs| {
+                if let Some(target) = TARGET.borrow(cs).borrow_mut().as_mut() {
+                    let sensor = target.map();
+                    sensor.temperature = sensor.temperature.wrapping_add(1);
+                }
+            });
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+#[interrupt]
+fn I2C1() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(target) = TARGET.borrow(cs).borrow_mut().as_mut() {
+            target.on_interrupt();
+        }
+    });
+}
@@ -0,0 +1,105 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    exti::{Exti, Level, Trigger},
+    power::{LowPower, Regulator, WakeupPin},
+    time::Monotonic,
+};
+
+use crate::hal::{
+    prelude::*,
+    serial::Serial,
+    stm32::{self, interrupt},
+};
+
+use cortex_m_rt::entry;
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use nb::block;
+
+// Set by the button to go to Standby mode
+static STANDBY: AtomicBool = AtomicBool::new(false);
+
+#[entry]
+fn main() -> ! {
+    if let (Some(mut p), Some(mut cp)) = (stm32::Peripherals::take(), cortex_m::Peripherals::take())
+    {
+        let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+        let gpioa = p.GPIOA.split(&mut rcc);
+
+        let (tx, rx, button) = cortex_m::interrupt::free(|cs| {
+            (
+                // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+                gpioa.pa2.into_alternate_af1(cs),
+                gpioa.pa15.into_alternate_af1(cs),
+                // A push button between D2 and GND
+                gpioa.pa12.into_pull_up_input(cs),
+            )
+        });
+
+        let (mut tx, _rx) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+        // The clock measures the wake latency
+        Monotonic::tim2(p.TIM2, &mut rcc);
+
+        let mut power = LowPower::new(p.PWR, &mut rcc);
+        if power.woke_from_standby() {
+            writeln!(tx, "\r\nWoke up from Standby\r").ok();
+        }
+
+        // Wake up from Stop mode on the button and on characters received from the ST-Link
+        let (mut exti, lines) = Exti::new(p.EXTI, p.SYSCFG, &mut rcc);
+        let _button = exti.attach(button, lines.line12, Trigger::FallingEdge, request_standby);
+        power.listen_usart2_rx(&mut exti, lines.line15);
+
+        loop {
+            if STANDBY.load(Ordering::Relaxed) {
+                writeln!(tx, "Entering Standby, pull A0 high to wake up\r").ok();
+                block!(tx.flush()).ok();
+
+                power.enable_wakeup_pin(WakeupPin::Wkup1);
+                power.standby(&mut cp.SCB);
+            }
+
+            writeln!(tx, "Entering Stop, press a key or the button to wake up\r").ok();
+            block!(tx.flush()).ok();
+
+            let wakeup = power.stop(&mut cp.SCB, Regulator::LowPower, &rcc);
+            writeln!(
+                tx,
+                "Clocks restored after {} us\r",
+                wakeup.latency.as_micros()
+            )
+            .ok();
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+fn request_standby(_level: Level) {
+    STANDBY.store(true, Ordering::Relaxed);
+}
+
+#[interrupt]
+fn EXTI4_15() {
+    Exti::on_interrupt();
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
@@ -0,0 +1,76 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::time::{Duration, Monotonic, Periodic};
+
+use crate::hal::{
+    prelude::*,
+    serial::Serial,
+    stm32::{self, interrupt},
+};
+
+use cortex_m_rt::entry;
+
+use core::fmt::Write;
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let (mut led, mut tx, _clock) = cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+            let gpioa = p.GPIOA.split(&mut rcc);
+            let gpiob = p.GPIOB.split(&mut rcc);
+
+            // (Re-)configure PB3 as output
+            let led = gpiob.pb3.into_push_pull_output(cs);
+
+            // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+            let tx = gpioa.pa2.into_alternate_af1(cs);
+            let rx = gpioa.pa15.into_alternate_af1(cs);
+            let (tx, _) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+            // Start the system clock
+            let clock = Monotonic::tim2(p.TIM2, &mut rcc);
+
+            (led, tx, clock)
+        });
+
+        let mut blink = Periodic::new(Duration::from_millis(250));
+        let mut report = Periodic::new(Duration::from_secs(1));
+
+        loop {
+            if blink.poll() {
+                led.toggle().ok();
+            }
+
+            if report.poll() {
+                // Measure how long formatting and sending the report takes
+                let start = Monotonic::now();
+                let uptime = start.as_micros();
+                write!(
+                    tx,
+                    "Uptime {}.{:06}s",
+                    uptime / 1_000_000,
+                    uptime % 1_000_000
+                )
+                .ok();
+                writeln!(tx, ", took {}us\r", start.elapsed().as_micros()).ok();
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
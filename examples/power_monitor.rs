@@ -0,0 +1,120 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    exti::Exti,
+    power::{Alert, AlertPin, PowerMonitor, DEFAULT_ADDRESS},
+    time::{Duration, Monotonic, Periodic},
+};
+
+use crate::hal::{
+    i2c::I2c,
+    prelude::*,
+    serial::Serial,
+    stm32::{self, interrupt},
+};
+
+use cortex_m::interrupt::Mutex;
+use cortex_m_rt::entry;
+
+use core::{
+    cell::RefCell,
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+// The ALERT pin and EXTI for the interrupt handler
+static SHARED: Mutex<RefCell<Option<(AlertPin, Exti)>>> = Mutex::new(RefCell::new(None));
+
+// Set by the interrupt handler when the INA260 pulled ALERT low
+static ALERT: AtomicBool = AtomicBool::new(false);
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let (mut tx, i2c) = cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+            let gpioa = p.GPIOA.split(&mut rcc);
+            let gpiof = p.GPIOF.split(&mut rcc);
+
+            // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+            let tx = gpioa.pa2.into_alternate_af1(cs);
+            let rx = gpioa.pa15.into_alternate_af1(cs);
+            let (tx, _) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+            // INA260 on I2C1
+            let scl = gpiof
+                .pf1
+                .into_alternate_af1(cs)
+                .internal_pull_up(cs, true)
+                .set_open_drain(cs);
+            let sda = gpiof
+                .pf0
+                .into_alternate_af1(cs)
+                .internal_pull_up(cs, true)
+                .set_open_drain(cs);
+            let i2c = I2c::i2c1(p.I2C1, (scl, sda), 400.khz(), &mut rcc);
+
+            // ALERT of the INA260 connected to D2
+            let alert = gpioa.pa12.into_pull_up_input(cs);
+            let (mut exti, lines) = Exti::new(p.EXTI, p.SYSCFG, &mut rcc);
+            let alert = AlertPin::new(alert, &mut exti, lines.line12);
+            *SHARED.borrow(cs).borrow_mut() = Some((alert, exti));
+
+            // Start the system clock used for scheduling and energy accounting
+            Monotonic::tim2(p.TIM2, &mut rcc);
+
+            (tx, i2c)
+        });
+
+        let mut monitor =
+            PowerMonitor::new(i2c, DEFAULT_ADDRESS, Duration::from_millis(50)).unwrap();
+
+        // Complain as soon as the board draws more than 100 mA
+        monitor.set_alert(Some(Alert::OverCurrent(100_000))).ok();
+
+        let mut report = Periodic::new(Duration::from_secs(1));
+
+        loop {
+            monitor.poll().ok();
+
+            // No atomic swap on Cortex-M0, but the handler only ever sets the flag
+            if ALERT.load(Ordering::Relaxed) {
+                ALERT.store(false, Ordering::Relaxed);
+                if let Ok(true) = monitor.clear_alert() {
+                    tx.write_str("ALERT: current above 100mA\r\n").ok();
+                }
+            }
+
+            if report.poll() {
+                monitor.report(&mut tx).ok();
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
+
+#[interrupt]
+fn EXTI4_15() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some((alert, exti)) = SHARED.borrow(cs).borrow_mut().as_mut() {
+            if alert.check(exti) {
+                ALERT.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+}
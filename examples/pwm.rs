@@ -0,0 +1,57 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::pwm::Pwm;
+
+use crate::hal::{delay::Delay, prelude::*, stm32};
+
+use cortex_m::peripheral::Peripherals;
+use cortex_m_rt::entry;
+
+#[entry]
+fn main() -> ! {
+    if let (Some(mut p), Some(cp)) = (stm32::Peripherals::take(), Peripherals::take()) {
+        let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+        let gpioa = p.GPIOA.split(&mut rcc);
+        let gpiob = p.GPIOB.split(&mut rcc);
+        let mut delay = Delay::new(cp.SYST, &rcc);
+
+        let (led, high, low) = cortex_m::interrupt::free(|cs| {
+            (
+                // The user LED on D13
+                gpiob.pb3.into_alternate_af2(cs),
+                // Half bridge driven by D9 and its complement on A6
+                gpioa.pa8.into_alternate_af2(cs),
+                gpioa.pa7.into_alternate_af2(cs),
+            )
+        });
+
+        // 20 kHz with 500 ns dead-time and 25% duty cycle for the half bridge
+        let (mut tim1, channels) = Pwm::new(p.TIM1, 20.khz(), &mut rcc);
+        tim1.set_dead_time(500);
+        let mut bridge = tim1.output((high, low), channels.c1);
+        bridge.set_duty_percent(25);
+        bridge.enable();
+
+        // Fade the LED at 1 kHz
+        let (mut tim2, channels) = Pwm::new(p.TIM2, 1.khz(), &mut rcc);
+        let mut led = tim2.output(led, channels.c2);
+        led.enable();
+
+        loop {
+            for percent in (0..=100).chain((0..100).rev()) {
+                led.set_duty_percent(percent);
+                delay.delay_ms(10_u16);
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
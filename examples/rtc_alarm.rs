@@ -0,0 +1,101 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    exti::Exti,
+    power::LowPower,
+    rtc::{Alarm, DateTime, Rtc},
+};
+
+use crate::hal::{
+    prelude::*,
+    serial::Serial,
+    stm32::{self, interrupt, Interrupt},
+};
+
+use cortex_m::interrupt::Mutex;
+use cortex_m_rt::entry;
+
+use core::{cell::RefCell, fmt::Write, ops::DerefMut};
+
+// Make the RTC and EXTI available to the interrupt handler
+static SHARED: Mutex<RefCell<Option<(Rtc, Exti)>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    if let (Some(mut p), Some(mut cp)) = (stm32::Peripherals::take(), cortex_m::Peripherals::take())
+    {
+        let (mut tx, mut power, rcc) = cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(8.mhz()).freeze(&mut p.FLASH);
+            let gpioa = p.GPIOA.split(&mut rcc);
+
+            // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+            let tx = gpioa.pa2.into_alternate_af1(cs);
+            let rx = gpioa.pa15.into_alternate_af1(cs);
+            let (mut tx, _) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+            // Start the RTC and trim it against the HSI
+            let mut power = LowPower::new(p.PWR, &mut rcc);
+            let mut rtc = Rtc::new(p.RTC, power.pwr(), &mut rcc).unwrap();
+            let lsi = rtc.calibrate(&mut p.TIM14, &mut rcc).unwrap();
+            writeln!(tx, "\r\nLSI measured at {} Hz\r", lsi).ok();
+
+            rtc.set_datetime(&DateTime::new(2020, 6, 1, 12, 0, 0).unwrap())
+                .unwrap();
+
+            // Wake up every second
+            let (mut exti, _lines) = Exti::new(p.EXTI, p.SYSCFG, &mut rcc);
+            rtc.set_alarm(&Alarm::every_second(), &mut exti).unwrap();
+
+            *SHARED.borrow(cs).borrow_mut() = Some((rtc, exti));
+
+            // Enable RTC IRQ and clear any pending IRQs
+            unsafe {
+                cortex_m::peripheral::NVIC::unmask(Interrupt::RTC);
+            }
+            cortex_m::peripheral::NVIC::unpend(Interrupt::RTC);
+
+            (tx, power, rcc)
+        });
+
+        loop {
+            // Sleep in Stop mode until the next alarm
+            let now = cortex_m::interrupt::free(|cs| {
+                if let Some((ref mut rtc, _)) = SHARED.borrow(cs).borrow_mut().deref_mut() {
+                    rtc.stop_until_alarm(&mut power, &mut cp.SCB, &rcc);
+                    Some(rtc.datetime())
+                } else {
+                    None
+                }
+            });
+
+            if let Some(now) = now {
+                writeln!(
+                    tx,
+                    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}\r",
+                    now.year, now.month, now.day, now.hour, now.minute, now.second
+                )
+                .ok();
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+// The alarm interrupt, only used to wake the MCU up from Stop mode
+#[interrupt]
+fn RTC() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some((ref mut rtc, ref mut exti)) = SHARED.borrow(cs).borrow_mut().deref_mut() {
+            rtc.check_alarm(exti);
+        }
+    });
+}
@@ -0,0 +1,133 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+#[rtic::app(device = stm32f0xx_hal::stm32, peripherals = true, dispatchers = [CEC_CAN, USB])]
+mod app {
+    use stm32f0xx_hal as hal;
+
+    use nucleo_f042k6::monotonic::{Duration, MonoTimer};
+
+    use crate::hal::{
+        gpio::gpioa::{PA11, PA8},
+        gpio::gpiob::{PB0, PB1, PB4, PB5, PB6, PB7},
+        gpio::gpiof::{PF0, PF1},
+        gpio::{Output, PushPull},
+        prelude::*,
+    };
+
+    use sevensegment::*;
+
+    type Display = SevenSeg<
+        PB4<Output<PushPull>>,
+        PB5<Output<PushPull>>,
+        PA11<Output<PushPull>>,
+        PA8<Output<PushPull>>,
+        PF1<Output<PushPull>>,
+        PF0<Output<PushPull>>,
+        PB1<Output<PushPull>>,
+    >;
+
+    #[monotonic(binds = TIM2, default = true)]
+    type Mono = MonoTimer;
+
+    #[shared]
+    struct Shared {
+        // The number we want to display
+        value: u16,
+    }
+
+    #[local]
+    struct Local {
+        display: Display,
+        one: PB0<Output<PushPull>>,
+        two: PB7<Output<PushPull>>,
+        three: PB6<Output<PushPull>>,
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let mut p = cx.device;
+
+        let (local, mono) = cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(8.mhz()).freeze(&mut p.FLASH);
+
+            let gpioa = p.GPIOA.split(&mut rcc);
+            let gpiob = p.GPIOB.split(&mut rcc);
+            let gpiof = p.GPIOF.split(&mut rcc);
+
+            // The GPIOs we use to drive the display, conveniently located at one side of the
+            // Nucleo breadboard connector
+            let one = gpiob.pb0.into_push_pull_output_hs(cs);
+            let two = gpiob.pb7.into_push_pull_output_hs(cs);
+            let three = gpiob.pb6.into_push_pull_output_hs(cs);
+            let seg_a = gpiob.pb4.into_push_pull_output_hs(cs);
+            let seg_b = gpiob.pb5.into_push_pull_output_hs(cs);
+            let seg_c = gpioa.pa11.into_push_pull_output_hs(cs);
+            let seg_d = gpioa.pa8.into_push_pull_output_hs(cs);
+            let seg_e = gpiof.pf1.into_push_pull_output_hs(cs);
+            let seg_f = gpiof.pf0.into_push_pull_output_hs(cs);
+            let seg_g = gpiob.pb1.into_push_pull_output_hs(cs);
+
+            let local = Local {
+                display: SevenSeg::new(seg_a, seg_b, seg_c, seg_d, seg_e, seg_f, seg_g),
+                one,
+                two,
+                three,
+            };
+
+            (local, MonoTimer::new(p.TIM2, &mut rcc))
+        });
+
+        refresh::spawn().ok();
+        count::spawn().ok();
+
+        (Shared { value: 0 }, local, init::Monotonics(mono))
+    }
+
+    // Show the next digit, every digit is lit for 5ms
+    #[task(priority = 2, local = [display, one, two, three, digit: u8 = 0], shared = [value])]
+    fn refresh(mut cx: refresh::Context) {
+        let num = cx.shared.value.lock(|value| *value);
+        let refresh::LocalResources {
+            display,
+            one,
+            two,
+            three,
+            digit,
+        } = cx.local;
+
+        three.set_low().ok();
+        two.set_low().ok();
+        one.set_low().ok();
+
+        *digit = match *digit {
+            0 => {
+                three.set_high().ok();
+                display.display((num % 16) as u8).ok();
+                1
+            }
+            1 => {
+                two.set_high().ok();
+                display.display(((num / 16) % 16) as u8).ok();
+                2
+            }
+            _ => {
+                one.set_high().ok();
+                display.display(((num / 256) % 16) as u8).ok();
+                0
+            }
+        };
+
+        refresh::spawn_after(Duration::millis(5)).ok();
+    }
+
+    // Increase the displayed number ten times a second
+    #[task(shared = [value])]
+    fn count(mut cx: count::Context) {
+        cx.shared.value.lock(|value| *value = value.wrapping_add(1));
+        count::spawn_after(Duration::millis(100)).ok();
+    }
+}
@@ -0,0 +1,125 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+#[rtic::app(device = stm32f0xx_hal::stm32, peripherals = true, dispatchers = [CEC_CAN])]
+mod app {
+    use stm32f0xx_hal as hal;
+
+    use nucleo_f042k6::monotonic::{Duration, MonoTimer};
+
+    use crate::hal::{
+        adc::{Adc, VRef, VTemp},
+        prelude::*,
+        serial::{Serial, Tx},
+        stm32,
+    };
+
+    use core::{fmt::Write, ptr};
+
+    #[monotonic(binds = TIM2, default = true)]
+    type Mono = MonoTimer;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        adc: Adc,
+        temp: VTemp,
+        reference: VRef,
+        tx: Tx<stm32::USART2>,
+    }
+
+    fn calculate_temperature(reading: u16) -> i16 {
+        const VDD_CALIB: i32 = 330;
+        const VDD_APPLI: i32 = 300;
+
+        let cal30 = i32::from(unsafe { ptr::read(0x1FFF_F7B8 as *const u16) });
+        let cal110 = i32::from(unsafe { ptr::read(0x1FFF_F7C2 as *const u16) });
+
+        let mut temperature: i32 = ((i32::from(reading) * VDD_APPLI) / VDD_CALIB) - cal30;
+        temperature *= 110 - 30;
+        temperature /= cal110 - cal30;
+        temperature += 30;
+        temperature as i16
+    }
+
+    fn calculate_vdda(reading: u16) -> u16 {
+        let vrefint = u32::from(unsafe { ptr::read(0x1FFF_F7BA as *const u16) });
+        (3250 * vrefint / u32::from(reading)) as u16
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let mut p = cx.device;
+
+        let (local, mono) = cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().freeze(&mut p.FLASH);
+            let gpioa = p.GPIOA.split(&mut rcc);
+
+            // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+            let tx = gpioa.pa2.into_alternate_af1(cs);
+            let rx = gpioa.pa15.into_alternate_af1(cs);
+            let (mut tx, _) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+            // Initialise ADC and enable the internal sensors
+            let mut adc = Adc::new(p.ADC, &mut rcc);
+            let mut temp = VTemp::new();
+            let mut reference = VRef::new();
+            temp.enable(&mut adc);
+            reference.enable(&mut adc);
+
+            // Output a friendly greeting
+            tx.write_str("\n\rThis ADC example will read various values using the ADC and print them out to the serial terminal\r\n").ok();
+
+            let local = Local {
+                adc,
+                temp,
+                reference,
+                tx,
+            };
+
+            (local, MonoTimer::new(p.TIM2, &mut rcc))
+        });
+
+        // Start the periodic measurements
+        measure::spawn().ok();
+
+        (Shared {}, local, init::Monotonics(mono))
+    }
+
+    // Take a measurement and reschedule ourselves to run again in one second
+    #[task(local = [adc, temp, reference, tx])]
+    fn measure(cx: measure::Context) {
+        let measure::LocalResources {
+            adc,
+            temp,
+            reference,
+            tx,
+        } = cx.local;
+
+        // Take the next deadline before measuring so the time spent here does not add up
+        let next = monotonics::now() + Duration::secs(1);
+
+        // Read raw temperature data from internal sensor using ADC
+        let t: Result<u16, _> = adc.read(temp);
+        if let Ok(t) = t {
+            writeln!(tx, "Temperature {}\r", calculate_temperature(t)).ok();
+        } else {
+            tx.write_str("Error reading temperature").ok();
+        }
+
+        // Read raw volatage reference data from internal sensor using ADC
+        let t: Result<u16, _> = adc.read(reference);
+        if let Ok(t) = t {
+            writeln!(tx, "Vdda {}mV\r", calculate_vdda(t)).ok();
+        } else {
+            tx.write_str("Error reading Vdda").ok();
+        }
+
+        measure::spawn_at(next).ok();
+    }
+}
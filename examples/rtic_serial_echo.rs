@@ -0,0 +1,96 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+#[rtic::app(device = stm32f0xx_hal::stm32, peripherals = true, dispatchers = [CEC_CAN])]
+mod app {
+    use stm32f0xx_hal as hal;
+
+    use nucleo_f042k6::monotonic::{Duration, MonoTimer};
+
+    use crate::hal::{
+        gpio::{gpioa, Alternate, AF1},
+        prelude::*,
+        serial::{Event, Serial},
+        stm32,
+    };
+
+    use core::fmt::Write;
+
+    #[monotonic(binds = TIM2, default = true)]
+    type Mono = MonoTimer;
+
+    #[shared]
+    struct Shared {
+        serial: Serial<stm32::USART2, gpioa::PA2<Alternate<AF1>>, gpioa::PA15<Alternate<AF1>>>,
+    }
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let mut p = cx.device;
+
+        let (serial, mono) = cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+            let gpioa = p.GPIOA.split(&mut rcc);
+
+            // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+            let tx = gpioa.pa2.into_alternate_af1(cs);
+            let rx = gpioa.pa15.into_alternate_af1(cs);
+
+            // Set up serial port and enable interrupt generation for received data
+            let mut serial = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc);
+            serial.listen(Event::Rxne);
+
+            (serial, MonoTimer::new(p.TIM2, &mut rcc))
+        });
+
+        (Shared { serial }, Local {}, init::Monotonics(mono))
+    }
+
+    // Triggered by a received character, hands it over to the echo task
+    #[task(binds = USART2, shared = [serial], priority = 2)]
+    fn usart2(mut cx: usart2::Context) {
+        cx.shared.serial.lock(|serial| {
+            if let Ok(received) = serial.read() {
+                echo::spawn(received).ok();
+            }
+        });
+    }
+
+    // Write a received character back, queued at a lower priority than the reception
+    #[task(shared = [serial], capacity = 8)]
+    fn echo(mut cx: echo::Context, received: u8) {
+        cx.shared.serial.lock(|serial| {
+            nb::block!(serial.write(received)).ok();
+            if received == b'\r' {
+                nb::block!(serial.write(b'\n')).ok();
+            }
+        });
+    }
+
+    // Remind the user of what this is about every 10 seconds
+    #[task(shared = [serial])]
+    fn banner(mut cx: banner::Context) {
+        cx.shared.serial.lock(|serial| {
+            serial
+                .write_str("\r\nTry typing some characters and watch them being echoed.\r\n")
+                .ok();
+        });
+        banner::spawn_after(Duration::secs(10)).ok();
+    }
+
+    #[idle]
+    fn idle(_: idle::Context) -> ! {
+        banner::spawn().ok();
+
+        loop {
+            // Power down a bit while waiting for interrupts
+            cortex_m::asm::wfi();
+        }
+    }
+}
@@ -0,0 +1,95 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    asynch::{self, serial::AsyncSerial},
+    time::Monotonic,
+};
+
+use crate::hal::{
+    gpio::{gpioa, gpiob, Alternate, Output, PushPull, AF1},
+    prelude::*,
+    serial::Serial,
+    stm32::{self, interrupt},
+};
+
+use cortex_m_rt::entry;
+use embassy_executor::Executor;
+use embassy_time::Timer;
+use embedded_io_async::{Read, Write};
+
+type VcpSerial = AsyncSerial<gpioa::PA2<Alternate<AF1>>, gpioa::PA15<Alternate<AF1>>>;
+
+// Echo all received characters straight back
+#[embassy_executor::task]
+async fn echo(mut serial: VcpSerial) {
+    serial
+        .write_all(b"\r\nTry typing some characters and watch them being echoed.\r\n")
+        .await
+        .ok();
+
+    let mut buf = [0; 16];
+    loop {
+        if let Ok(count) = serial.read(&mut buf).await {
+            serial.write_all(&buf[..count]).await.ok();
+        }
+    }
+}
+
+// Show that we're alive by blinking the LED
+#[embassy_executor::task]
+async fn blink(mut led: gpiob::PB3<Output<PushPull>>) {
+    loop {
+        led.toggle().ok();
+        Timer::after_millis(500).await;
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let (serial, led) = cortex_m::interrupt::free(|cs| {
+            let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+            let gpioa = p.GPIOA.split(&mut rcc);
+            let gpiob = p.GPIOB.split(&mut rcc);
+
+            // (Re-)configure PB3 as output
+            let led = gpiob.pb3.into_push_pull_output(cs);
+
+            // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+            let tx = gpioa.pa2.into_alternate_af1(cs);
+            let rx = gpioa.pa15.into_alternate_af1(cs);
+            let serial = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc);
+
+            // Provide the time base for the executor
+            asynch::time_driver::init(Monotonic::tim2(p.TIM2, &mut rcc));
+
+            (AsyncSerial::new(serial), led)
+        });
+
+        let executor = cortex_m::singleton!(: Executor = Executor::new()).unwrap();
+        executor.run(|spawner| {
+            spawner.spawn(echo(serial)).unwrap();
+            spawner.spawn(blink(led)).unwrap();
+        });
+    }
+
+    loop {
+        continue;
+    }
+}
+
+#[interrupt]
+fn TIM2() {
+    asynch::time_driver::on_interrupt();
+}
+
+#[interrupt]
+fn USART2() {
+    asynch::serial::on_interrupt();
+}
@@ -0,0 +1,90 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    servo::{self, Calibration, Servo},
+    time::{Duration, Monotonic, Periodic},
+};
+
+use crate::hal::{
+    prelude::*,
+    stm32::{self, interrupt},
+};
+
+use cortex_m_rt::entry;
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+        let gpiob = p.GPIOB.split(&mut rcc);
+
+        let (pan, tilt, esc) = cortex_m::interrupt::free(|cs| {
+            (
+                gpiob.pb4.into_alternate_af1(cs),
+                gpiob.pb5.into_alternate_af1(cs),
+                gpiob.pb0.into_alternate_af1(cs),
+            )
+        });
+
+        Monotonic::tim2(p.TIM2, &mut rcc);
+
+        // Servos on D12 and D11 and an ESC on D3, all on TIM3 at 50 Hz
+        let (mut tim3, channels) = servo::timer(p.TIM3, 50.hz(), &mut rcc);
+
+        // A servo covering 180° with 0.5 to 2.5 ms whose horn is a bit off center, moving
+        // 90° per second
+        let mut pan = Servo::new(
+            tim3.output(pan, channels.c1),
+            Calibration::new(500, 2500).with_trim(-15),
+        )
+        .with_slew_rate(1000);
+        let mut tilt =
+            Servo::new(tim3.output(tilt, channels.c2), Calibration::default()).with_slew_rate(500);
+        let mut esc = Servo::new(tim3.output(esc, channels.c3), Calibration::default());
+
+        // Arm the ESC with zero throttle
+        esc.set_throttle(0);
+        pan.set_angle(90);
+        tilt.set_angle(90);
+
+        let period = Duration::from_millis(20);
+        let mut update = Periodic::new(period);
+        let mut step = Periodic::new(Duration::from_secs(3));
+        let mut forward = true;
+
+        loop {
+            if update.poll() {
+                pan.update(period);
+                tilt.update(period);
+            }
+
+            // Sweep back and forth
+            if step.poll() {
+                if forward {
+                    pan.set_angle(180);
+                    tilt.set_angle(45);
+                } else {
+                    pan.set_angle(0);
+                    tilt.set_angle(135);
+                }
+                forward = !forward;
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
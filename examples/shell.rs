@@ -0,0 +1,152 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    header::Pin,
+    i2c_tools::BoardI2c,
+    shell::{Args, Command, Context, Error, Shell},
+    time::Monotonic,
+};
+
+use crate::hal::{
+    adc::Adc,
+    gpio::{gpioa::PA0, gpioa::PA1, gpiob::PB0, gpiob::PB3, Analog, Output, PushPull},
+    i2c::I2c,
+    prelude::*,
+    serial::Serial,
+    stm32::{self, interrupt},
+};
+
+use cortex_m_rt::entry;
+
+use core::{convert::Infallible, fmt::Write};
+
+use embedded_hal::digital::v2::OutputPin;
+
+// The hardware the shell has access to
+struct Board {
+    i2c: BoardI2c,
+    adc: Adc,
+    a0: PA0<Analog>,
+    a1: PA1<Analog>,
+    d3: PB0<Output<PushPull>>,
+    led: PB3<Output<PushPull>>,
+}
+
+impl Context for Board {
+    fn i2c(&mut self) -> Option<&mut BoardI2c> {
+        Some(&mut self.i2c)
+    }
+
+    fn adc_read(&mut self, pin: Pin) -> Option<u16> {
+        match pin {
+            Pin::A0 => self.adc.read(&mut self.a0).ok(),
+            Pin::A1 => self.adc.read(&mut self.a1).ok(),
+            _ => None,
+        }
+    }
+
+    fn output(&mut self, pin: Pin) -> Option<&mut dyn OutputPin<Error = Infallible>> {
+        match pin {
+            Pin::D3 => Some(&mut self.d3),
+            Pin::D13 => Some(&mut self.led),
+            _ => None,
+        }
+    }
+}
+
+// Commands of this firmware in addition to the built-in ones
+static COMMANDS: &[Command<Board>] = &[Command {
+    name: "led",
+    args: "<on|off>",
+    help: "Switch the user LED",
+    run: led,
+}];
+
+fn led(args: &mut Args, out: &mut dyn Write, board: &mut Board) -> Result<(), Error> {
+    let on: bool = args.parse()?;
+    args.finish()?;
+
+    if on {
+        board.led.set_high().ok();
+    } else {
+        board.led.set_low().ok();
+    }
+    write!(out, "LED {}\r\n", if on { "on" } else { "off" })?;
+
+    Ok(())
+}
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+        let gpioa = p.GPIOA.split(&mut rcc);
+        let gpiob = p.GPIOB.split(&mut rcc);
+        let gpiof = p.GPIOF.split(&mut rcc);
+
+        let (tx, rx, scl, sda, a0, a1, d3, led) = cortex_m::interrupt::free(|cs| {
+            (
+                // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+                gpioa.pa2.into_alternate_af1(cs),
+                gpioa.pa15.into_alternate_af1(cs),
+                gpiof
+                    .pf1
+                    .into_alternate_af1(cs)
+                    .internal_pull_up(cs, true)
+                    .set_open_drain(cs),
+                gpiof
+                    .pf0
+                    .into_alternate_af1(cs)
+                    .internal_pull_up(cs, true)
+                    .set_open_drain(cs),
+                gpioa.pa0.into_analog(cs),
+                gpioa.pa1.into_analog(cs),
+                gpiob.pb0.into_push_pull_output(cs),
+                gpiob.pb3.into_push_pull_output(cs),
+            )
+        });
+
+        let (mut tx, mut rx) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+        // The clock provides the uptime shown by `info`
+        Monotonic::tim2(p.TIM2, &mut rcc);
+
+        let mut board = Board {
+            i2c: I2c::i2c1(p.I2C1, (scl, sda), 100.khz(), &mut rcc),
+            adc: Adc::new(p.ADC, &mut rcc),
+            a0,
+            a1,
+            d3,
+            led,
+        };
+
+        // A line of up to 64 characters and the last 4 lines in the history
+        let mut shell: Shell<Board, 64, 4> = Shell::new(COMMANDS).with_prompt("nucleo> ");
+
+        tx.write_str("\r\nDebug console, enter 'help' for a list of commands")
+            .ok();
+        shell.start(&mut tx).ok();
+
+        loop {
+            if let Ok(byte) = rx.read() {
+                shell.input(byte, &mut tx, &mut board).ok();
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
@@ -6,16 +6,26 @@ use panic_halt;
 
 use stm32f0xx_hal as hal;
 
-use cortex_m_rt::entry;
+use nucleo_f042k6::{
+    display::{EinkPanel, Refresh},
+    exti::Exti,
+    power::LowPower,
+    rtc::{Alarm, DateTime, Rtc},
+};
 
 use crate::hal::{
     delay::Delay,
     prelude::*,
     spi::Spi,
     spi::{Mode, Phase, Polarity},
-    stm32,
+    stm32::{self, interrupt, Interrupt},
 };
 
+use cortex_m::interrupt::Mutex;
+use cortex_m_rt::entry;
+
+use core::{cell::RefCell, ops::DerefMut};
+
 // the eink library
 use epd_waveshare::{
     color::Black,
@@ -25,66 +35,168 @@ use epd_waveshare::{
 };
 
 // Graphics
-use embedded_graphics::fonts::{Font12x16, Text};
+use embedded_graphics::fonts::{Font12x16, Font6x8, Text};
 use embedded_graphics::pixelcolor::BinaryColor;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::*;
 use embedded_graphics::style::{PrimitiveStyle, TextStyleBuilder};
 
+use numtoa::NumToA;
+
 pub const MODE: Mode = Mode {
     polarity: Polarity::IdleHigh,
     phase: Phase::CaptureOnSecondTransition,
 };
 
+// Make the RTC and EXTI available to the interrupt handler
+static SHARED: Mutex<RefCell<Option<(Rtc, Exti)>>> = Mutex::new(RefCell::new(None));
+
 #[entry]
 fn main() -> ! {
-    if let (Some(mut p), Some(cp)) = (stm32::Peripherals::take(), cortex_m::Peripherals::take()) {
-        cortex_m::interrupt::free(|cs| {
-            let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
-            let gpiob = p.GPIOB.split(&mut rcc);
-            let mut delay = Delay::new(cp.SYST, &rcc);
-
-            // Configure pins for SPI
-            let sck = gpiob.pb3.into_alternate_af0(cs);
-            let miso = gpiob.pb4.into_alternate_af0(cs);
-            let mosi = gpiob.pb5.into_alternate_af0(cs);
+    if let (Some(mut p), Some(mut cp)) = (stm32::Peripherals::take(), cortex_m::Peripherals::take())
+    {
+        let mut rcc = p.RCC.configure().sysclk(8.mhz()).freeze(&mut p.FLASH);
+        let gpiob = p.GPIOB.split(&mut rcc);
+        let mut delay = Delay::new(cp.SYST, &rcc);
+
+        let (sck, miso, mosi, dc, busy, rst, cs) = cortex_m::interrupt::free(|cs| {
+            (
+                // Configure pins for SPI
+                gpiob.pb3.into_alternate_af0(cs),
+                gpiob.pb4.into_alternate_af0(cs),
+                gpiob.pb5.into_alternate_af0(cs),
+                gpiob.pb1.into_push_pull_output(cs),
+                // BUSY on D5 signals the end of a refresh via EXTI
+                gpiob.pb6.into_floating_input(cs),
+                gpiob.pb7.into_push_pull_output(cs),
+                gpiob.pb0.into_push_pull_output(cs),
+            )
+        });
 
-            let dc = gpiob.pb1.into_push_pull_output(cs);
-            let busy = gpiob.pb6.into_floating_input(cs);
-            let rst = gpiob.pb7.into_push_pull_output(cs);
-            let cs = gpiob.pb0.into_push_pull_output(cs);
+        // Configure SPI with 4MHz rate
+        let mut spi = Spi::spi1(p.SPI1, (sck, miso, mosi), MODE, 4_000_000.hz(), &mut rcc);
 
-            // Configure SPI with 1MHz rate
-            let mut spi = Spi::spi1(p.SPI1, (sck, miso, mosi), MODE, 8_000_000.hz(), &mut rcc);
+        let epd = EPD1in54::new(&mut spi, cs, busy, dc, rst, &mut delay).unwrap();
 
-            let mut epd = EPD1in54::new(&mut spi, cs, busy, dc, rst, &mut delay).unwrap();
+        // Clear the ghosting left by partial refreshes every 10 updates
+        let (mut exti, lines) = Exti::new(p.EXTI, p.SYSCFG, &mut rcc);
+        let mut panel = EinkPanel::new(epd, 10, &mut exti, lines.line6);
 
-            // Setup the graphics
-            let mut display = Display1in54::default();
+        // Wake up once a minute to update the dashboard
+        let mut power = LowPower::new(p.PWR, &mut rcc);
+        let mut rtc = Rtc::new(p.RTC, power.pwr(), &mut rcc).unwrap();
+        rtc.calibrate(&mut p.TIM14, &mut rcc).unwrap();
+        rtc.set_datetime(&DateTime::new(2020, 6, 1, 12, 0, 0).unwrap())
+            .unwrap();
+        rtc.set_alarm(&Alarm::every_minute(0), &mut exti).unwrap();
 
-            let text_style = TextStyleBuilder::new(Font12x16)
-                .text_color(BinaryColor::On)
-                .build();
+        cortex_m::interrupt::free(|cs| {
+            *SHARED.borrow(cs).borrow_mut() = Some((rtc, exti));
+        });
 
-            Text::new("Hello Rust!", Point::new(5, 50))
-                .into_styled(text_style)
+        // Enable RTC IRQ and clear any pending IRQs
+        unsafe {
+            cortex_m::peripheral::NVIC::unmask(Interrupt::RTC);
+        }
+        cortex_m::peripheral::NVIC::unpend(Interrupt::RTC);
+
+        let large = TextStyleBuilder::new(Font12x16)
+            .text_color(BinaryColor::On)
+            .background_color(BinaryColor::Off)
+            .build();
+        let small = TextStyleBuilder::new(Font6x8)
+            .text_color(BinaryColor::On)
+            .background_color(BinaryColor::Off)
+            .build();
+
+        // Setup the graphics
+        let mut display = Display1in54::default();
+
+        Text::new("Hello Rust!", Point::new(5, 50))
+            .into_styled(large)
+            .draw(&mut display)
+            .ok();
+
+        Circle::new(Point::new(100, 140), 40)
+            .into_styled(PrimitiveStyle::with_stroke(Black, 1))
+            .draw(&mut display)
+            .ok();
+
+        let mut updates: u32 = 0;
+        let mut full_refreshes: u32 = 0;
+
+        loop {
+            let now = cortex_m::interrupt::free(|cs| {
+                if let Some((ref mut rtc, _)) = SHARED.borrow(cs).borrow_mut().deref_mut() {
+                    Some(rtc.datetime())
+                } else {
+                    None
+                }
+            });
+
+            if let Some(now) = now {
+                let time = [
+                    b'0' + now.hour / 10,
+                    b'0' + now.hour % 10,
+                    b':',
+                    b'0' + now.minute / 10,
+                    b'0' + now.minute % 10,
+                ];
+                Text::new(core::str::from_utf8(&time).unwrap(), Point::new(5, 80))
+                    .into_styled(large)
+                    .draw(&mut display)
+                    .ok();
+            }
+
+            let mut buf = [0u8; 10];
+            Text::new("Updates:", Point::new(5, 110))
+                .into_styled(small)
                 .draw(&mut display)
                 .ok();
-
-            Circle::new(Point::new(80, 80), 80)
-                .into_styled(PrimitiveStyle::with_stroke(Black, 1))
+            Text::new(updates.numtoa_str(10, &mut buf), Point::new(60, 110))
+                .into_styled(small)
+                .draw(&mut display)
+                .ok();
+            Text::new("Full:", Point::new(5, 120))
+                .into_styled(small)
+                .draw(&mut display)
+                .ok();
+            Text::new(full_refreshes.numtoa_str(10, &mut buf), Point::new(60, 120))
+                .into_styled(small)
                 .draw(&mut display)
                 .ok();
 
-            // Transfer the frame data to the epd
-            let _ = epd.update_frame(&mut spi, &display.buffer());
-
-            // Display the frame on the epd
-            let _ = epd.display_frame(&mut spi);
-        });
+            // Start the refresh and stay in Stop mode until the panel is done, then let the
+            // panel sleep as well
+            if let Ok(refresh) = panel.update(&mut spi, &display.buffer(), &mut delay) {
+                updates += 1;
+                if refresh == Refresh::Full {
+                    full_refreshes += 1;
+                }
+            }
+            panel.stop_until_idle(&mut power, &mut cp.SCB, &rcc);
+            panel.sleep(&mut spi).ok();
+
+            // Sleep in Stop mode until the next alarm
+            cortex_m::interrupt::free(|cs| {
+                if let Some((ref mut rtc, _)) = SHARED.borrow(cs).borrow_mut().deref_mut() {
+                    rtc.stop_until_alarm(&mut power, &mut cp.SCB, &rcc);
+                }
+            });
+        }
     }
 
     loop {
         continue;
     }
 }
+
+// The alarm interrupt, only used to wake the MCU up from Stop mode
+#[interrupt]
+fn RTC() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some((ref mut rtc, ref mut exti)) = SHARED.borrow(cs).borrow_mut().deref_mut() {
+            rtc.check_alarm(exti);
+        }
+    });
+}
@@ -0,0 +1,96 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    time::{Duration, Monotonic, Periodic, Timeout},
+    watchdog::Supervisor,
+};
+
+use crate::hal::{
+    prelude::*,
+    serial::Serial,
+    stm32::{self, interrupt},
+};
+
+use cortex_m_rt::entry;
+
+use core::fmt::Write;
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+        let gpioa = p.GPIOA.split(&mut rcc);
+        let gpiob = p.GPIOB.split(&mut rcc);
+
+        let (tx, rx, mut led) = cortex_m::interrupt::free(|cs| {
+            (
+                // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+                gpioa.pa2.into_alternate_af1(cs),
+                gpioa.pa15.into_alternate_af1(cs),
+                gpiob.pb3.into_push_pull_output(cs),
+            )
+        });
+
+        let (mut tx, _) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+        // The clock is needed for the deadlines
+        Monotonic::tim2(p.TIM2, &mut rcc);
+
+        let mut supervisor: Supervisor<2> =
+            Supervisor::new(p.IWDG, &mut p.DBGMCU, Duration::from_millis(500), &mut rcc);
+
+        match supervisor.starved_before_reset() {
+            Some(starved) => writeln!(
+                tx,
+                "\r\nReset after task {} was silent for {} ms\r",
+                starved.task, starved.silent_ms
+            ),
+            None => writeln!(tx, "\r\nStarted\r"),
+        }
+        .ok();
+
+        // The LED blinks every 100 ms, the report is sent every second
+        let blink = supervisor.register(Duration::from_millis(200)).unwrap();
+        let report = supervisor.register(Duration::from_millis(1500)).unwrap();
+
+        let mut blinking = Periodic::new(Duration::from_millis(100));
+        let mut reporting = Periodic::new(Duration::from_secs(1));
+
+        // Stop reporting after a while to let the watchdog bite
+        let hang = Timeout::new(Duration::from_secs(5));
+
+        loop {
+            if blinking.poll() {
+                led.toggle().ok();
+                supervisor.check_in(blink);
+            }
+
+            if reporting.poll() && !hang.expired() {
+                writeln!(tx, "Alive\r").ok();
+                supervisor.check_in(report);
+            }
+
+            if let Err(starved) = supervisor.service() {
+                if starved.task == report.index() {
+                    led.set_high().ok();
+                }
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
@@ -0,0 +1,80 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{dma::Channels, ws2812::Ws2812};
+
+use crate::hal::{
+    delay::Delay,
+    prelude::*,
+    spi::Spi,
+    spi::{Mode, Phase, Polarity},
+    stm32,
+};
+
+use cortex_m::peripheral::Peripherals;
+use cortex_m_rt::entry;
+
+use smart_leds::{
+    brightness,
+    hsv::{hsv2rgb, Hsv},
+    SmartLedsWrite,
+};
+
+pub const MODE: Mode = Mode {
+    polarity: Polarity::IdleLow,
+    phase: Phase::CaptureOnFirstTransition,
+};
+
+/// Number of LEDs on the strip
+const LEDS: usize = 60;
+
+#[entry]
+fn main() -> ! {
+    if let (Some(mut p), Some(cp)) = (stm32::Peripherals::take(), Peripherals::take()) {
+        let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+        let gpioa = p.GPIOA.split(&mut rcc);
+        let mut delay = Delay::new(cp.SYST, &rcc);
+
+        // The data input of the strip is connected to A6, the other pins are unused
+        let (sck, miso, mosi) = cortex_m::interrupt::free(|cs| {
+            (
+                gpioa.pa5.into_alternate_af0(cs),
+                gpioa.pa6.into_alternate_af0(cs),
+                gpioa.pa7.into_alternate_af0(cs),
+            )
+        });
+
+        // 3 MHz are needed for the bit timing
+        let spi = Spi::spi1(p.SPI1, (sck, miso, mosi), MODE, 3.mhz(), &mut rcc);
+        let dma = Channels::new(p.DMA1, &mut rcc);
+
+        // Four LEDs per half of the buffer
+        let buffer = cortex_m::singleton!(: [[u8; 36]; 2] = [[0; 36]; 2]).unwrap();
+        let mut strip = Ws2812::new(spi, dma.ch3, buffer);
+
+        // Let a rainbow run along the strip
+        let mut offset = 0_u8;
+        loop {
+            let colors = (0..LEDS).map(|i| {
+                hsv2rgb(Hsv {
+                    hue: offset.wrapping_add((i * 256 / LEDS) as u8),
+                    sat: 255,
+                    val: 255,
+                })
+            });
+            strip.write(brightness(colors, 32)).ok();
+
+            offset = offset.wrapping_add(1);
+            delay.delay_ms(20_u16);
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
@@ -0,0 +1,102 @@
+#![no_main]
+#![no_std]
+
+#[allow(unused)]
+use panic_halt;
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    time::{Duration, Monotonic, Periodic, Timeout},
+    wwdg::{self, Wwdg},
+};
+
+use crate::hal::{
+    prelude::*,
+    serial::Serial,
+    stm32::{self, interrupt},
+};
+
+use cortex_m_rt::entry;
+
+use core::fmt::Write;
+
+// Checkpoints passed by the main loop
+const FEEDING: u32 = 1;
+const STUCK: u32 = 2;
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+        let gpioa = p.GPIOA.split(&mut rcc);
+
+        let (tx, rx) = cortex_m::interrupt::free(|cs| {
+            (
+                // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+                gpioa.pa2.into_alternate_af1(cs),
+                gpioa.pa15.into_alternate_af1(cs),
+            )
+        });
+
+        let (mut tx, _) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+        // The clock times the refreshes and adds the uptime to the diagnostics
+        Monotonic::tim2(p.TIM2, &mut rcc);
+
+        // Expect a refresh every 20 to 40 ms
+        let mut wwdg = Wwdg::new(p.WWDG, &mut p.DBGMCU, 20_000, 40_000, &mut rcc).unwrap();
+
+        if let Some(diagnostics) = wwdg.diagnostics_before_reset() {
+            writeln!(
+                tx,
+                "\r\nReset at checkpoint {} after {} ms\r",
+                diagnostics.checkpoint,
+                diagnostics.uptime.as_millis()
+            )
+            .ok();
+        }
+
+        let timing = wwdg.timing();
+        writeln!(
+            tx,
+            "\r\nRefresh between {} and {} us\r",
+            timing.min_us(),
+            timing.max_us()
+        )
+        .ok();
+
+        let mut refresh = Periodic::new(Duration::from_millis(30));
+        let hang = Timeout::new(Duration::from_secs(5));
+
+        loop {
+            if hang.expired() {
+                // Get stuck to let the watchdog bite
+                wwdg::checkpoint(STUCK);
+                loop {
+                    continue;
+                }
+            }
+
+            wwdg::checkpoint(FEEDING);
+            if refresh.poll() {
+                wwdg.feed();
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+#[interrupt]
+fn WWDG() {
+    Wwdg::on_interrupt();
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
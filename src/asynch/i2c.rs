@@ -0,0 +1,255 @@
+//! Interrupt driven `async` I2C1 master
+
+use super::WakerCell;
+
+use crate::hal::{i2c::I2c, stm32::I2C1};
+
+use core::{future::poll_fn, task::Poll};
+
+use embedded_hal_async::i2c::{ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+
+static WAKER: WakerCell = WakerCell::new();
+
+// ISR flags
+const TXIS: u32 = 1 << 1;
+const RXNE: u32 = 1 << 2;
+const NACKF: u32 = 1 << 4;
+const STOPF: u32 = 1 << 5;
+const TC: u32 = 1 << 6;
+const TCR: u32 = 1 << 7;
+const BERR: u32 = 1 << 8;
+const ARLO: u32 = 1 << 9;
+
+// CR1 interrupt enables
+const TXIE: u32 = 1 << 1;
+const RXIE: u32 = 1 << 2;
+const NACKIE: u32 = 1 << 4;
+const STOPIE: u32 = 1 << 5;
+const TCIE: u32 = 1 << 6;
+const ERRIE: u32 = 1 << 7;
+const ALL_IE: u32 = TXIE | RXIE | NACKIE | STOPIE | TCIE | ERRIE;
+
+// CR2 fields
+const RD_WRN: u32 = 1 << 10;
+const START: u32 = 1 << 13;
+const STOP: u32 = 1 << 14;
+const RELOAD: u32 = 1 << 24;
+const NBYTES_SHIFT: u32 = 16;
+const NBYTES_MASK: u32 = 0xff << NBYTES_SHIFT;
+
+/// Errors of the `async` I2C master
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// A start or stop condition was detected at an unexpected position
+    Bus,
+    /// Another master won the arbitration
+    ArbitrationLoss,
+    /// The target did not acknowledge its address or a data byte
+    Nack,
+}
+
+impl embedded_hal_async::i2c::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Bus => ErrorKind::Bus,
+            Error::ArbitrationLoss => ErrorKind::ArbitrationLoss,
+            Error::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+        }
+    }
+}
+
+/// Handle the I2C1 interrupt, to be called from the `I2C1` interrupt handler
+pub fn on_interrupt() {
+    let i2c = unsafe { &(*I2C1::ptr()) };
+    i2c.cr1.modify(|r, w| unsafe { w.bits(r.bits() & !ALL_IE) });
+    WAKER.wake();
+}
+
+/// `async` wrapper around a configured I2C1
+pub struct AsyncI2c<SCLPIN, SDAPIN> {
+    i2c: I2c<I2C1, SCLPIN, SDAPIN>,
+}
+
+impl<SCLPIN, SDAPIN> AsyncI2c<SCLPIN, SDAPIN> {
+    /// Take over a bus set up via [`I2c::i2c1`]
+    ///
+    /// The `I2C1` interrupt is unmasked in the NVIC.
+    pub fn new(i2c: I2c<I2C1, SCLPIN, SDAPIN>) -> Self {
+        cortex_m::peripheral::NVIC::unpend(crate::hal::stm32::Interrupt::I2C1);
+        unsafe {
+            cortex_m::peripheral::NVIC::unmask(crate::hal::stm32::Interrupt::I2C1);
+        }
+
+        Self { i2c }
+    }
+
+    /// Release the bus
+    pub fn release(self) -> I2c<I2C1, SCLPIN, SDAPIN> {
+        cortex_m::peripheral::NVIC::mask(crate::hal::stm32::Interrupt::I2C1);
+        self.i2c
+    }
+
+    /// Wait until any of the `flags` is set, enabling `interrupts` in the meantime
+    async fn wait(&mut self, flags: u32, interrupts: u32) -> Result<(), Error> {
+        let i2c = unsafe { &(*I2C1::ptr()) };
+
+        poll_fn(|cx| {
+            let isr = i2c.isr.read().bits();
+
+            if isr & (BERR | ARLO) != 0 {
+                i2c.icr.write(|w| w.berrcf().set_bit().arlocf().set_bit());
+                return Poll::Ready(Err(if isr & ARLO != 0 {
+                    Error::ArbitrationLoss
+                } else {
+                    Error::Bus
+                }));
+            }
+
+            if isr & NACKF != 0 && flags & NACKF == 0 {
+                return Poll::Ready(Err(Error::Nack));
+            }
+
+            if isr & flags != 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            WAKER.register(cx.waker());
+            i2c.cr1
+                .modify(|r, w| unsafe { w.bits(r.bits() | interrupts | NACKIE | ERRIE) });
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Generate a stop condition after a failed transfer and wait for the bus to be released
+    fn abort(&mut self) {
+        let i2c = unsafe { &(*I2C1::ptr()) };
+
+        // After a NACK the stop condition is generated by the hardware
+        if i2c.isr.read().bits() & NACKF == 0 {
+            i2c.cr2.modify(|r, w| unsafe { w.bits(r.bits() | STOP) });
+        }
+
+        // The stop condition only takes a bit period, so don't bother with the interrupt here
+        for _ in 0..10_000 {
+            if i2c.isr.read().bits() & STOPF != 0 {
+                break;
+            }
+        }
+        i2c.icr.write(|w| w.stopcf().set_bit().nackcf().set_bit());
+    }
+
+    /// Run all operations, merging adjacent operations of the same direction
+    async fn run(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        let i2c = unsafe { &(*I2C1::ptr()) };
+
+        if operations.is_empty() {
+            return Ok(());
+        }
+
+        let mut start = 0;
+        while start < operations.len() {
+            let read = matches!(operations[start], Operation::Read(_));
+            let mut end = start + 1;
+            while end < operations.len() && matches!(operations[end], Operation::Read(_)) == read {
+                end += 1;
+            }
+
+            let segment = &mut operations[start..end];
+            let mut remaining: usize = segment
+                .iter()
+                .map(|op| match op {
+                    Operation::Read(buf) => buf.len(),
+                    Operation::Write(buf) => buf.len(),
+                })
+                .sum();
+
+            // Address the target with a (repeated) start condition
+            let chunk = remaining.min(255);
+            let mut cr2 = u32::from(address) << 1 | (chunk as u32) << NBYTES_SHIFT | START;
+            if read {
+                cr2 |= RD_WRN;
+            }
+            if remaining > 255 {
+                cr2 |= RELOAD;
+            }
+            i2c.cr2.write(|w| unsafe { w.bits(cr2) });
+            let mut left_in_chunk = chunk;
+
+            for op in segment.iter_mut() {
+                match op {
+                    Operation::Read(buf) => {
+                        for byte in buf.iter_mut() {
+                            if left_in_chunk == 0 {
+                                left_in_chunk = self.reload(remaining).await?;
+                            }
+                            self.wait(RXNE, RXIE).await?;
+                            *byte = i2c.rxdr.read().bits() as u8;
+                            left_in_chunk -= 1;
+                            remaining -= 1;
+                        }
+                    }
+                    Operation::Write(buf) => {
+                        for &byte in buf.iter() {
+                            if left_in_chunk == 0 {
+                                left_in_chunk = self.reload(remaining).await?;
+                            }
+                            self.wait(TXIS, TXIE).await?;
+                            i2c.txdr.write(|w| unsafe { w.bits(u32::from(byte)) });
+                            left_in_chunk -= 1;
+                            remaining -= 1;
+                        }
+                    }
+                }
+            }
+
+            // Wait until the last byte has been transferred before the next (re)start or stop
+            self.wait(TC, TCIE).await?;
+
+            start = end;
+        }
+
+        i2c.cr2.modify(|r, w| unsafe { w.bits(r.bits() | STOP) });
+        self.wait(STOPF, STOPIE).await?;
+        i2c.icr.write(|w| w.stopcf().set_bit());
+
+        Ok(())
+    }
+
+    /// Continue a transfer of more than 255 bytes with the next chunk
+    async fn reload(&mut self, remaining: usize) -> Result<usize, Error> {
+        let i2c = unsafe { &(*I2C1::ptr()) };
+
+        self.wait(TCR, TCIE).await?;
+
+        let chunk = remaining.min(255);
+        i2c.cr2.modify(|r, w| {
+            let mut bits = r.bits() & !(NBYTES_MASK | RELOAD | START);
+            bits |= (chunk as u32) << NBYTES_SHIFT;
+            if remaining > 255 {
+                bits |= RELOAD;
+            }
+            unsafe { w.bits(bits) }
+        });
+
+        Ok(chunk)
+    }
+}
+
+impl<SCLPIN, SDAPIN> ErrorType for AsyncI2c<SCLPIN, SDAPIN> {
+    type Error = Error;
+}
+
+impl<SCLPIN, SDAPIN> embedded_hal_async::i2c::I2c for AsyncI2c<SCLPIN, SDAPIN> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let res = self.run(address, operations).await;
+        if res.is_err() {
+            self.abort();
+        }
+        res
+    }
+}
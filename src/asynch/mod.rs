@@ -0,0 +1,82 @@
+//! Support for `async` firmware
+//!
+//! This module provides an `embassy-time` driver based on TIM2 and interrupt driven `async`
+//! wrappers for USART2, I2C1 and SPI1 implementing the `embedded-io-async` and
+//! `embedded-hal-async` traits. Like the rest of this crate it does not claim any interrupt
+//! handlers itself, the firmware has to forward the interrupts:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn TIM2() {
+//!     nucleo_f042k6::asynch::time_driver::on_interrupt();
+//! }
+//!
+//! #[interrupt]
+//! fn USART2() {
+//!     nucleo_f042k6::asynch::serial::on_interrupt();
+//! }
+//! ```
+//!
+//! The executor and the `embassy` utilities need a `critical-section` implementation. Firmware
+//! which doesn't get one from elsewhere can enable the `critical-section-single-core` feature to
+//! use the one in this module.
+
+pub mod i2c;
+pub mod serial;
+pub mod spi;
+pub mod time_driver;
+
+use core::{cell::RefCell, task::Waker};
+
+use cortex_m::interrupt::Mutex;
+
+#[cfg(feature = "critical-section-single-core")]
+struct SingleCoreCriticalSection;
+#[cfg(feature = "critical-section-single-core")]
+critical_section::set_impl!(SingleCoreCriticalSection);
+
+#[cfg(feature = "critical-section-single-core")]
+unsafe impl critical_section::Impl for SingleCoreCriticalSection {
+    unsafe fn acquire() -> bool {
+        let was_active = cortex_m::register::primask::read().is_active();
+        cortex_m::interrupt::disable();
+        was_active
+    }
+
+    unsafe fn release(was_active: bool) {
+        if was_active {
+            cortex_m::interrupt::enable();
+        }
+    }
+}
+
+/// Storage for the waker of a task waiting on an interrupt
+pub(crate) struct WakerCell {
+    waker: Mutex<RefCell<Option<Waker>>>,
+}
+
+impl WakerCell {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waker: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Remember `waker` to be woken by the next [`WakerCell::wake`]
+    pub(crate) fn register(&self, waker: &Waker) {
+        cortex_m::interrupt::free(|cs| {
+            let mut stored = self.waker.borrow(cs).borrow_mut();
+            match *stored {
+                Some(ref w) if w.will_wake(waker) => {}
+                _ => *stored = Some(waker.clone()),
+            }
+        });
+    }
+
+    /// Wake the registered task, if any
+    pub(crate) fn wake(&self) {
+        if let Some(waker) = cortex_m::interrupt::free(|cs| self.waker.borrow(cs).take()) {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Interrupt driven `async` USART2, i.e. the virtual COM port of the ST-Link
+
+use super::WakerCell;
+
+use crate::hal::{serial::Serial, stm32::USART2};
+
+use core::{future::poll_fn, task::Poll};
+
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+static RX_WAKER: WakerCell = WakerCell::new();
+static TX_WAKER: WakerCell = WakerCell::new();
+
+/// Receive errors
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    Framing,
+    Noise,
+    Overrun,
+    Parity,
+}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            // embedded-io has no kind for lost data, it's not a lack of memory either
+            Error::Overrun => ErrorKind::Other,
+            _ => ErrorKind::InvalidData,
+        }
+    }
+}
+
+/// Handle the USART2 interrupt, to be called from the `USART2` interrupt handler
+pub fn on_interrupt() {
+    let usart = unsafe { &(*USART2::ptr()) };
+    let isr = usart.isr.read();
+    let cr1 = usart.cr1.read();
+
+    if cr1.rxneie().bit_is_set() && (isr.rxne().bit_is_set() || isr.ore().bit_is_set()) {
+        usart.cr1.modify(|_, w| w.rxneie().clear_bit());
+        RX_WAKER.wake();
+    }
+
+    if (cr1.txeie().bit_is_set() && isr.txe().bit_is_set())
+        || (cr1.tcie().bit_is_set() && isr.tc().bit_is_set())
+    {
+        usart
+            .cr1
+            .modify(|_, w| w.txeie().clear_bit().tcie().clear_bit());
+        TX_WAKER.wake();
+    }
+}
+
+/// `async` wrapper around a configured USART2
+pub struct AsyncSerial<TXPIN, RXPIN> {
+    serial: Serial<USART2, TXPIN, RXPIN>,
+}
+
+impl<TXPIN, RXPIN> AsyncSerial<TXPIN, RXPIN> {
+    /// Take over a serial port set up via [`Serial::usart2`]
+    ///
+    /// The `USART2` interrupt is unmasked in the NVIC.
+    pub fn new(serial: Serial<USART2, TXPIN, RXPIN>) -> Self {
+        cortex_m::peripheral::NVIC::unpend(crate::hal::stm32::Interrupt::USART2);
+        unsafe {
+            cortex_m::peripheral::NVIC::unmask(crate::hal::stm32::Interrupt::USART2);
+        }
+
+        Self { serial }
+    }
+
+    /// Release the serial port
+    pub fn release(self) -> Serial<USART2, TXPIN, RXPIN> {
+        cortex_m::peripheral::NVIC::mask(crate::hal::stm32::Interrupt::USART2);
+        self.serial
+    }
+
+    /// Read a single byte if available, checking for errors first
+    fn try_read(&mut self) -> Option<Result<u8, Error>> {
+        let usart = unsafe { &(*USART2::ptr()) };
+        let isr = usart.isr.read();
+
+        let error = if isr.pe().bit_is_set() {
+            Some(Error::Parity)
+        } else if isr.fe().bit_is_set() {
+            Some(Error::Framing)
+        } else if isr.nf().bit_is_set() {
+            Some(Error::Noise)
+        } else if isr.ore().bit_is_set() {
+            Some(Error::Overrun)
+        } else {
+            None
+        };
+
+        if let Some(error) = error {
+            usart.icr.write(|w| {
+                w.pecf()
+                    .set_bit()
+                    .fecf()
+                    .set_bit()
+                    .ncf()
+                    .set_bit()
+                    .orecf()
+                    .set_bit()
+            });
+            return Some(Err(error));
+        }
+
+        if isr.rxne().bit_is_set() {
+            Some(Ok(usart.rdr.read().bits() as u8))
+        } else {
+            None
+        }
+    }
+}
+
+impl<TXPIN, RXPIN> ErrorType for AsyncSerial<TXPIN, RXPIN> {
+    type Error = Error;
+}
+
+impl<TXPIN, RXPIN> Read for AsyncSerial<TXPIN, RXPIN> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let first = poll_fn(|cx| {
+            if let Some(res) = self.try_read() {
+                return Poll::Ready(res);
+            }
+
+            let usart = unsafe { &(*USART2::ptr()) };
+            RX_WAKER.register(cx.waker());
+            usart.cr1.modify(|_, w| w.rxneie().set_bit());
+            Poll::Pending
+        })
+        .await?;
+        buf[0] = first;
+
+        // Hand out whatever else has arrived in the meantime without waiting
+        let mut count = 1;
+        while count < buf.len() {
+            match self.try_read() {
+                Some(Ok(byte)) => {
+                    buf[count] = byte;
+                    count += 1;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+impl<TXPIN, RXPIN> Write for AsyncSerial<TXPIN, RXPIN> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let usart = unsafe { &(*USART2::ptr()) };
+
+        for &byte in buf {
+            poll_fn(|cx| {
+                if usart.isr.read().txe().bit_is_set() {
+                    return Poll::Ready(());
+                }
+
+                TX_WAKER.register(cx.waker());
+                usart.cr1.modify(|_, w| w.txeie().set_bit());
+                Poll::Pending
+            })
+            .await;
+
+            usart.tdr.write(|w| unsafe { w.bits(u32::from(byte)) });
+        }
+
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        let usart = unsafe { &(*USART2::ptr()) };
+
+        poll_fn(|cx| {
+            if usart.isr.read().tc().bit_is_set() {
+                return Poll::Ready(());
+            }
+
+            TX_WAKER.register(cx.waker());
+            usart.cr1.modify(|_, w| w.tcie().set_bit());
+            Poll::Pending
+        })
+        .await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Interrupt driven `async` SPI1 bus
+
+use super::WakerCell;
+
+use crate::hal::{
+    spi::{EightBit, Spi},
+    stm32::SPI1,
+};
+
+use core::{future::poll_fn, ptr, task::Poll};
+
+use embedded_hal_async::spi::{ErrorKind, ErrorType, SpiBus};
+
+static WAKER: WakerCell = WakerCell::new();
+
+// SR flags
+const RXNE: u32 = 1 << 0;
+const TXE: u32 = 1 << 1;
+const MODF: u32 = 1 << 5;
+const OVR: u32 = 1 << 6;
+const BSY: u32 = 1 << 7;
+
+// CR1 master selection and SPI enable, both cleared by a mode fault
+const MSTR: u32 = 1 << 2;
+const SPE: u32 = 1 << 6;
+
+// CR2 interrupt enables
+const ERRIE: u32 = 1 << 5;
+const RXNEIE: u32 = 1 << 6;
+const TXEIE: u32 = 1 << 7;
+const ALL_IE: u32 = ERRIE | RXNEIE | TXEIE;
+
+/// Errors of the `async` SPI bus
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// Received data was not read in time
+    Overrun,
+    /// NSS was pulled low by another master
+    ModeFault,
+}
+
+impl embedded_hal_async::spi::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Overrun => ErrorKind::Overrun,
+            Error::ModeFault => ErrorKind::ModeFault,
+        }
+    }
+}
+
+/// Handle the SPI1 interrupt, to be called from the `SPI1` interrupt handler
+pub fn on_interrupt() {
+    let spi = unsafe { &(*SPI1::ptr()) };
+    spi.cr2.modify(|r, w| unsafe { w.bits(r.bits() & !ALL_IE) });
+    WAKER.wake();
+}
+
+/// `async` wrapper around a configured SPI1 using 8-bit frames
+pub struct AsyncSpi<SCKPIN, MISOPIN, MOSIPIN> {
+    spi: Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, EightBit>,
+}
+
+impl<SCKPIN, MISOPIN, MOSIPIN> AsyncSpi<SCKPIN, MISOPIN, MOSIPIN> {
+    /// Take over a bus set up via [`Spi::spi1`]
+    ///
+    /// The `SPI1` interrupt is unmasked in the NVIC.
+    pub fn new(spi: Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, EightBit>) -> Self {
+        cortex_m::peripheral::NVIC::unpend(crate::hal::stm32::Interrupt::SPI1);
+        unsafe {
+            cortex_m::peripheral::NVIC::unmask(crate::hal::stm32::Interrupt::SPI1);
+        }
+
+        Self { spi }
+    }
+
+    /// Release the bus
+    pub fn release(self) -> Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, EightBit> {
+        cortex_m::peripheral::NVIC::mask(crate::hal::stm32::Interrupt::SPI1);
+        self.spi
+    }
+
+    /// Wait until `flag` is set, enabling `interrupt` in the meantime
+    async fn wait(&mut self, flag: u32, interrupt: u32) -> Result<(), Error> {
+        let spi = unsafe { &(*SPI1::ptr()) };
+
+        poll_fn(|cx| {
+            let sr = spi.sr.read().bits();
+
+            if sr & OVR != 0 {
+                // Cleared by reading DR followed by SR
+                let _ = spi.dr.read();
+                let _ = spi.sr.read();
+                return Poll::Ready(Err(Error::Overrun));
+            }
+
+            if sr & MODF != 0 {
+                // Cleared by reading SR followed by writing CR1, which re-enables the master
+                spi.cr1
+                    .modify(|r, w| unsafe { w.bits(r.bits() | MSTR | SPE) });
+                return Poll::Ready(Err(Error::ModeFault));
+            }
+
+            if sr & flag != 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            WAKER.register(cx.waker());
+            spi.cr2
+                .modify(|r, w| unsafe { w.bits(r.bits() | interrupt | ERRIE) });
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Exchange a single byte
+    async fn exchange(&mut self, byte: u8) -> Result<u8, Error> {
+        let spi = unsafe { &(*SPI1::ptr()) };
+
+        self.wait(TXE, TXEIE).await?;
+        // Byte access to DR so only a single frame is put into the FIFO
+        unsafe { ptr::write_volatile(&spi.dr as *const _ as *mut u8, byte) };
+
+        self.wait(RXNE, RXNEIE).await?;
+        Ok(unsafe { ptr::read_volatile(&spi.dr as *const _ as *const u8) })
+    }
+}
+
+impl<SCKPIN, MISOPIN, MOSIPIN> ErrorType for AsyncSpi<SCKPIN, MISOPIN, MOSIPIN> {
+    type Error = Error;
+}
+
+impl<SCKPIN, MISOPIN, MOSIPIN> SpiBus<u8> for AsyncSpi<SCKPIN, MISOPIN, MOSIPIN> {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        for word in words.iter_mut() {
+            *word = self.exchange(0).await?;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        for &word in words {
+            self.exchange(word).await?;
+        }
+        Ok(())
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        for i in 0..read.len().max(write.len()) {
+            let byte = self.exchange(write.get(i).copied().unwrap_or(0)).await?;
+            if let Some(r) = read.get_mut(i) {
+                *r = byte;
+            }
+        }
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        for word in words.iter_mut() {
+            *word = self.exchange(*word).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        let spi = unsafe { &(*SPI1::ptr()) };
+
+        // Every byte has been received already, so the bus will be idle momentarily
+        while spi.sr.read().bits() & BSY != 0 {}
+        Ok(())
+    }
+}
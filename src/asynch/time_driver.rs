@@ -0,0 +1,112 @@
+//! `embassy-time` driver based on [`Monotonic`]
+//!
+//! The driver counts at 1 MHz, matching the default tick rate of `embassy-time`. Timer queue
+//! deadlines are signalled with capture/compare channel 1 of TIM2.
+
+use crate::time::Monotonic;
+
+use crate::hal::stm32::TIM2;
+
+use core::{cell::RefCell, task::Waker};
+
+use cortex_m::interrupt::Mutex;
+use embassy_time_driver::Driver;
+use embassy_time_queue_utils::Queue;
+
+/// Capture/compare 1 interrupt flag and enable bit
+const CC1: u32 = 1 << 1;
+
+struct TimeDriver {
+    clock: Mutex<RefCell<Option<Monotonic>>>,
+    queue: Mutex<RefCell<Queue>>,
+}
+
+embassy_time_driver::time_driver_impl!(static DRIVER: TimeDriver = TimeDriver {
+    clock: Mutex::new(RefCell::new(None)),
+    queue: Mutex::new(RefCell::new(Queue::new())),
+});
+
+/// Hand the monotonic clock over to the `embassy-time` driver
+///
+/// Until this is called time stands still for `embassy-time`.
+pub fn init(clock: Monotonic) {
+    cortex_m::interrupt::free(|cs| {
+        *DRIVER.clock.borrow(cs).borrow_mut() = Some(clock);
+    });
+}
+
+/// Handle the TIM2 interrupt, to be called from the `TIM2` interrupt handler
+///
+/// This also takes care of [`Monotonic::on_interrupt`].
+pub fn on_interrupt() {
+    let tim = unsafe { &(*TIM2::ptr()) };
+
+    Monotonic::on_interrupt();
+
+    // Write ones to all other flags so a concurrently set one isn't lost
+    tim.sr.write(|w| unsafe { w.bits(!CC1) });
+
+    DRIVER.dispatch();
+}
+
+impl TimeDriver {
+    /// Wake all expired tasks and arm the alarm for the next deadline
+    fn dispatch(&self) {
+        cortex_m::interrupt::free(|cs| {
+            let mut queue = self.queue.borrow(cs).borrow_mut();
+            let mut next = queue.next_expiration(self.now());
+            while !self.set_alarm(next) {
+                next = queue.next_expiration(self.now());
+            }
+        });
+    }
+
+    /// Arm the compare channel for `at`, returns `false` if that time has already passed
+    ///
+    /// The compare only covers the lower 32 bits, so deadlines beyond the current counter period
+    /// are left to be armed by the overflow interrupt.
+    fn set_alarm(&self, at: u64) -> bool {
+        let tim = unsafe { &(*TIM2::ptr()) };
+        let disarm = || tim.dier.modify(|r, w| unsafe { w.bits(r.bits() & !CC1) });
+
+        if at == u64::MAX {
+            disarm();
+            return true;
+        }
+
+        let now = self.now();
+        if at <= now {
+            disarm();
+            return false;
+        }
+
+        tim.ccr1.write(|w| unsafe { w.bits(at as u32) });
+        if at >> 32 == now >> 32 {
+            tim.sr.write(|w| unsafe { w.bits(!CC1) });
+            tim.dier.modify(|r, w| unsafe { w.bits(r.bits() | CC1) });
+        } else {
+            disarm();
+        }
+
+        // The deadline might have passed while arming the compare
+        self.now() < at
+    }
+}
+
+impl Driver for TimeDriver {
+    fn now(&self) -> u64 {
+        Monotonic::now().as_micros()
+    }
+
+    fn schedule_wake(&self, at: u64, waker: &Waker) {
+        cortex_m::interrupt::free(|cs| {
+            let mut queue = self.queue.borrow(cs).borrow_mut();
+            if queue.schedule_wake(at, waker) {
+                let mut next = queue.next_expiration(self.now());
+                while !self.set_alarm(next) {
+                    next = queue.next_expiration(self.now());
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,579 @@
+//! Production self-test of boards assembled with the STM32F042
+//!
+//! Runs all tests once after reset and reports them over USART2, which is connected to the
+//! ST-Link virtual COM port, at 115200 baud. Every line is terminated by `\r\n`:
+//!
+//! ```text
+//! SELFTEST START board=nucleo-f042k6 version=0.9.0 uid=<24 hex digits>
+//! <ITEM> PASS|FAIL [<key>=<value> ...]
+//! ...
+//! SELFTEST END PASS|FAIL passed=<count> failed=<count>
+//! ```
+//!
+//! The items are `LED`, `VCP`, `VREFINT`, `TEMP`, `FLASH`, `CRC` and `I2C`, in this order. For
+//! the `VCP` loopback the board sends `ECHO <8 hex digits>` and expects the test station to send
+//! back the 8 digits within one second. A panic is reported as `SELFTEST END FAIL reason=panic`.
+//!
+//! Afterwards the LED on D13 is lit if all tests passed and blinks quickly otherwise.
+//!
+//! The `FLASH` test erases and programs the last page of the flash, which must not be part of
+//! the image. `I2C` expects external pull-ups on D8 (SCL) and D7 (SDA) and the devices in
+//! [`I2C_EXPECTED`] to answer.
+
+#![no_main]
+#![no_std]
+
+use stm32f0xx_hal as hal;
+
+use nucleo_f042k6::{
+    i2c_tools::{self, BoardI2c, BusState},
+    signature,
+    time::{Duration, Monotonic, Timeout},
+};
+
+use crate::hal::{
+    adc::{Adc, AdcSampleTime, VRef, VTemp},
+    gpio::{gpiob::PB3, Output, PushPull},
+    i2c::I2c,
+    prelude::*,
+    serial::{Rx, Serial, Tx},
+    stm32::{self, interrupt, CRC, FLASH, GPIOB, RCC, USART2},
+};
+
+use cortex_m_rt::entry;
+
+use core::{
+    fmt::{self, Write},
+    panic::PanicInfo,
+    ptr,
+};
+
+/// I2C addresses which have to answer, to be adjusted to the devices assembled
+const I2C_EXPECTED: &[u8] = &[];
+
+// Factory calibration
+const TS_CAL1: *const u16 = 0x1fff_f7b8 as *const u16;
+const VREFINT_CAL: *const u16 = 0x1fff_f7ba as *const u16;
+const TS_CAL2: *const u16 = 0x1fff_f7c2 as *const u16;
+
+// Supply the calibration values were taken at and the range accepted for VDDA in mV
+const VDDA_CAL_MV: u32 = 3300;
+const VDDA_MIN_MV: u32 = 3135;
+const VDDA_MAX_MV: u32 = 3465;
+
+// Range of VREFINT_CAL for 1.16 V to 1.24 V at 3.3 V, with some margin
+const VREFINT_CAL_MIN: u16 = 1400;
+const VREFINT_CAL_MAX: u16 = 1600;
+
+// Ambient temperature range in °C accepted on the production floor
+const TEMP_MIN_C: i32 = 10;
+const TEMP_MAX_C: i32 = 50;
+
+// Number of ADC samples averaged per measurement
+const SAMPLES: u32 = 16;
+
+// The LED is on PB3
+const LED_MASK: u32 = 1 << 3;
+
+// Flash keys, page size and register bits
+const FLASH_BASE: u32 = 0x0800_0000;
+const PAGE_SIZE: u32 = 1024;
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+const PG: u32 = 1 << 0;
+const PER: u32 = 1 << 1;
+const STRT: u32 = 1 << 6;
+const LOCK: u32 = 1 << 7;
+const BSY: u32 = 1 << 0;
+const PGERR: u32 = 1 << 2;
+const WRPRTERR: u32 = 1 << 4;
+const EOP: u32 = 1 << 5;
+
+// CRC clock enable and reset, the check value is the one of CRC-32/MPEG-2 for "123456789"
+const CRCEN: u32 = 1 << 6;
+const CRC_RESET: u32 = 1 << 0;
+const CRC_CHECK: u32 = 0x0376_e6e7;
+
+// Start and end of the initialised data in flash, provided by cortex-m-rt
+extern "C" {
+    static __sidata: u32;
+    static __sdata: u32;
+    static __edata: u32;
+}
+
+/// Everything the tests need
+struct Board {
+    tx: Tx<USART2>,
+    rx: Rx<USART2>,
+    led: PB3<Output<PushPull>>,
+    adc: Adc,
+    vref: VRef,
+    vtemp: VTemp,
+    i2c: BoardI2c,
+    flash: FLASH,
+    crc: CRC,
+}
+
+/// `key=value` pairs following the result of a test, cut off if they don't fit
+struct Details {
+    buffer: [u8; 64],
+    len: usize,
+}
+
+impl Details {
+    fn new() -> Self {
+        Self {
+            buffer: [0; 64],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for Details {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let free = self.buffer.len() - self.len;
+        let count = s.len().min(free);
+        self.buffer[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
+/// A test writing its details and returning whether it passed
+type Test = fn(&mut Board, &mut Details) -> bool;
+
+const TESTS: [(&str, Test); 7] = [
+    ("LED", led),
+    ("VCP", vcp),
+    ("VREFINT", vrefint),
+    ("TEMP", temperature),
+    ("FLASH", flash),
+    ("CRC", crc),
+    ("I2C", i2c),
+];
+
+fn wait(duration: Duration) {
+    let timeout = Timeout::new(duration);
+    while !timeout.expired() {}
+}
+
+/// Drive the LED and read the level back, blinking it for the operator
+fn led(board: &mut Board, details: &mut Details) -> bool {
+    let gpiob = unsafe { &(*GPIOB::ptr()) };
+
+    for _ in 0..3 {
+        board.led.set_high().ok();
+        wait(Duration::from_millis(100));
+        if gpiob.idr.read().bits() & LED_MASK == 0 {
+            write!(details, "reason=stuck_low").ok();
+            return false;
+        }
+
+        board.led.set_low().ok();
+        wait(Duration::from_millis(100));
+        if gpiob.idr.read().bits() & LED_MASK != 0 {
+            write!(details, "reason=stuck_high").ok();
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Send a token which the test station has to echo
+fn vcp(board: &mut Board, details: &mut Details) -> bool {
+    let token = Monotonic::now().as_micros() as u32 ^ signature::uid()[0];
+    let mut expected = Details::new();
+    write!(expected, "{:08x}", token).ok();
+
+    // Discard anything received before
+    let flush = Timeout::new(Duration::from_millis(10));
+    while !flush.expired() {
+        board.rx.read().ok();
+    }
+
+    write!(board.tx, "ECHO {}\r\n", expected.as_str()).ok();
+
+    let mut received = 0;
+    let timeout = Timeout::new(Duration::from_secs(1));
+    while received < expected.len {
+        if timeout.expired() {
+            write!(details, "reason=timeout received={}", received).ok();
+            return false;
+        }
+
+        match board.rx.read() {
+            Ok(b'\r') | Ok(b'\n') | Ok(b' ') if received == 0 => {}
+            Ok(byte) if byte == expected.buffer[received] => received += 1,
+            Ok(_) => {
+                write!(details, "reason=mismatch received={}", received).ok();
+                return false;
+            }
+            Err(_) => {}
+        }
+    }
+
+    true
+}
+
+/// Average a number of conversions of VREFINT
+fn read_vref(board: &mut Board) -> Option<u32> {
+    let mut sum = 0;
+    for _ in 0..SAMPLES {
+        let sample: u16 = board.adc.read(&mut board.vref).ok()?;
+        sum += u32::from(sample);
+    }
+    Some(sum / SAMPLES)
+}
+
+/// VDDA in mV from VREFINT and its calibration value
+fn vdda_mv(board: &mut Board) -> Option<u32> {
+    let cal = unsafe { ptr::read_volatile(VREFINT_CAL) };
+    let raw = read_vref(board)?;
+    Some(VDDA_CAL_MV * u32::from(cal) / raw.max(1))
+}
+
+/// Check the calibration value of VREFINT and the supply derived from it
+fn vrefint(board: &mut Board, details: &mut Details) -> bool {
+    let cal = unsafe { ptr::read_volatile(VREFINT_CAL) };
+    if !(VREFINT_CAL_MIN..=VREFINT_CAL_MAX).contains(&cal) {
+        write!(details, "reason=calibration cal={}", cal).ok();
+        return false;
+    }
+
+    let vdda = match vdda_mv(board) {
+        Some(vdda) => vdda,
+        None => {
+            write!(details, "reason=adc").ok();
+            return false;
+        }
+    };
+    write!(details, "vdda_mv={} cal={}", vdda, cal).ok();
+
+    (VDDA_MIN_MV..=VDDA_MAX_MV).contains(&vdda)
+}
+
+/// Check the temperature sensor against its two calibration points
+fn temperature(board: &mut Board, details: &mut Details) -> bool {
+    let cal30 = i32::from(unsafe { ptr::read_volatile(TS_CAL1) });
+    let cal110 = i32::from(unsafe { ptr::read_volatile(TS_CAL2) });
+    // The sensor voltage falls with rising temperature, so the 110 °C value is the lower one
+    let erased = |cal| cal == 0 || cal == 0xffff;
+    if cal110 >= cal30 || erased(cal30) || erased(cal110) {
+        write!(
+            details,
+            "reason=calibration cal30={} cal110={}",
+            cal30, cal110
+        )
+        .ok();
+        return false;
+    }
+
+    let mut sum = 0;
+    for _ in 0..SAMPLES {
+        let sample: Result<u16, _> = board.adc.read(&mut board.vtemp);
+        match sample {
+            Ok(sample) => sum += u32::from(sample),
+            Err(_) => {
+                write!(details, "reason=adc").ok();
+                return false;
+            }
+        }
+    }
+    let vdda = match vdda_mv(board) {
+        Some(vdda) => vdda,
+        None => {
+            write!(details, "reason=adc").ok();
+            return false;
+        }
+    };
+
+    // The calibration values were taken at 3.3 V
+    let raw = (sum / SAMPLES * vdda / VDDA_CAL_MV) as i32;
+    let temp = 30 + (raw - cal30) * (110 - 30) / (cal110 - cal30);
+    write!(details, "temp_c={} raw={}", temp, raw).ok();
+
+    (TEMP_MIN_C..=TEMP_MAX_C).contains(&temp)
+}
+
+/// Wait for a flash operation to finish and clear its flags
+fn flash_wait(flash: &FLASH) -> Result<(), &'static str> {
+    while flash.sr.read().bits() & BSY != 0 {}
+
+    let sr = flash.sr.read().bits();
+    flash
+        .sr
+        .write(|w| unsafe { w.bits(EOP | PGERR | WRPRTERR) });
+
+    if sr & WRPRTERR != 0 {
+        Err("write_protected")
+    } else if sr & PGERR != 0 {
+        Err("program_error")
+    } else {
+        Ok(())
+    }
+}
+
+fn erase_page(flash: &FLASH, address: u32) -> Result<(), &'static str> {
+    flash.cr.write(|w| unsafe { w.bits(PER) });
+    flash.ar.write(|w| unsafe { w.bits(address) });
+    flash.cr.write(|w| unsafe { w.bits(PER | STRT) });
+    let result = flash_wait(flash);
+    flash.cr.write(|w| unsafe { w.bits(0) });
+    result
+}
+
+/// Test pattern differing between neighbouring half-words and from the erased state
+fn pattern(index: u32) -> u16 {
+    (index as u16).wrapping_mul(0x9e37) ^ 0xa55a
+}
+
+fn half_word(address: u32) -> u16 {
+    unsafe { ptr::read_volatile(address as *const u16) }
+}
+
+fn flash_page(flash: &FLASH, page: u32) -> Result<(), &'static str> {
+    let half_words = PAGE_SIZE / 2;
+
+    erase_page(flash, page)?;
+    if (0..half_words).any(|index| half_word(page + index * 2) != 0xffff) {
+        return Err("erase_verify");
+    }
+
+    flash.cr.write(|w| unsafe { w.bits(PG) });
+    for index in 0..half_words {
+        unsafe { ptr::write_volatile((page + index * 2) as *mut u16, pattern(index)) };
+        if let Err(e) = flash_wait(flash) {
+            flash.cr.write(|w| unsafe { w.bits(0) });
+            return Err(e);
+        }
+    }
+    flash.cr.write(|w| unsafe { w.bits(0) });
+
+    if (0..half_words).any(|index| half_word(page + index * 2) != pattern(index)) {
+        return Err("program_verify");
+    }
+
+    // Leave the page erased
+    erase_page(flash, page)
+}
+
+/// Erase, program and verify the last flash page
+fn flash(board: &mut Board, details: &mut Details) -> bool {
+    let size = u32::from(signature::flash_size_kib()) * 1024;
+    let page = FLASH_BASE + size - PAGE_SIZE;
+    write!(details, "page=0x{:08x}", page).ok();
+
+    let image_end = unsafe {
+        let data = &__edata as *const u32 as u32 - &__sdata as *const u32 as u32;
+        &__sidata as *const u32 as u32 + data
+    };
+    if image_end > page {
+        write!(details, " reason=image_overlaps").ok();
+        return false;
+    }
+
+    let flash = &board.flash;
+    if flash.cr.read().bits() & LOCK != 0 {
+        flash.keyr.write(|w| unsafe { w.bits(KEY1) });
+        flash.keyr.write(|w| unsafe { w.bits(KEY2) });
+    }
+
+    let result = flash_page(flash, page);
+    flash.cr.write(|w| unsafe { w.bits(LOCK) });
+
+    match result {
+        Ok(()) => true,
+        Err(reason) => {
+            write!(details, " reason={}", reason).ok();
+            false
+        }
+    }
+}
+
+/// Compute the check value of CRC-32/MPEG-2, which is the default configuration of the unit
+fn crc(board: &mut Board, details: &mut Details) -> bool {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.ahbenr
+        .modify(|r, w| unsafe { w.bits(r.bits() | CRCEN) });
+
+    let crc = &board.crc;
+    crc.init.write(|w| unsafe { w.bits(0xffff_ffff) });
+    crc.cr.write(|w| unsafe { w.bits(CRC_RESET) });
+
+    // Byte writes feed a single byte into the calculation
+    let dr = &crc.dr as *const _ as *mut u8;
+    for byte in b"123456789" {
+        unsafe { ptr::write_volatile(dr, *byte) };
+    }
+
+    let value = crc.dr.read().bits();
+    write!(details, "crc=0x{:08x}", value).ok();
+
+    value == CRC_CHECK
+}
+
+/// Check the pull-ups of I2C1 and the expected devices
+fn i2c(board: &mut Board, details: &mut Details) -> bool {
+    match i2c_tools::bus_state(&board.i2c) {
+        BusState::Idle => {}
+        BusState::SclStuckLow => {
+            write!(details, "reason=scl_low").ok();
+            return false;
+        }
+        BusState::SdaStuckLow => {
+            write!(details, "reason=sda_low").ok();
+            return false;
+        }
+    }
+
+    let result = match i2c_tools::scan(&mut board.i2c) {
+        Ok(result) => result,
+        Err(e) => {
+            write!(details, "reason={:?}", e).ok();
+            return false;
+        }
+    };
+
+    write!(details, "devices=").ok();
+    for (index, address) in result.devices().enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        write!(details, "{}0x{:02x}", separator, address).ok();
+    }
+
+    let missing = I2C_EXPECTED
+        .iter()
+        .filter(|&&address| !result.is_present(address))
+        .count();
+    if missing > 0 {
+        write!(details, " missing={}", missing).ok();
+    }
+
+    missing == 0
+}
+
+#[entry]
+fn main() -> ! {
+    if let Some(mut p) = stm32::Peripherals::take() {
+        let mut rcc = p.RCC.configure().sysclk(48.mhz()).freeze(&mut p.FLASH);
+        let gpioa = p.GPIOA.split(&mut rcc);
+        let gpiob = p.GPIOB.split(&mut rcc);
+        let gpiof = p.GPIOF.split(&mut rcc);
+
+        let (tx, rx, scl, sda, led) = cortex_m::interrupt::free(|cs| {
+            (
+                // USART2 at PA2 (TX) and PA15(RX) is connectet to ST-Link
+                gpioa.pa2.into_alternate_af1(cs),
+                gpioa.pa15.into_alternate_af1(cs),
+                // No internal pull-ups, so missing external ones are detected
+                gpiof.pf1.into_alternate_af1(cs).set_open_drain(cs),
+                gpiof.pf0.into_alternate_af1(cs).set_open_drain(cs),
+                gpiob.pb3.into_push_pull_output(cs),
+            )
+        });
+
+        let (tx, rx) = Serial::usart2(p.USART2, (tx, rx), 115_200.bps(), &mut rcc).split();
+
+        Monotonic::tim2(p.TIM2, &mut rcc);
+
+        let mut adc = Adc::new(p.ADC, &mut rcc);
+        let mut vref = VRef::new();
+        let mut vtemp = VTemp::new();
+        vref.enable(&mut adc);
+        vtemp.enable(&mut adc);
+
+        // The internal sensors need a long sampling time
+        adc.set_sample_time(AdcSampleTime::T_239);
+
+        let mut board = Board {
+            tx,
+            rx,
+            led,
+            adc,
+            vref,
+            vtemp,
+            i2c: I2c::i2c1(p.I2C1, (scl, sda), 100.khz(), &mut rcc),
+            flash: p.FLASH,
+            crc: p.CRC,
+        };
+
+        let uid = signature::uid();
+        write!(
+            board.tx,
+            "\r\nSELFTEST START board={} version={} uid={:08x}{:08x}{:08x}\r\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            uid[2],
+            uid[1],
+            uid[0]
+        )
+        .ok();
+
+        let mut failed = 0;
+        for (name, test) in TESTS.iter() {
+            let mut details = Details::new();
+            let pass = test(&mut board, &mut details);
+            if !pass {
+                failed += 1;
+            }
+
+            let result = if pass { "PASS" } else { "FAIL" };
+            if details.len == 0 {
+                write!(board.tx, "{} {}\r\n", name, result).ok();
+            } else {
+                write!(board.tx, "{} {} {}\r\n", name, result, details.as_str()).ok();
+            }
+        }
+
+        write!(
+            board.tx,
+            "SELFTEST END {} passed={} failed={}\r\n",
+            if failed == 0 { "PASS" } else { "FAIL" },
+            TESTS.len() - failed,
+            failed
+        )
+        .ok();
+
+        // Show the result to the operator
+        if failed == 0 {
+            board.led.set_high().ok();
+        } else {
+            loop {
+                board.led.set_high().ok();
+                wait(Duration::from_millis(100));
+                board.led.set_low().ok();
+                wait(Duration::from_millis(100));
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
+
+// Extend the 32-bit counter of TIM2 on overflow
+#[interrupt]
+fn TIM2() {
+    Monotonic::on_interrupt();
+}
+
+/// Report the panic as failure of the whole test
+#[panic_handler]
+fn panic(_: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    let usart = unsafe { &(*USART2::ptr()) };
+    for byte in b"\r\nSELFTEST END FAIL reason=panic\r\n" {
+        while usart.isr.read().txe().bit_is_clear() {}
+        usart.tdr.write(|w| unsafe { w.bits(u32::from(*byte)) });
+    }
+
+    loop {
+        continue;
+    }
+}
@@ -0,0 +1,407 @@
+//! Frequency, period and duty cycle measurement with input capture
+//!
+//! TIM2 or TIM3 is run in PWM input mode on channel 1 or 2: the rising edges of the signal reset
+//! the counter and capture the period, the falling edges capture the high time. Counter overflows
+//! are counted in the interrupt handler, extending the 16 resp. 32-bit captures to 64 bits, so
+//! slow signals can be measured with the full resolution of the timer clock.
+//!
+//! Each completed period is posted to a mailbox which can be read without blocking, a measurement
+//! not picked up before the next one arrives is replaced. The timer interrupt has to call
+//! [`on_interrupt`]:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn TIM3() {
+//!     capture::on_interrupt::<TIM3>();
+//! }
+//! ```
+//!
+//! A signal with a duty cycle of 0 or 100% doesn't produce any captures and therefore no
+//! measurements. Since the interrupt handler runs twice per period, signals of up to about 100 kHz
+//! can be measured. TIM2 is also used by [`crate::time::Monotonic`], so use TIM3 if both are
+//! needed.
+
+use crate::hal::{
+    rcc::Rcc,
+    stm32::{Interrupt, RCC, TIM2, TIM3},
+};
+
+use crate::pwm::{
+    read, write, Channel, ChannelNumber, ChannelPin, Instance, ARR, CCER, CCMR1, CCR1, CR1, DIER,
+    EGR, PSC, SMCR, SR,
+};
+use crate::time::Duration;
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+
+// Offset of CCR2, the others are shared with `pwm`
+const CCR2: usize = 0x38;
+
+// CR1, EGR, DIER and SR bits
+const CEN: u32 = 1 << 0;
+const URS: u32 = 1 << 2;
+const UG: u32 = 1 << 0;
+const UIF: u32 = 1 << 0;
+const CC1IF: u32 = 1 << 1;
+const CC2IF: u32 = 1 << 2;
+
+// CCMR1 input selection, CC1S in bits 0-1 and CC2S in bits 8-9
+const CC1S_TI1: u32 = 0b01;
+const CC1S_TI2: u32 = 0b10;
+const CC2S_TI2: u32 = 0b01 << 8;
+const CC2S_TI1: u32 = 0b10 << 8;
+
+// CCER enables and polarities
+const CC1E: u32 = 1 << 0;
+const CC1P: u32 = 1 << 1;
+const CC2E: u32 = 1 << 4;
+const CC2P: u32 = 1 << 5;
+
+// SMCR reset mode triggered by TI1FP1 or TI2FP2
+const SMS_RESET: u32 = 0b100;
+const TS_TI1FP1: u32 = 0b101 << 4;
+const TS_TI2FP2: u32 = 0b110 << 4;
+
+/// A timer which can capture with overflow extension
+pub trait CaptureTimer: Instance {
+    #[doc(hidden)]
+    const INTERRUPT: Interrupt;
+    #[doc(hidden)]
+    const MAX_COUNT: u32;
+    #[doc(hidden)]
+    fn state() -> &'static Mutex<RefCell<State>>;
+}
+
+static TIM2_STATE: Mutex<RefCell<State>> = Mutex::new(RefCell::new(State::new()));
+static TIM3_STATE: Mutex<RefCell<State>> = Mutex::new(RefCell::new(State::new()));
+
+impl CaptureTimer for TIM2 {
+    const INTERRUPT: Interrupt = Interrupt::TIM2;
+    const MAX_COUNT: u32 = 0xffff_ffff;
+
+    fn state() -> &'static Mutex<RefCell<State>> {
+        &TIM2_STATE
+    }
+}
+
+impl CaptureTimer for TIM3 {
+    const INTERRUPT: Interrupt = Interrupt::TIM3;
+    const MAX_COUNT: u32 = 0xffff;
+
+    fn state() -> &'static Mutex<RefCell<State>> {
+        &TIM3_STATE
+    }
+}
+
+/// A channel whose input can drive PWM input mode, i.e. channel 1 or 2
+pub trait InputChannel: ChannelNumber {}
+
+impl<TIM> InputChannel for Channel<TIM, 1> {}
+impl<TIM> InputChannel for Channel<TIM, 2> {}
+
+/// One period of the measured signal
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Measurement {
+    /// Length of the period in timer ticks
+    pub period: u64,
+    /// Time the signal was high in timer ticks
+    pub high: u64,
+    /// Frequency of the timer ticks
+    pub tick_hz: u32,
+}
+
+impl Measurement {
+    /// Frequency of the signal in mHz
+    pub fn millihertz(&self) -> u64 {
+        u64::from(self.tick_hz) * 1000 / self.period.max(1)
+    }
+
+    /// Frequency of the signal in Hz, rounded
+    pub fn hertz(&self) -> u32 {
+        ((u64::from(self.tick_hz) + self.period / 2) / self.period.max(1)) as u32
+    }
+
+    /// Length of the period in ns
+    pub fn period_ns(&self) -> u64 {
+        ticks_to_ns(self.period, self.tick_hz)
+    }
+
+    /// Length of the period
+    pub fn period_duration(&self) -> Duration {
+        Duration::from_micros(self.period_ns() / 1000)
+    }
+
+    /// Time the signal was high in ns
+    pub fn high_ns(&self) -> u64 {
+        ticks_to_ns(self.high, self.tick_hz)
+    }
+
+    /// Duty cycle in per mille
+    pub fn duty_per_mille(&self) -> u16 {
+        (self.high.min(self.period) * 1000 / self.period.max(1)) as u16
+    }
+}
+
+fn ticks_to_ns(ticks: u64, tick_hz: u32) -> u64 {
+    // Split up to avoid overflowing for periods of several hours
+    let tick_hz = u64::from(tick_hz.max(1));
+    ticks / tick_hz * 1_000_000_000 + ticks % tick_hz * 1_000_000_000 / tick_hz
+}
+
+/// Overflow extension and mailbox of a capture timer
+#[doc(hidden)]
+pub struct State {
+    overflows: u64,
+    high: u64,
+    synchronised: bool,
+    latest: Option<Measurement>,
+    missed: u32,
+    period_flag: u32,
+    high_flag: u32,
+    tick_hz: u32,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            overflows: 0,
+            high: 0,
+            synchronised: false,
+            latest: None,
+            missed: 0,
+            period_flag: CC1IF,
+            high_flag: CC2IF,
+            tick_hz: 0,
+        }
+    }
+}
+
+/// Measures the signal on pin `P`
+pub struct Capture<TIM, P> {
+    tim: TIM,
+    pin: P,
+}
+
+impl<TIM, P> Capture<TIM, P>
+where
+    TIM: CaptureTimer,
+    P: ChannelPin<TIM>,
+    P::Channel: InputChannel,
+{
+    /// Start measuring the signal on `pin`, using both channel 1 and 2 of `tim`
+    ///
+    /// The timer counts at the full timer clock and its interrupt is unmasked in the NVIC.
+    pub fn new(tim: TIM, pin: P, rcc: &mut Rcc) -> Self {
+        let rccr = unsafe { &(*RCC::ptr()) };
+        TIM::enable(rccr);
+
+        let base = TIM::base();
+        let first = P::Channel::NUMBER == 1;
+
+        // The rising edge captures the period and resets the counter, the falling edge captures
+        // the high time
+        let (ccmr1, ccer, smcr, period_flag, high_flag) = if first {
+            (
+                CC1S_TI1 | CC2S_TI1,
+                CC1E | CC2E | CC2P,
+                SMS_RESET | TS_TI1FP1,
+                CC1IF,
+                CC2IF,
+            )
+        } else {
+            (
+                CC2S_TI2 | CC1S_TI2,
+                CC2E | CC1E | CC1P,
+                SMS_RESET | TS_TI2FP2,
+                CC2IF,
+                CC1IF,
+            )
+        };
+
+        write(base, CCMR1, ccmr1);
+        write(base, CCER, ccer);
+        write(base, SMCR, smcr);
+        write(base, PSC, 0);
+        write(base, ARR, TIM::MAX_COUNT);
+
+        // Only counter overflows should set UIF, not the resets by the slave mode controller
+        write(base, CR1, URS);
+        write(base, EGR, UG);
+        write(base, SR, 0);
+
+        cortex_m::interrupt::free(|cs| {
+            *TIM::state().borrow(cs).borrow_mut() = State {
+                period_flag,
+                high_flag,
+                tick_hz: crate::timer_clock(&rcc.clocks),
+                ..State::new()
+            };
+        });
+
+        // The interrupt enables are at the same positions as the flags
+        write(base, DIER, UIF | CC1IF | CC2IF);
+        write(base, CR1, URS | CEN);
+
+        cortex_m::peripheral::NVIC::unpend(TIM::INTERRUPT);
+        unsafe {
+            cortex_m::peripheral::NVIC::unmask(TIM::INTERRUPT);
+        }
+
+        Self { tim, pin }
+    }
+
+    /// Take the latest measurement, if a new one is available
+    pub fn read(&mut self) -> Option<Measurement> {
+        cortex_m::interrupt::free(|cs| TIM::state().borrow(cs).borrow_mut().latest.take())
+    }
+
+    /// Number of measurements replaced before being read
+    pub fn missed(&self) -> u32 {
+        cortex_m::interrupt::free(|cs| TIM::state().borrow(cs).borrow().missed)
+    }
+
+    /// Stop measuring and release the timer and the pin
+    pub fn release(self) -> (TIM, P) {
+        cortex_m::peripheral::NVIC::mask(TIM::INTERRUPT);
+
+        let base = TIM::base();
+        write(base, CR1, 0);
+        write(base, DIER, 0);
+        write(base, SMCR, 0);
+        write(base, CCER, 0);
+
+        let rccr = unsafe { &(*RCC::ptr()) };
+        TIM::disable(rccr);
+
+        (self.tim, self.pin)
+    }
+}
+
+/// Account for overflows and captures, to be called from the interrupt handler of the timer
+pub fn on_interrupt<TIM: CaptureTimer>() {
+    let base = TIM::base();
+
+    cortex_m::interrupt::free(|cs| {
+        let mut state = TIM::state().borrow(cs).borrow_mut();
+        let state = &mut *state;
+
+        let sr = read(base, SR);
+        let (period_ccr, high_ccr) = if state.period_flag == CC1IF {
+            (CCR1, CCR2)
+        } else {
+            (CCR2, CCR1)
+        };
+        let span = u64::from(TIM::MAX_COUNT) + 1;
+        let half = TIM::MAX_COUNT / 2;
+
+        // Reading a CCR clears its flag, the other flags are cleared by writing zeros
+        let mut overflowed = sr & UIF != 0;
+        if overflowed {
+            write(base, SR, !UIF);
+        }
+
+        // The falling edge comes before the rising edge ending the period
+        if sr & state.high_flag != 0 {
+            let ccr = read(base, high_ccr);
+
+            // An overflow pending together with a small capture happened before it
+            let mut overflows = state.overflows;
+            if overflowed && ccr < half {
+                overflows += 1;
+            }
+            state.high = overflows * span + u64::from(ccr);
+        }
+
+        if sr & state.period_flag != 0 {
+            let ccr = read(base, period_ccr);
+
+            if overflowed && ccr < half {
+                state.overflows += 1;
+                overflowed = false;
+            }
+            let period = state.overflows * span + u64::from(ccr);
+
+            // The first capture ends a period which started before the measurement
+            if state.synchronised {
+                if state.latest.is_some() {
+                    state.missed = state.missed.wrapping_add(1);
+                }
+                state.latest = Some(Measurement {
+                    period,
+                    high: state.high,
+                    tick_hz: state.tick_hz,
+                });
+            }
+            state.synchronised = true;
+            state.overflows = 0;
+        }
+
+        if overflowed {
+            state.overflows += 1;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 kHz at 25% duty cycle, measured with a 48 MHz timer clock
+    const KHZ: Measurement = Measurement {
+        period: 48_000,
+        high: 12_000,
+        tick_hz: 48_000_000,
+    };
+
+    #[test]
+    fn frequency() {
+        assert_eq!(KHZ.millihertz(), 1_000_000);
+        assert_eq!(KHZ.hertz(), 1_000);
+
+        let measurement = Measurement {
+            period: 4,
+            high: 0,
+            tick_hz: 10,
+        };
+        assert_eq!(measurement.hertz(), 3);
+        assert_eq!(measurement.millihertz(), 2_500);
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(KHZ.period_ns(), 1_000_000);
+        assert_eq!(KHZ.period_duration(), Duration::from_millis(1));
+        assert_eq!(KHZ.high_ns(), 250_000);
+        assert_eq!(KHZ.duty_per_mille(), 250);
+    }
+
+    #[test]
+    fn long_period_does_not_overflow() {
+        let measurement = Measurement {
+            period: 48_000_000 * 3_600 * 10,
+            high: 0,
+            tick_hz: 48_000_000,
+        };
+        assert_eq!(measurement.period_ns(), 36_000_000_000_000);
+    }
+
+    #[test]
+    fn degenerate_measurements() {
+        let measurement = Measurement {
+            period: 0,
+            high: 5,
+            tick_hz: 1_000,
+        };
+        assert_eq!(measurement.duty_per_mille(), 0);
+        assert_eq!(measurement.hertz(), 1_000);
+
+        let measurement = Measurement {
+            period: 10,
+            high: 20,
+            tick_hz: 1_000,
+        };
+        assert_eq!(measurement.duty_per_mille(), 1_000);
+    }
+}
@@ -0,0 +1,539 @@
+//! Scrolling text console for small displays
+//!
+//! [`Console`] keeps the text in a fixed grid of characters and draws it through the
+//! [`CharDisplay`] trait, which is implemented for the SSD1306 in terminal mode by
+//! `TerminalDisplay` and for any `embedded-graphics` target by `GraphicsDisplay`.
+
+use core::fmt;
+
+/// Most rows of a status bar
+pub const MAX_STATUS_ROWS: usize = 2;
+
+/// A display showing a grid of characters
+pub trait CharDisplay {
+    type Error;
+
+    /// Number of columns and rows
+    fn size(&self) -> (usize, usize);
+
+    /// Draw a whole `row`, `text` is ASCII and as wide as the display
+    fn draw_row(&mut self, row: usize, text: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Where the status bar is shown
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StatusPosition {
+    Top,
+    Bottom,
+}
+
+/// Scrolling text console with scroll-back and an optional status bar
+///
+/// The console keeps the last `LINES` lines of up to `COLS` characters, so it takes up about
+/// `COLS * LINES` bytes of RAM; text wraps at the width of the display or `COLS`, whatever is
+/// smaller. Only ASCII is supported, other characters are shown as `?`.
+///
+/// Writing via [`fmt::Write`] updates the display right away, other changes are shown by
+/// [`Console::flush`]. Only rows which changed are redrawn.
+pub struct Console<D, const COLS: usize, const LINES: usize> {
+    display: D,
+    columns: usize,
+    rows: usize,
+    lines: [[u8; COLS]; LINES],
+    first: usize,
+    count: usize,
+    column: usize,
+    line: usize,
+    scrollback: usize,
+    status: [[u8; COLS]; MAX_STATUS_ROWS],
+    status_rows: usize,
+    status_position: StatusPosition,
+    dirty: u64,
+}
+
+impl<D: CharDisplay, const COLS: usize, const LINES: usize> Console<D, COLS, LINES> {
+    // The lines are a ring buffer indexed modulo `LINES`, so a console without any fails to build
+    const HAS_LINES: () = assert!(LINES > 0, "the console needs at least one line");
+
+    /// Create a blank console covering the whole `display`
+    pub fn new(display: D) -> Self {
+        let () = Self::HAS_LINES;
+
+        let (columns, rows) = display.size();
+        // The dirty rows are tracked in a u64
+        let rows = rows.min(LINES).min(64);
+
+        let mut console = Self {
+            display,
+            columns: columns.min(COLS),
+            rows,
+            lines: [[b' '; COLS]; LINES],
+            first: 0,
+            count: rows,
+            column: 0,
+            line: 0,
+            scrollback: 0,
+            status: [[b' '; COLS]; MAX_STATUS_ROWS],
+            status_rows: 0,
+            status_position: StatusPosition::Top,
+            dirty: 0,
+        };
+        console.invalidate();
+        console
+    }
+
+    /// Reserve up to [`MAX_STATUS_ROWS`] rows for a status bar which doesn't scroll
+    pub fn with_status_bar(mut self, rows: usize, position: StatusPosition) -> Self {
+        self.status_rows = rows.min(MAX_STATUS_ROWS).min(self.rows.saturating_sub(1));
+        self.status_position = position;
+        self.clear();
+        self
+    }
+
+    /// Release the display
+    pub fn release(self) -> D {
+        self.display
+    }
+
+    /// Number of columns and rows of the text area
+    pub fn size(&self) -> (usize, usize) {
+        (self.columns, self.text_rows())
+    }
+
+    /// Blank the text area and move the cursor to the top left corner
+    pub fn clear(&mut self) {
+        self.lines = [[b' '; COLS]; LINES];
+        self.first = 0;
+        self.count = self.text_rows();
+        self.column = 0;
+        self.line = 0;
+        self.scrollback = 0;
+        self.invalidate();
+    }
+
+    /// Move the cursor to `column` and `row` of the text area
+    pub fn set_cursor(&mut self, column: usize, row: usize) {
+        self.scroll_to_end();
+        self.column = column.min(self.columns.saturating_sub(1));
+        self.line = self.top() + row.min(self.text_rows().saturating_sub(1));
+    }
+
+    /// Column and row of the cursor within the text area
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.column, self.line.saturating_sub(self.top()))
+    }
+
+    /// Replace the contents of status bar `row`
+    pub fn set_status(&mut self, row: usize, args: fmt::Arguments<'_>) {
+        if row >= self.status_rows {
+            return;
+        }
+
+        self.status[row] = [b' '; COLS];
+        let mut writer = StatusWriter {
+            line: &mut self.status[row][..self.columns],
+            column: 0,
+        };
+        fmt::write(&mut writer, args).ok();
+
+        let display_row = self.status_row(row);
+        self.dirty |= 1 << display_row;
+    }
+
+    /// Show older lines, `lines` further back than now
+    pub fn scroll_back(&mut self, lines: usize) {
+        let max = self.count - self.text_rows();
+        let scrollback = (self.scrollback + lines).min(max);
+        if scrollback != self.scrollback {
+            self.scrollback = scrollback;
+            self.invalidate_text();
+        }
+    }
+
+    /// Show newer lines, `lines` closer to the end than now
+    pub fn scroll_forward(&mut self, lines: usize) {
+        let scrollback = self.scrollback.saturating_sub(lines);
+        if scrollback != self.scrollback {
+            self.scrollback = scrollback;
+            self.invalidate_text();
+        }
+    }
+
+    /// Show the most recent lines again
+    pub fn scroll_to_end(&mut self) {
+        self.scroll_forward(self.scrollback);
+    }
+
+    /// Number of lines the view is scrolled back
+    pub fn scrollback(&self) -> usize {
+        self.scrollback
+    }
+
+    /// Draw all rows which changed since the last call
+    pub fn flush(&mut self) -> Result<(), D::Error> {
+        for row in 0..self.rows {
+            if self.dirty & 1 << row == 0 {
+                continue;
+            }
+
+            let text = match self.row_content(row) {
+                RowContent::Status(index) => &self.status[index],
+                RowContent::Line(line) => &self.lines[(self.first + line) % LINES],
+            };
+            self.display.draw_row(row, &text[..self.columns])?;
+            self.dirty &= !(1 << row);
+        }
+
+        Ok(())
+    }
+
+    /// Put a single character at the cursor, handling control characters
+    fn put(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            _ => {
+                if self.column >= self.columns {
+                    self.new_line();
+                }
+
+                let byte = if c.is_ascii() && !c.is_ascii_control() {
+                    c as u8
+                } else {
+                    b'?'
+                };
+                self.lines[(self.first + self.line) % LINES][self.column] = byte;
+                self.mark_line(self.line);
+                self.column += 1;
+            }
+        }
+    }
+
+    /// Move the cursor to the start of the next line, scrolling if necessary
+    fn new_line(&mut self) {
+        self.column = 0;
+
+        if self.line + 1 < self.count {
+            self.line += 1;
+            return;
+        }
+
+        // Append a line, dropping the oldest one if the buffer is full
+        if self.count < LINES {
+            self.count += 1;
+        } else {
+            self.first = (self.first + 1) % LINES;
+        }
+        self.line = self.count - 1;
+        self.lines[(self.first + self.line) % LINES] = [b' '; COLS];
+        self.invalidate_text();
+    }
+
+    fn text_rows(&self) -> usize {
+        self.rows - self.status_rows
+    }
+
+    /// Logical index of the line shown in the first row of the text area
+    fn top(&self) -> usize {
+        self.count - self.text_rows() - self.scrollback
+    }
+
+    fn status_row(&self, index: usize) -> usize {
+        match self.status_position {
+            StatusPosition::Top => index,
+            StatusPosition::Bottom => self.text_rows() + index,
+        }
+    }
+
+    fn row_content(&self, row: usize) -> RowContent {
+        let text_start = match self.status_position {
+            StatusPosition::Top => self.status_rows,
+            StatusPosition::Bottom => 0,
+        };
+
+        if row >= text_start && row < text_start + self.text_rows() {
+            RowContent::Line(self.top() + row - text_start)
+        } else {
+            RowContent::Status(
+                row - if row < text_start {
+                    0
+                } else {
+                    self.text_rows()
+                },
+            )
+        }
+    }
+
+    fn mark_line(&mut self, line: usize) {
+        let top = self.top();
+        if line >= top && line < top + self.text_rows() {
+            let text_start = match self.status_position {
+                StatusPosition::Top => self.status_rows,
+                StatusPosition::Bottom => 0,
+            };
+            self.dirty |= 1 << (text_start + line - top);
+        }
+    }
+
+    fn invalidate_text(&mut self) {
+        for line in self.top()..self.top() + self.text_rows() {
+            self.mark_line(line);
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.dirty = if self.rows == 64 {
+            u64::MAX
+        } else {
+            (1 << self.rows) - 1
+        };
+    }
+}
+
+impl<D: CharDisplay, const COLS: usize, const LINES: usize> fmt::Write for Console<D, COLS, LINES> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.scroll_to_end();
+        s.chars().for_each(|c| self.put(c));
+        self.flush().map_err(|_| fmt::Error)
+    }
+}
+
+enum RowContent {
+    Status(usize),
+    Line(usize),
+}
+
+/// Formats into a line of the status bar, cutting off what doesn't fit
+struct StatusWriter<'a> {
+    line: &'a mut [u8],
+    column: usize,
+}
+
+impl fmt::Write for StatusWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if let Some(slot) = self.line.get_mut(self.column) {
+                *slot = if c.is_ascii() && !c.is_ascii_control() {
+                    c as u8
+                } else {
+                    b'?'
+                };
+                self.column += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// SSD1306 in terminal mode with the built-in 8x8 font, i.e. 16 columns
+#[cfg(feature = "ssd1306")]
+pub struct TerminalDisplay<DI> {
+    terminal: ssd1306::mode::TerminalMode<DI>,
+    rows: usize,
+}
+
+#[cfg(feature = "ssd1306")]
+impl<DI: ssd1306::interface::DisplayInterface> TerminalDisplay<DI> {
+    /// Wrap an initialised terminal with `rows` rows, i.e. 8 for 128x64 and 4 for 128x32 pixels
+    pub fn new(terminal: ssd1306::mode::TerminalMode<DI>, rows: usize) -> Self {
+        Self { terminal, rows }
+    }
+
+    /// Release the terminal
+    pub fn release(self) -> ssd1306::mode::TerminalMode<DI> {
+        self.terminal
+    }
+}
+
+#[cfg(feature = "ssd1306")]
+impl<DI: ssd1306::interface::DisplayInterface> CharDisplay for TerminalDisplay<DI> {
+    type Error = ssd1306::mode::terminal::TerminalModeError;
+
+    fn size(&self) -> (usize, usize) {
+        (16, self.rows)
+    }
+
+    fn draw_row(&mut self, row: usize, text: &[u8]) -> Result<(), Self::Error> {
+        self.terminal.set_position(0, row as u8)?;
+        for &byte in text {
+            self.terminal.print_char(byte as char)?;
+        }
+        Ok(())
+    }
+}
+
+/// Any `embedded-graphics` display, e.g. the ST7789, showing text in a monospaced font
+#[cfg(feature = "embedded-graphics")]
+pub struct GraphicsDisplay<D, F, C> {
+    display: D,
+    font: F,
+    columns: usize,
+    rows: usize,
+    foreground: C,
+    background: C,
+}
+
+#[cfg(feature = "embedded-graphics")]
+impl<D, F, C> GraphicsDisplay<D, F, C>
+where
+    D: embedded_graphics::DrawTarget<C>,
+    F: embedded_graphics::fonts::Font + Copy,
+    C: embedded_graphics::pixelcolor::PixelColor,
+{
+    /// Use the whole `display` for text in `font` with the given colors
+    pub fn new(display: D, font: F, foreground: C, background: C) -> Self {
+        let size = display.size();
+        Self {
+            display,
+            font,
+            columns: (size.width / F::CHARACTER_SIZE.width) as usize,
+            rows: (size.height / F::CHARACTER_SIZE.height) as usize,
+            foreground,
+            background,
+        }
+    }
+
+    /// Release the display
+    pub fn release(self) -> D {
+        self.display
+    }
+}
+
+#[cfg(feature = "embedded-graphics")]
+impl<D, F, C> CharDisplay for GraphicsDisplay<D, F, C>
+where
+    D: embedded_graphics::DrawTarget<C>,
+    F: embedded_graphics::fonts::Font + Copy,
+    C: embedded_graphics::pixelcolor::PixelColor,
+{
+    type Error = D::Error;
+
+    fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    fn draw_row(&mut self, row: usize, text: &[u8]) -> Result<(), Self::Error> {
+        use embedded_graphics::{fonts::Text, prelude::*, style::TextStyleBuilder};
+
+        let style = TextStyleBuilder::new(self.font)
+            .text_color(self.foreground)
+            .background_color(self.background)
+            .build();
+        let position = Point::new(0, (row as u32 * F::CHARACTER_SIZE.height) as i32);
+
+        // The console only ever hands out ASCII
+        let text = core::str::from_utf8(text).unwrap_or("");
+        Text::new(text, position)
+            .into_styled(style)
+            .draw(&mut self.display)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::fmt::Write;
+
+    /// A display of 4x3 characters remembering which rows were drawn
+    struct Screen {
+        rows: [[u8; 4]; 3],
+        drawn: u32,
+    }
+
+    impl Screen {
+        fn new() -> Self {
+            Self {
+                rows: [[b'.'; 4]; 3],
+                drawn: 0,
+            }
+        }
+    }
+
+    impl CharDisplay for Screen {
+        type Error = ();
+
+        fn size(&self) -> (usize, usize) {
+            (4, 3)
+        }
+
+        fn draw_row(&mut self, row: usize, text: &[u8]) -> Result<(), ()> {
+            self.rows[row].copy_from_slice(text);
+            self.drawn |= 1 << row;
+            Ok(())
+        }
+    }
+
+    fn shown<const LINES: usize>(console: Console<Screen, 8, LINES>) -> [[u8; 4]; 3] {
+        console.release().rows
+    }
+
+    #[test]
+    fn text_wraps_at_display_width() {
+        let mut console: Console<_, 8, 6> = Console::new(Screen::new());
+        write!(console, "abcdef").unwrap();
+        assert_eq!(console.cursor(), (2, 1));
+
+        // Only the row with the cursor changes
+        console.display.drawn = 0;
+        write!(console, "g").unwrap();
+        assert_eq!(console.display.drawn, 1 << 1);
+
+        assert_eq!(shown(console), [*b"abcd", *b"efg ", *b"    "]);
+    }
+
+    #[test]
+    fn oldest_line_is_dropped() {
+        let mut console: Console<_, 8, 6> = Console::new(Screen::new());
+        write!(console, "1\n2\n3\n4\n5\n6\n7").unwrap();
+        assert_eq!(console.cursor(), (1, 2));
+
+        console.scroll_back(10);
+        assert_eq!(console.scrollback(), 3);
+        console.flush().unwrap();
+        assert_eq!(console.display.rows, [*b"2   ", *b"3   ", *b"4   "]);
+
+        // Writing shows the end again
+        write!(console, "8").unwrap();
+        assert_eq!(console.scrollback(), 0);
+        assert_eq!(shown(console), [*b"5   ", *b"6   ", *b"78  "]);
+    }
+
+    #[test]
+    fn scroll_back_is_limited_to_written_lines() {
+        let mut console: Console<_, 8, 6> = Console::new(Screen::new());
+        write!(console, "1\n2\n3\n4").unwrap();
+
+        console.scroll_back(1);
+        console.scroll_back(5);
+        assert_eq!(console.scrollback(), 1);
+        console.flush().unwrap();
+        assert_eq!(console.display.rows, [*b"1   ", *b"2   ", *b"3   "]);
+
+        console.scroll_forward(5);
+        assert_eq!(console.scrollback(), 0);
+    }
+
+    #[test]
+    fn status_bar_at_top() {
+        let mut console: Console<_, 8, 6> =
+            Console::new(Screen::new()).with_status_bar(1, StatusPosition::Top);
+        assert_eq!(console.size(), (4, 2));
+
+        console.set_status(0, format_args!("ok!?!"));
+        write!(console, "a\nb\nc").unwrap();
+        assert_eq!(shown(console), [*b"ok!?", *b"b   ", *b"c   "]);
+    }
+
+    #[test]
+    fn status_bar_at_bottom() {
+        let mut console: Console<_, 8, 6> =
+            Console::new(Screen::new()).with_status_bar(1, StatusPosition::Bottom);
+        console.set_cursor(3, 1);
+        assert_eq!(console.cursor(), (3, 1));
+
+        console.set_status(0, format_args!("ok"));
+        write!(console, "a\nb\nc").unwrap();
+        assert_eq!(shown(console), [*b"b   ", *b"c   ", *b"ok  "]);
+    }
+}
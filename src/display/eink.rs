@@ -0,0 +1,252 @@
+//! Waveshare 1.54" e-paper panel with power saving refreshes
+//!
+//! [`EinkPanel`] mixes partial and full refreshes as decided by a [`RefreshSchedule`], sleeps
+//! while the panel is busy and puts the panel into deep sleep between updates.
+
+use crate::exti::{Exti, Line};
+use crate::power::{LowPower, Regulator, Wakeup};
+
+use crate::hal::{
+    gpio::{gpiob::PB6, Floating, Input},
+    rcc::Rcc,
+};
+
+use cortex_m::peripheral::SCB;
+use embedded_hal::{
+    blocking::{delay::DelayMs, spi::Write},
+    digital::v2::OutputPin,
+};
+use epd_waveshare::{
+    epd1in54::EPD1in54,
+    prelude::{RefreshLUT, WaveshareDisplay},
+};
+
+/// The BUSY pin of the panel
+pub type BusyPin = PB6<Input<Floating>>;
+
+/// Kind of a refresh of the panel
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Refresh {
+    /// Flashes the whole panel, removing any ghosting; takes about 2 s
+    Full,
+    /// Only changes pixels which differ, without flashing; takes about 300 ms
+    Partial,
+}
+
+/// Decides which refresh to use, forcing a full refresh every so many partial refreshes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RefreshSchedule {
+    full_every: u16,
+    partials: u16,
+    force_full: bool,
+}
+
+impl RefreshSchedule {
+    /// Do a full refresh first and then after every `full_every` partial refreshes
+    ///
+    /// With `full_every` set to 0 every refresh is a full one.
+    pub const fn new(full_every: u16) -> Self {
+        Self {
+            full_every,
+            partials: 0,
+            force_full: true,
+        }
+    }
+
+    /// Make the next refresh a full one
+    pub fn force_full(&mut self) {
+        self.force_full = true;
+    }
+
+    /// Number of partial refreshes since the last full refresh
+    pub fn partials(&self) -> u16 {
+        self.partials
+    }
+
+    /// Kind of the next refresh, counting it as done
+    pub fn next(&mut self) -> Refresh {
+        if self.force_full || self.partials >= self.full_every {
+            self.force_full = false;
+            self.partials = 0;
+            Refresh::Full
+        } else {
+            self.partials += 1;
+            Refresh::Partial
+        }
+    }
+}
+
+/// Waveshare 1.54" e-paper panel which is kept in deep sleep between updates
+///
+/// The end of a refresh is signalled by a falling edge of the BUSY pin on D5 (PB6), which is routed
+/// to EXTI line 6 as an event. Waiting for the panel therefore puts the MCU to sleep via `WFE`
+/// instead of polling the pin, no interrupt handler is needed.
+pub struct EinkPanel<SPI, CS, DC, RST> {
+    epd: EPD1in54<SPI, CS, BusyPin, DC, RST>,
+    line: Line<6>,
+    schedule: RefreshSchedule,
+    asleep: bool,
+}
+
+impl<SPI, CS, DC, RST> EinkPanel<SPI, CS, DC, RST>
+where
+    SPI: Write<u8>,
+    CS: OutputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+    /// Take over an initialised panel, doing a full refresh after every `full_every` partial
+    /// refreshes
+    pub fn new(
+        epd: EPD1in54<SPI, CS, BusyPin, DC, RST>,
+        full_every: u16,
+        exti: &mut Exti,
+        line: Line<6>,
+    ) -> Self {
+        // Only an event is generated, which wakes up WFE without needing an interrupt handler
+        exti.route::<BusyPin>(&line);
+        exti.set_edges(&line, false, true);
+        exti.enable_event(&line);
+
+        Self {
+            epd,
+            line,
+            schedule: RefreshSchedule::new(full_every),
+            asleep: false,
+        }
+    }
+
+    /// The schedule of full and partial refreshes
+    pub fn schedule(&mut self) -> &mut RefreshSchedule {
+        &mut self.schedule
+    }
+
+    /// Whether the panel is still refreshing
+    pub fn is_busy(&self) -> bool {
+        self.epd.is_busy()
+    }
+
+    /// Whether the panel is in deep sleep
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+
+    /// Transfer `buffer` and start refreshing the panel, waking it up first if necessary
+    ///
+    /// This returns as soon as the refresh has started, use [`EinkPanel::wait_until_idle`] or
+    /// [`EinkPanel::stop_until_idle`] to wait for its end.
+    pub fn update<DELAY: DelayMs<u8>>(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        delay: &mut DELAY,
+    ) -> Result<Refresh, SPI::Error> {
+        if self.asleep {
+            self.wait_until_idle();
+            self.epd.wake_up(spi, delay)?;
+            self.asleep = false;
+        }
+
+        let refresh = self.schedule.next();
+        let lut = match refresh {
+            Refresh::Full => RefreshLUT::FULL,
+            Refresh::Partial => RefreshLUT::QUICK,
+        };
+
+        self.wait_until_idle();
+        self.epd.set_lut(spi, Some(lut))?;
+        self.epd.update_frame(spi, buffer)?;
+        self.epd.display_frame(spi)?;
+
+        Ok(refresh)
+    }
+
+    /// Show `buffer`, wait for the refresh to finish and put the panel into deep sleep
+    pub fn show<DELAY: DelayMs<u8>>(
+        &mut self,
+        spi: &mut SPI,
+        buffer: &[u8],
+        delay: &mut DELAY,
+    ) -> Result<Refresh, SPI::Error> {
+        let refresh = self.update(spi, buffer, delay)?;
+        self.sleep(spi)?;
+        Ok(refresh)
+    }
+
+    /// Put the MCU into Sleep mode until the panel is idle
+    pub fn wait_until_idle(&mut self) {
+        while self.epd.is_busy() {
+            // An edge between the check and WFE sets the event register, so it is not missed
+            cortex_m::asm::wfe();
+        }
+    }
+
+    /// Put the MCU into Stop mode with the regulator in low-power mode until the panel is idle
+    ///
+    /// The clocks are restored by [`LowPower::stop_until`] before returning.
+    pub fn stop_until_idle(&mut self, power: &mut LowPower, scb: &mut SCB, rcc: &Rcc) -> Wakeup {
+        let epd = &self.epd;
+        power.stop_until(scb, Regulator::LowPower, rcc, || !epd.is_busy())
+    }
+
+    /// Wait for the panel to become idle and put it into deep sleep
+    ///
+    /// The panel keeps showing its image while drawing next to no current, the next
+    /// [`EinkPanel::update`] wakes it up again.
+    pub fn sleep(&mut self, spi: &mut SPI) -> Result<(), SPI::Error> {
+        if !self.asleep {
+            self.wait_until_idle();
+            self.epd.sleep(spi)?;
+            self.asleep = true;
+        }
+        Ok(())
+    }
+
+    /// Stop listening to the BUSY pin and release the panel and the token of its EXTI line
+    pub fn release(self, exti: &mut Exti) -> (EPD1in54<SPI, CS, BusyPin, DC, RST>, Line<6>) {
+        exti.disable_event(&self.line);
+        exti.set_edges(&self.line, false, false);
+        (self.epd, self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_refresh_first_and_periodically() {
+        let mut schedule = RefreshSchedule::new(2);
+
+        assert_eq!(schedule.next(), Refresh::Full);
+        assert_eq!(schedule.next(), Refresh::Partial);
+        assert_eq!(schedule.next(), Refresh::Partial);
+        assert_eq!(schedule.partials(), 2);
+        assert_eq!(schedule.next(), Refresh::Full);
+        assert_eq!(schedule.partials(), 0);
+        assert_eq!(schedule.next(), Refresh::Partial);
+    }
+
+    #[test]
+    fn forced_full_refresh_restarts_count() {
+        let mut schedule = RefreshSchedule::new(3);
+
+        schedule.next();
+        schedule.next();
+        schedule.force_full();
+        assert_eq!(schedule.next(), Refresh::Full);
+        assert_eq!(schedule.next(), Refresh::Partial);
+        assert_eq!(schedule.next(), Refresh::Partial);
+        assert_eq!(schedule.next(), Refresh::Partial);
+        assert_eq!(schedule.next(), Refresh::Full);
+    }
+
+    #[test]
+    fn only_full_refreshes() {
+        let mut schedule = RefreshSchedule::new(0);
+
+        for _ in 0..3 {
+            assert_eq!(schedule.next(), Refresh::Full);
+        }
+    }
+}
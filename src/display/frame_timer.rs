@@ -0,0 +1,89 @@
+//! Measuring how long rendering a frame takes
+
+use crate::time::{Duration, Instant, Monotonic};
+
+/// Statistics about the time spent rendering frames, based on [`Monotonic`]
+#[derive(Debug, Copy, Clone)]
+pub struct FrameTimer {
+    start: Option<Instant>,
+    last: Duration,
+    min: Duration,
+    max: Duration,
+    total: Duration,
+    frames: u32,
+}
+
+impl Default for FrameTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameTimer {
+    /// Create a frame timer without any measurements
+    pub const fn new() -> Self {
+        Self {
+            start: None,
+            last: Duration::ZERO,
+            min: Duration::from_micros(u64::MAX),
+            max: Duration::ZERO,
+            total: Duration::ZERO,
+            frames: 0,
+        }
+    }
+
+    /// Mark the start of a frame
+    pub fn begin(&mut self) {
+        self.start = Some(Monotonic::now());
+    }
+
+    /// Mark the end of a frame started with [`FrameTimer::begin`], returning its duration
+    pub fn end(&mut self) -> Duration {
+        if let Some(start) = self.start.take() {
+            self.last = start.elapsed();
+            self.min = self.min.min(self.last);
+            self.max = self.max.max(self.last);
+            self.total = self.total + self.last;
+            self.frames += 1;
+        }
+        self.last
+    }
+
+    /// Duration of the last frame
+    pub fn last(&self) -> Duration {
+        self.last
+    }
+
+    /// Shortest frame so far
+    pub fn min(&self) -> Duration {
+        if self.frames == 0 {
+            Duration::ZERO
+        } else {
+            self.min
+        }
+    }
+
+    /// Longest frame so far
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Average frame duration
+    pub fn average(&self) -> Duration {
+        if self.frames == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_micros(self.total.as_micros() / u64::from(self.frames))
+        }
+    }
+
+    /// Number of frames measured
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Forget all measurements
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
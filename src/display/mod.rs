@@ -0,0 +1,16 @@
+//! Helpers for driving displays attached to the board
+mod console;
+#[cfg(feature = "epd-waveshare")]
+mod eink;
+mod frame_timer;
+mod spi_dma;
+
+#[cfg(feature = "embedded-graphics")]
+pub use console::GraphicsDisplay;
+#[cfg(feature = "ssd1306")]
+pub use console::TerminalDisplay;
+pub use console::{CharDisplay, Console, StatusPosition, MAX_STATUS_ROWS};
+#[cfg(feature = "epd-waveshare")]
+pub use eink::{BusyPin, EinkPanel, Refresh, RefreshSchedule};
+pub use frame_timer::FrameTimer;
+pub use spi_dma::{LineBuffer, SpiDmaInterface};
@@ -0,0 +1,275 @@
+//! SPI display interface with pixel data sent by DMA
+//!
+//! [`SpiDmaInterface`] implements `display-interface` for SPI1, so display drivers like
+//! `st7789` can stream pixels while the CPU prepares the next line in a [`LineBuffer`].
+
+use crate::dma::{Channel, SpiTxDma, Transfer};
+
+use crate::hal::{
+    spi::{EightBit, SixteenBit, Spi},
+    stm32::SPI1,
+};
+
+use display_interface::{DataFormat, DisplayError, WriteOnlyDataCommand};
+use embedded_dma::ReadBuffer;
+use embedded_hal::{blocking::spi::Write as _, digital::v2::OutputPin};
+
+/// A line of pixels which can be streamed via DMA
+pub struct LineBuffer<const N: usize> {
+    pixels: &'static mut [u16; N],
+    len: usize,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    fn new(pixels: &'static mut [u16; N]) -> Self {
+        Self { pixels, len: 0 }
+    }
+
+    /// Fill the buffer from `pixels`, returning the number of pixels taken
+    fn fill_from(&mut self, pixels: &mut impl Iterator<Item = u16>) -> usize {
+        self.len = 0;
+        for (slot, pixel) in self.pixels.iter_mut().zip(pixels) {
+            *slot = pixel;
+            self.len += 1;
+        }
+        self.len
+    }
+}
+
+unsafe impl<const N: usize> ReadBuffer for LineBuffer<N> {
+    type Word = u16;
+
+    unsafe fn read_buffer(&self) -> (*const u16, usize) {
+        (self.pixels.as_ptr(), self.len)
+    }
+}
+
+/// State of the SPI bus, which is switched between 8-bit frames for commands and 16-bit frames
+/// for pixel data
+enum Bus<SCKPIN, MISOPIN, MOSIPIN, const N: usize> {
+    Bytes(Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, EightBit>, Channel<3>),
+    Words(SpiTxDma<SCKPIN, MISOPIN, MOSIPIN, SixteenBit>),
+    Busy(Transfer<LineBuffer<N>, SpiTxDma<SCKPIN, MISOPIN, MOSIPIN, SixteenBit>>),
+}
+
+/// Display interface streaming pixel data via SPI1 and DMA
+///
+/// Commands and their parameters are sent as blocking 8-bit writes, pixel data is copied into
+/// one of two line buffers of `N` pixels and sent in 16-bit frames by the DMA while the other
+/// line buffer is being filled. With lines of 240 pixels, as used by the common 240x240 ST7789
+/// and 240x320 ILI9341 panels, the two line buffers take up 960 bytes of RAM.
+///
+/// Chip select is not handled, it has to be tied low or managed by the caller.
+pub struct SpiDmaInterface<SCKPIN, MISOPIN, MOSIPIN, DC, const N: usize> {
+    bus: Option<Bus<SCKPIN, MISOPIN, MOSIPIN, N>>,
+    free: [Option<LineBuffer<N>>; 2],
+    dc: DC,
+}
+
+impl<SCKPIN, MISOPIN, MOSIPIN, DC, const N: usize> SpiDmaInterface<SCKPIN, MISOPIN, MOSIPIN, DC, N>
+where
+    DC: OutputPin,
+{
+    /// Create the interface from an SPI1 set up with 8-bit frames, DMA channel 3, the data/command
+    /// pin and two line buffers
+    pub fn new(
+        spi: Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, EightBit>,
+        channel: Channel<3>,
+        dc: DC,
+        lines: [&'static mut [u16; N]; 2],
+    ) -> Self {
+        let [first, second] = lines;
+
+        Self {
+            bus: Some(Bus::Bytes(spi, channel)),
+            free: [Some(LineBuffer::new(first)), Some(LineBuffer::new(second))],
+            dc,
+        }
+    }
+
+    /// Wait for pending transfers and release all resources
+    pub fn release(
+        mut self,
+    ) -> (
+        Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, EightBit>,
+        Channel<3>,
+        DC,
+        [&'static mut [u16; N]; 2],
+    ) {
+        self.flush().ok();
+        let (spi, channel) = self.bytes();
+
+        let [first, second] = self.free;
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        (spi, channel, self.dc, [first.pixels, second.pixels])
+    }
+
+    /// Send `count` pixels of the same `color` as pixel data
+    ///
+    /// The address window has to be set up beforehand, e.g. by the display driver.
+    pub fn fill(&mut self, color: u16, count: u32) -> Result<(), DisplayError> {
+        self.flush()?;
+        self.dc.set_high().map_err(|_| DisplayError::DCError)?;
+
+        // Both line buffers are filled with the color once and then sent alternately
+        for line in self.free.iter_mut().flatten() {
+            line.pixels.iter_mut().for_each(|pixel| *pixel = color);
+        }
+
+        let mut left = count as usize;
+        while left > 0 {
+            let mut line = self.take_line()?;
+            line.len = left.min(N);
+            left -= line.len;
+            self.send(line)?;
+        }
+
+        self.flush()
+    }
+
+    /// Send `pixels` as pixel data
+    ///
+    /// The address window has to be set up beforehand, e.g. by the display driver.
+    pub fn blit(&mut self, pixels: &[u16]) -> Result<(), DisplayError> {
+        self.stream(pixels.iter().copied())
+    }
+
+    /// Send pixel data from an iterator
+    fn stream(&mut self, mut pixels: impl Iterator<Item = u16>) -> Result<(), DisplayError> {
+        self.flush()?;
+        self.dc.set_high().map_err(|_| DisplayError::DCError)?;
+
+        loop {
+            let mut line = self.take_line()?;
+            if line.fill_from(&mut pixels) == 0 {
+                self.put_line(line);
+                break;
+            }
+            self.send(line)?;
+        }
+
+        self.flush()
+    }
+
+    /// Send bytes as blocking 8-bit writes
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), DisplayError> {
+        let (mut spi, channel) = self.bytes();
+        let res = spi.write(bytes).map_err(|_| DisplayError::BusWriteError);
+        self.bus = Some(Bus::Bytes(spi, channel));
+        res
+    }
+
+    fn write_byte_iter(&mut self, bytes: &mut dyn Iterator<Item = u8>) -> Result<(), DisplayError> {
+        let mut chunk = [0; 16];
+        loop {
+            let mut len = 0;
+            for (slot, byte) in chunk.iter_mut().zip(&mut *bytes) {
+                *slot = byte;
+                len += 1;
+            }
+            if len == 0 {
+                return Ok(());
+            }
+            self.write_bytes(&chunk[..len])?;
+        }
+    }
+
+    /// Get a free line buffer, waiting for the transfer in flight if necessary
+    fn take_line(&mut self) -> Result<LineBuffer<N>, DisplayError> {
+        if let Some(line) = self.free.iter_mut().find_map(Option::take) {
+            return Ok(line);
+        }
+
+        self.flush()?;
+        Ok(self.free.iter_mut().find_map(Option::take).unwrap())
+    }
+
+    fn put_line(&mut self, line: LineBuffer<N>) {
+        if let Some(slot) = self.free.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(line);
+        }
+    }
+
+    /// Start sending a line as soon as the previous one is done
+    fn send(&mut self, line: LineBuffer<N>) -> Result<(), DisplayError> {
+        self.flush()?;
+
+        let spi = match self.bus.take() {
+            Some(Bus::Bytes(spi, channel)) => SpiTxDma::new(spi.into_16bit_width(), channel),
+            Some(Bus::Words(spi)) => spi,
+            _ => unreachable!(),
+        };
+
+        self.bus = Some(Bus::Busy(spi.write(line)));
+        Ok(())
+    }
+
+    /// Wait for the transfer in flight, if any
+    fn flush(&mut self) -> Result<(), DisplayError> {
+        match self.bus.take() {
+            Some(Bus::Busy(transfer)) => {
+                let (res, line, spi) = match transfer.wait() {
+                    Ok((line, spi)) => (Ok(()), line, spi),
+                    Err((_, line, spi)) => (Err(DisplayError::BusWriteError), line, spi),
+                };
+                self.put_line(line);
+                self.bus = Some(Bus::Words(spi));
+                res
+            }
+            bus => {
+                self.bus = bus;
+                Ok(())
+            }
+        }
+    }
+
+    /// Switch the idle bus to 8-bit frames and take it out
+    fn bytes(&mut self) -> (Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, EightBit>, Channel<3>) {
+        self.flush().ok();
+
+        match self.bus.take() {
+            Some(Bus::Bytes(spi, channel)) => (spi, channel),
+            Some(Bus::Words(spi)) => {
+                let (spi, channel) = spi.release();
+                (spi.into_8bit_width(), channel)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<SCKPIN, MISOPIN, MOSIPIN, DC, const N: usize> WriteOnlyDataCommand
+    for SpiDmaInterface<SCKPIN, MISOPIN, MOSIPIN, DC, N>
+where
+    DC: OutputPin,
+{
+    fn send_commands(&mut self, cmd: DataFormat<'_>) -> Result<(), DisplayError> {
+        self.flush()?;
+        self.dc.set_low().map_err(|_| DisplayError::DCError)?;
+
+        match cmd {
+            DataFormat::U8(bytes) => self.write_bytes(bytes),
+            DataFormat::U8Iter(bytes) => self.write_byte_iter(bytes),
+            _ => Err(DisplayError::DataFormatNotImplemented),
+        }
+    }
+
+    fn send_data(&mut self, buf: DataFormat<'_>) -> Result<(), DisplayError> {
+        self.flush()?;
+        self.dc.set_high().map_err(|_| DisplayError::DCError)?;
+
+        // 16-bit frames are shifted out MSB first, i.e. big endian
+        match buf {
+            DataFormat::U8(bytes) => self.write_bytes(bytes),
+            DataFormat::U8Iter(bytes) => self.write_byte_iter(bytes),
+            DataFormat::U16BE(words) => self.stream(words.iter().copied()),
+            DataFormat::U16BEIter(words) => self.stream(words),
+            DataFormat::U16LE(words) => self.stream(words.iter().map(|w| w.swap_bytes())),
+            DataFormat::U16LEIter(words) => self.stream(words.map(u16::swap_bytes)),
+            // Native endianness, which is little endian on Cortex-M
+            DataFormat::U16(words) => self.stream(words.iter().map(|w| w.swap_bytes())),
+            _ => Err(DisplayError::DataFormatNotImplemented),
+        }
+    }
+}
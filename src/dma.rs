@@ -0,0 +1,998 @@
+//! DMA channels and DMA driven transfers for SPI1, USART2 and the ADC
+//!
+//! The F042 has a single DMA controller with five channels and a fixed request mapping:
+//!
+//! | Channel | Requests                              |
+//! |---------|---------------------------------------|
+//! | 1       | ADC                                   |
+//! | 2       | SPI1 RX, ADC (remapped)               |
+//! | 3       | SPI1 TX                               |
+//! | 4       | USART2 TX                             |
+//! | 5       | USART2 RX                             |
+//!
+//! The channel a request is served by is checked at compile time via the [`Request`] trait, the
+//! SYSCFG remap is applied automatically when the ADC is used with channel 2.
+//!
+//! Transfers take ownership of `'static` buffers (as described by the `embedded-dma` traits) for
+//! their whole duration, so the memory can't be touched or freed while the DMA accesses it.
+
+use crate::hal::{
+    adc::Adc,
+    rcc::Rcc,
+    serial::{Rx, Tx},
+    spi::{EightBit, SixteenBit, Spi},
+    stm32::{Interrupt, ADC, DMA1, RCC, SPI1, SYSCFG, USART2},
+};
+
+use core::{
+    mem::ManuallyDrop,
+    ptr,
+    sync::atomic::{compiler_fence, Ordering},
+};
+
+use embedded_dma::{ReadBuffer, WriteBuffer};
+
+// Channel register offsets
+const CCR: usize = 0x00;
+const CNDTR: usize = 0x04;
+const CPAR: usize = 0x08;
+const CMAR: usize = 0x0c;
+
+// CCR bits
+const EN: u32 = 1 << 0;
+const TCIE: u32 = 1 << 1;
+const HTIE: u32 = 1 << 2;
+const TEIE: u32 = 1 << 3;
+const DIR: u32 = 1 << 4;
+const CIRC: u32 = 1 << 5;
+const MINC: u32 = 1 << 7;
+
+// Per channel ISR/IFCR flags, shifted by 4 bits per channel
+const GIF: u32 = 1 << 0;
+const TCIF: u32 = 1 << 1;
+const HTIF: u32 = 1 << 2;
+const TEIF: u32 = 1 << 3;
+
+// SYSCFG_CFGR1 remap bit for the ADC request
+const ADC_DMA_RMP: u32 = 1 << 8;
+
+/// Errors of DMA transfers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The DMA accessed an invalid address
+    TransferError,
+    /// A half of a circular buffer was overwritten before it was read
+    Overrun,
+}
+
+/// Interrupt generating channel events
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    HalfTransfer,
+    TransferComplete,
+    TransferError,
+}
+
+/// A half of a circular buffer
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Half {
+    First,
+    Second,
+}
+
+/// Channel priority
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Priority {
+    Low = 0,
+    Medium = 1,
+    High = 2,
+    VeryHigh = 3,
+}
+
+/// Word sizes supported by the DMA
+pub trait Word: Copy + 'static {
+    /// Value of the PSIZE/MSIZE fields
+    const SIZE: u32;
+}
+
+impl Word for u8 {
+    const SIZE: u32 = 0b00;
+}
+
+impl Word for u16 {
+    const SIZE: u32 = 0b01;
+}
+
+impl Word for u32 {
+    const SIZE: u32 = 0b10;
+}
+
+/// A DMA channel, `N` being the channel number as used in the reference manual
+pub struct Channel<const N: u8> {
+    _private: (),
+}
+
+/// All channels of the DMA controller
+pub struct Channels {
+    pub ch1: Channel<1>,
+    pub ch2: Channel<2>,
+    pub ch3: Channel<3>,
+    pub ch4: Channel<4>,
+    pub ch5: Channel<5>,
+}
+
+impl Channels {
+    /// Enable the DMA controller and split it into its channels
+    pub fn new(_dma: DMA1, _rcc: &mut Rcc) -> Self {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // SYSCFG is needed for the request remapping
+        rcc.ahbenr.modify(|_, w| w.dmaen().set_bit());
+        rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+
+        Self {
+            ch1: Channel { _private: () },
+            ch2: Channel { _private: () },
+            ch3: Channel { _private: () },
+            ch4: Channel { _private: () },
+            ch5: Channel { _private: () },
+        }
+    }
+}
+
+fn register(n: u8, offset: usize) -> *mut u32 {
+    (DMA1::ptr() as usize + 0x08 + 20 * (usize::from(n) - 1) + offset) as *mut u32
+}
+
+fn flags(n: u8) -> u32 {
+    let isr = unsafe { ptr::read_volatile(DMA1::ptr() as *const u32) };
+    (isr >> (4 * (u32::from(n) - 1))) & 0xf
+}
+
+fn clear_flags(n: u8, flags: u32) {
+    unsafe {
+        ptr::write_volatile(
+            (DMA1::ptr() as usize + 0x04) as *mut u32,
+            (flags & 0xf) << (4 * (u32::from(n) - 1)),
+        )
+    };
+}
+
+fn modify_ccr(n: u8, f: impl FnOnce(u32) -> u32) {
+    unsafe {
+        let ccr = register(n, CCR);
+        ptr::write_volatile(ccr, f(ptr::read_volatile(ccr)));
+    }
+}
+
+impl<const N: u8> Channel<N> {
+    /// Channel number as used in the reference manual
+    pub fn number(&self) -> u8 {
+        N
+    }
+
+    /// The interrupt this channel's events are signalled with
+    pub fn interrupt(&self) -> Interrupt {
+        match N {
+            1 => Interrupt::DMA1_CH1,
+            2 | 3 => Interrupt::DMA1_CH2_3,
+            _ => Interrupt::DMA1_CH4_5_6_7,
+        }
+    }
+
+    /// Generate an interrupt for `event`
+    pub fn listen(&mut self, event: Event) {
+        let bit = event_bit(event);
+        modify_ccr(N, |ccr| ccr | bit);
+    }
+
+    /// Stop generating an interrupt for `event`
+    pub fn unlisten(&mut self, event: Event) {
+        let bit = event_bit(event);
+        modify_ccr(N, |ccr| ccr & !bit);
+    }
+
+    /// Whether the transfer complete flag is set
+    pub fn is_complete(&self) -> bool {
+        flags(N) & TCIF != 0
+    }
+
+    /// Whether the half transfer flag is set
+    pub fn is_half_complete(&self) -> bool {
+        flags(N) & HTIF != 0
+    }
+
+    /// Whether a transfer error occurred
+    pub fn has_error(&self) -> bool {
+        flags(N) & TEIF != 0
+    }
+
+    /// Clear all event flags, to be called from the interrupt handler
+    pub fn clear_events(&mut self) {
+        clear_flags(N, GIF | TCIF | HTIF | TEIF);
+    }
+
+    /// Number of words left to transfer
+    pub fn remaining(&self) -> u16 {
+        unsafe { ptr::read_volatile(register(N, CNDTR)) as u16 }
+    }
+
+    /// Set the priority used for arbitration between the channels
+    pub fn set_priority(&mut self, priority: Priority) {
+        modify_ccr(N, |ccr| (ccr & !(0b11 << 12)) | (priority as u32) << 12);
+    }
+
+    /// Configure and enable the channel
+    ///
+    /// # Safety
+    ///
+    /// `peripheral` and `memory` have to stay valid for the whole transfer.
+    unsafe fn start<W: Word>(
+        &mut self,
+        peripheral: u32,
+        memory: u32,
+        len: usize,
+        from_memory: bool,
+        memory_increment: bool,
+        circular: bool,
+    ) {
+        assert!(len <= usize::from(u16::MAX));
+
+        self.stop();
+        self.clear_events();
+
+        ptr::write_volatile(register(N, CPAR), peripheral);
+        ptr::write_volatile(register(N, CMAR), memory);
+        ptr::write_volatile(register(N, CNDTR), len as u32);
+
+        // Make sure all buffer writes are done before the DMA gets to see them
+        compiler_fence(Ordering::Release);
+
+        modify_ccr(N, |ccr| {
+            // Keep the interrupt enables and priority, set up everything else from scratch
+            let mut bits = ccr & (TCIE | HTIE | TEIE | 0b11 << 12);
+            bits |= W::SIZE << 8 | W::SIZE << 10 | EN;
+            if memory_increment {
+                bits |= MINC;
+            }
+            if from_memory {
+                bits |= DIR;
+            }
+            if circular {
+                bits |= CIRC;
+            }
+            bits
+        });
+    }
+
+    /// Disable the channel
+    fn stop(&mut self) {
+        modify_ccr(N, |ccr| ccr & !EN);
+
+        // Make sure the buffer isn't read before the DMA is done with it
+        compiler_fence(Ordering::Acquire);
+    }
+}
+
+fn event_bit(event: Event) -> u32 {
+    match event {
+        Event::HalfTransfer => HTIE,
+        Event::TransferComplete => TCIE,
+        Event::TransferError => TEIE,
+    }
+}
+
+/// DMA requests a channel can serve
+///
+/// # Safety
+///
+/// Must only be implemented for channels the request is actually routed to.
+pub unsafe trait Request<R> {
+    /// Apply the SYSCFG remap necessary to route the request to this channel
+    fn select(&mut self) {}
+}
+
+/// ADC request
+pub struct AdcRequest;
+/// SPI1 receive request
+pub struct Spi1RxRequest;
+/// SPI1 transmit request
+pub struct Spi1TxRequest;
+/// USART2 transmit request
+pub struct Usart2TxRequest;
+/// USART2 receive request
+pub struct Usart2RxRequest;
+
+fn remap_adc(remap: bool) {
+    let syscfg = unsafe { &(*SYSCFG::ptr()) };
+    syscfg.cfgr1.modify(|r, w| unsafe {
+        w.bits(if remap {
+            r.bits() | ADC_DMA_RMP
+        } else {
+            r.bits() & !ADC_DMA_RMP
+        })
+    });
+}
+
+unsafe impl Request<AdcRequest> for Channel<1> {
+    fn select(&mut self) {
+        remap_adc(false);
+    }
+}
+
+unsafe impl Request<AdcRequest> for Channel<2> {
+    fn select(&mut self) {
+        remap_adc(true);
+    }
+}
+
+unsafe impl Request<Spi1RxRequest> for Channel<2> {}
+unsafe impl Request<Spi1TxRequest> for Channel<3> {}
+unsafe impl Request<Usart2TxRequest> for Channel<4> {}
+unsafe impl Request<Usart2RxRequest> for Channel<5> {}
+
+/// Peripherals driving a DMA transfer
+pub trait TransferPayload {
+    /// Number of the DMA channel in use
+    fn channel(&self) -> u8;
+
+    /// Stop the peripheral from issuing further DMA requests
+    fn stop(&mut self);
+}
+
+/// Disable the channel of `payload` and stop the peripheral
+fn halt<PAYLOAD: TransferPayload>(payload: &mut PAYLOAD) {
+    let n = payload.channel();
+    modify_ccr(n, |ccr| ccr & !EN);
+    clear_flags(n, GIF | TCIF | HTIF | TEIF);
+    payload.stop();
+
+    // Make sure the buffer isn't read before the DMA is done with it
+    compiler_fence(Ordering::Acquire);
+}
+
+/// A one-shot transfer, owning the buffer and the peripheral until it is done
+///
+/// Dropping the transfer aborts it, the buffer and the peripheral are only returned by
+/// [`Transfer::wait`] and [`Transfer::abort`].
+#[must_use = "dropping the transfer aborts it"]
+pub struct Transfer<BUF, PAYLOAD: TransferPayload> {
+    buffer: BUF,
+    payload: PAYLOAD,
+}
+
+impl<BUF, PAYLOAD: TransferPayload> Transfer<BUF, PAYLOAD> {
+    /// Whether the transfer has finished, successfully or not
+    pub fn is_done(&self) -> bool {
+        flags(self.payload.channel()) & (TCIF | TEIF) != 0
+    }
+
+    /// Number of words left to transfer
+    pub fn remaining(&self) -> u16 {
+        unsafe { ptr::read_volatile(register(self.payload.channel(), CNDTR)) as u16 }
+    }
+
+    /// Block until the transfer is done and return the buffer and the peripheral
+    pub fn wait(self) -> Result<(BUF, PAYLOAD), (Error, BUF, PAYLOAD)> {
+        while !self.is_done() {}
+        self.finish()
+    }
+
+    /// Abort the transfer and return the buffer and the peripheral
+    pub fn abort(self) -> (BUF, PAYLOAD) {
+        match self.finish() {
+            Ok(res) => res,
+            Err((_, buffer, payload)) => (buffer, payload),
+        }
+    }
+
+    fn finish(self) -> Result<(BUF, PAYLOAD), (Error, BUF, PAYLOAD)> {
+        // Move the fields out without running `Drop`
+        let this = ManuallyDrop::new(self);
+        let (buffer, mut payload) = unsafe { (ptr::read(&this.buffer), ptr::read(&this.payload)) };
+        let error = flags(payload.channel()) & TEIF != 0;
+
+        halt(&mut payload);
+
+        if error {
+            Err((Error::TransferError, buffer, payload))
+        } else {
+            Ok((buffer, payload))
+        }
+    }
+}
+
+impl<BUF, PAYLOAD: TransferPayload> Drop for Transfer<BUF, PAYLOAD> {
+    fn drop(&mut self) {
+        halt(&mut self.payload);
+    }
+}
+
+/// The half of a circular buffer which can be accessed given the channel `flags`, if the next
+/// one is `next`
+fn readable_half(next: Half, flags: u32) -> Result<Option<Half>, Error> {
+    if flags & TEIF != 0 {
+        return Err(Error::TransferError);
+    }
+
+    match (next, flags & HTIF != 0, flags & TCIF != 0) {
+        // Both halves filled since the last peek means one of them was overwritten
+        (_, true, true) => Err(Error::Overrun),
+        (Half::First, true, false) | (Half::Second, false, true) => Ok(Some(next)),
+        (Half::First, false, true) | (Half::Second, true, false) => Err(Error::Overrun),
+        _ => Ok(None),
+    }
+}
+
+/// A circular transfer into a double buffer, each half is handed out once it has been filled
+///
+/// Dropping the transfer stops it, the buffer and the peripheral are only returned by
+/// [`CircTransfer::stop`].
+#[must_use = "dropping the transfer stops it"]
+pub struct CircTransfer<W: 'static, const L: usize, PAYLOAD: TransferPayload> {
+    buffer: &'static mut [[W; L]; 2],
+    payload: PAYLOAD,
+    next: Half,
+}
+
+impl<W: 'static, const L: usize, PAYLOAD: TransferPayload> CircTransfer<W, L, PAYLOAD> {
+    /// Number of words in both halves, buffers too large for the DMA are rejected at compile
+    /// time
+    const LEN: usize = {
+        assert!(
+            2 * L <= u16::MAX as usize,
+            "DMA transfers are limited to 65535 words"
+        );
+        2 * L
+    };
+}
+
+impl<W: Copy, const L: usize, PAYLOAD: TransferPayload> CircTransfer<W, L, PAYLOAD> {
+    /// The half that will be handed out by [`CircTransfer::peek`] next, if it has been filled
+    pub fn readable_half(&self) -> Result<Option<Half>, Error> {
+        readable_half(self.next, flags(self.payload.channel()))
+    }
+
+    /// Run `f` on the next filled half of the buffer
+    ///
+    /// Returns `Ok(None)` if the next half isn't ready yet and [`Error::Overrun`] if the DMA
+    /// caught up with `f` while it was running.
+    pub fn peek<R>(&mut self, f: impl FnOnce(&[W; L], Half) -> R) -> Result<Option<R>, Error> {
+        let half = match self.readable_half()? {
+            Some(half) => half,
+            None => return Ok(None),
+        };
+
+        let n = self.payload.channel();
+        clear_flags(
+            n,
+            match half {
+                Half::First => HTIF,
+                Half::Second => TCIF,
+            },
+        );
+        compiler_fence(Ordering::Acquire);
+
+        let res = f(
+            match half {
+                Half::First => &self.buffer[0],
+                Half::Second => &self.buffer[1],
+            },
+            half,
+        );
+
+        compiler_fence(Ordering::Acquire);
+
+        // The DMA must not have moved on into the half we've just read
+        let overrun = match half {
+            Half::First => flags(n) & TCIF != 0,
+            Half::Second => flags(n) & HTIF != 0,
+        };
+        if overrun {
+            return Err(Error::Overrun);
+        }
+
+        self.next = match half {
+            Half::First => Half::Second,
+            Half::Second => Half::First,
+        };
+
+        Ok(Some(res))
+    }
+
+    /// Run `f` on the half of the buffer the DMA has just finished sending, to refill it
+    ///
+    /// This is the counterpart of [`CircTransfer::peek`] for circular transmissions. Returns
+    /// `Ok(None)` if the DMA is still sending the next half and [`Error::Overrun`] if it wrapped
+    /// into the half being refilled before `f` was done.
+    pub fn poke<R>(&mut self, f: impl FnOnce(&mut [W; L], Half) -> R) -> Result<Option<R>, Error> {
+        let half = match self.readable_half()? {
+            Some(half) => half,
+            None => return Ok(None),
+        };
+
+        let n = self.payload.channel();
+        clear_flags(
+            n,
+            match half {
+                Half::First => HTIF,
+                Half::Second => TCIF,
+            },
+        );
+
+        let res = f(
+            match half {
+                Half::First => &mut self.buffer[0],
+                Half::Second => &mut self.buffer[1],
+            },
+            half,
+        );
+
+        // Make sure the new contents are written before the DMA gets to them
+        compiler_fence(Ordering::Release);
+
+        let overrun = match half {
+            Half::First => flags(n) & TCIF != 0,
+            Half::Second => flags(n) & HTIF != 0,
+        };
+        if overrun {
+            return Err(Error::Overrun);
+        }
+
+        self.next = match half {
+            Half::First => Half::Second,
+            Half::Second => Half::First,
+        };
+
+        Ok(Some(res))
+    }
+
+    /// Recover from an [`Error::Overrun`] by skipping to the half the DMA will fill next
+    pub fn clear_overrun(&mut self) {
+        let n = self.payload.channel();
+        clear_flags(n, GIF | HTIF | TCIF);
+
+        let remaining = unsafe { ptr::read_volatile(register(n, CNDTR)) } as usize;
+        self.next = if remaining > L {
+            Half::First
+        } else {
+            Half::Second
+        };
+    }
+
+    /// Stop the transfer and return the buffer and the peripheral
+    pub fn stop(self) -> (&'static mut [[W; L]; 2], PAYLOAD) {
+        // Move the fields out without running `Drop`
+        let this = ManuallyDrop::new(self);
+        let (buffer, mut payload) = unsafe { (ptr::read(&this.buffer), ptr::read(&this.payload)) };
+
+        halt(&mut payload);
+
+        (buffer, payload)
+    }
+}
+
+impl<W: 'static, const L: usize, PAYLOAD: TransferPayload> Drop for CircTransfer<W, L, PAYLOAD> {
+    fn drop(&mut self) {
+        halt(&mut self.payload);
+    }
+}
+
+/// Frame sizes of the SPI and the matching DMA word
+pub trait FrameSize {
+    type Word: Word;
+}
+
+impl FrameSize for EightBit {
+    type Word = u8;
+}
+
+impl FrameSize for SixteenBit {
+    type Word = u16;
+}
+
+// SPI register bits
+const SPI_RXDMAEN: u32 = 1 << 0;
+const SPI_TXDMAEN: u32 = 1 << 1;
+const SPI_BSY: u32 = 1 << 7;
+const SPI_FRLVL: u32 = 0b11 << 9;
+const SPI_FTLVL: u32 = 0b11 << 11;
+
+/// SPI1 transmitting via DMA channel 3
+pub struct SpiTxDma<SCKPIN, MISOPIN, MOSIPIN, WIDTH> {
+    spi: Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, WIDTH>,
+    channel: Channel<3>,
+}
+
+impl<SCKPIN, MISOPIN, MOSIPIN, WIDTH: FrameSize> SpiTxDma<SCKPIN, MISOPIN, MOSIPIN, WIDTH> {
+    pub fn new(spi: Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, WIDTH>, channel: Channel<3>) -> Self {
+        Self { spi, channel }
+    }
+
+    /// Send the whole buffer, discarding the received data
+    pub fn write<B>(mut self, buffer: B) -> Transfer<B, Self>
+    where
+        B: ReadBuffer<Word = WIDTH::Word>,
+    {
+        let (ptr, len) = unsafe { buffer.read_buffer() };
+        let spi = unsafe { &(*SPI1::ptr()) };
+
+        unsafe {
+            self.channel.start::<WIDTH::Word>(
+                &spi.dr as *const _ as u32,
+                ptr as u32,
+                len,
+                true,
+                true,
+                false,
+            )
+        };
+        spi.cr2
+            .modify(|r, w| unsafe { w.bits(r.bits() | SPI_TXDMAEN) });
+
+        Transfer {
+            buffer,
+            payload: self,
+        }
+    }
+
+    /// Send the same word `count` times, e.g. to fill an area of a display
+    pub fn repeat(
+        mut self,
+        word: &'static WIDTH::Word,
+        count: u16,
+    ) -> Transfer<&'static WIDTH::Word, Self> {
+        let spi = unsafe { &(*SPI1::ptr()) };
+
+        unsafe {
+            self.channel.start::<WIDTH::Word>(
+                &spi.dr as *const _ as u32,
+                word as *const _ as u32,
+                usize::from(count),
+                true,
+                // Don't walk through memory, keep sending the same word
+                false,
+                false,
+            );
+        }
+        spi.cr2
+            .modify(|r, w| unsafe { w.bits(r.bits() | SPI_TXDMAEN) });
+
+        Transfer {
+            buffer: word,
+            payload: self,
+        }
+    }
+
+    /// Send a double buffer continuously, each half can be refilled via [`CircTransfer::poke`]
+    /// once it has been sent
+    pub fn circ_write<const L: usize>(
+        mut self,
+        buffer: &'static mut [[WIDTH::Word; L]; 2],
+    ) -> CircTransfer<WIDTH::Word, L, Self> {
+        let spi = unsafe { &(*SPI1::ptr()) };
+
+        unsafe {
+            self.channel.start::<WIDTH::Word>(
+                &spi.dr as *const _ as u32,
+                buffer.as_ptr() as u32,
+                CircTransfer::<WIDTH::Word, L, Self>::LEN,
+                true,
+                true,
+                true,
+            )
+        };
+        spi.cr2
+            .modify(|r, w| unsafe { w.bits(r.bits() | SPI_TXDMAEN) });
+
+        CircTransfer {
+            buffer,
+            payload: self,
+            next: Half::First,
+        }
+    }
+
+    pub fn release(self) -> (Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, WIDTH>, Channel<3>) {
+        (self.spi, self.channel)
+    }
+}
+
+impl<SCKPIN, MISOPIN, MOSIPIN, WIDTH> TransferPayload
+    for SpiTxDma<SCKPIN, MISOPIN, MOSIPIN, WIDTH>
+{
+    fn channel(&self) -> u8 {
+        3
+    }
+
+    fn stop(&mut self) {
+        let spi = unsafe { &(*SPI1::ptr()) };
+
+        // Wait for the last frame to leave the shift register
+        while spi.sr.read().bits() & (SPI_FTLVL | SPI_BSY) != 0 {}
+
+        spi.cr2
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(SPI_TXDMAEN | SPI_RXDMAEN)) });
+
+        // Drop the data received in the meantime and clear the resulting overrun
+        while spi.sr.read().bits() & SPI_FRLVL != 0 {
+            let _ = spi.dr.read();
+        }
+        let _ = spi.sr.read();
+    }
+}
+
+// USART CR3 bits
+const USART_DMAR: u32 = 1 << 6;
+const USART_DMAT: u32 = 1 << 7;
+
+/// USART2 transmitter using DMA channel 4
+pub struct SerialTxDma {
+    tx: Tx<USART2>,
+    channel: Channel<4>,
+}
+
+impl SerialTxDma {
+    pub fn new(tx: Tx<USART2>, channel: Channel<4>) -> Self {
+        Self { tx, channel }
+    }
+
+    /// Send the whole buffer
+    pub fn write<B>(mut self, buffer: B) -> Transfer<B, Self>
+    where
+        B: ReadBuffer<Word = u8>,
+    {
+        let (ptr, len) = unsafe { buffer.read_buffer() };
+        let usart = unsafe { &(*USART2::ptr()) };
+
+        // Clear transmission complete so it can be waited for at the end
+        usart.icr.write(|w| w.tccf().set_bit());
+        unsafe {
+            self.channel.start::<u8>(
+                &usart.tdr as *const _ as u32,
+                ptr as u32,
+                len,
+                true,
+                true,
+                false,
+            )
+        };
+        usart
+            .cr3
+            .modify(|r, w| unsafe { w.bits(r.bits() | USART_DMAT) });
+
+        Transfer {
+            buffer,
+            payload: self,
+        }
+    }
+
+    pub fn release(self) -> (Tx<USART2>, Channel<4>) {
+        (self.tx, self.channel)
+    }
+}
+
+impl TransferPayload for SerialTxDma {
+    fn channel(&self) -> u8 {
+        4
+    }
+
+    fn stop(&mut self) {
+        let usart = unsafe { &(*USART2::ptr()) };
+        usart
+            .cr3
+            .modify(|r, w| unsafe { w.bits(r.bits() & !USART_DMAT) });
+    }
+}
+
+/// USART2 receiver using DMA channel 5
+pub struct SerialRxDma {
+    rx: Rx<USART2>,
+    channel: Channel<5>,
+}
+
+impl SerialRxDma {
+    pub fn new(rx: Rx<USART2>, channel: Channel<5>) -> Self {
+        Self { rx, channel }
+    }
+
+    /// Receive until the buffer is full
+    pub fn read<B>(mut self, mut buffer: B) -> Transfer<B, Self>
+    where
+        B: WriteBuffer<Word = u8>,
+    {
+        let (ptr, len) = unsafe { buffer.write_buffer() };
+        self.start(ptr as u32, len, false);
+
+        Transfer {
+            buffer,
+            payload: self,
+        }
+    }
+
+    /// Receive continuously into a double buffer
+    pub fn circ_read<const L: usize>(
+        mut self,
+        buffer: &'static mut [[u8; L]; 2],
+    ) -> CircTransfer<u8, L, Self> {
+        self.start(
+            buffer.as_mut_ptr() as u32,
+            CircTransfer::<u8, L, Self>::LEN,
+            true,
+        );
+
+        CircTransfer {
+            buffer,
+            payload: self,
+            next: Half::First,
+        }
+    }
+
+    pub fn release(self) -> (Rx<USART2>, Channel<5>) {
+        (self.rx, self.channel)
+    }
+
+    fn start(&mut self, memory: u32, len: usize, circular: bool) {
+        let usart = unsafe { &(*USART2::ptr()) };
+        unsafe {
+            self.channel.start::<u8>(
+                &usart.rdr as *const _ as u32,
+                memory,
+                len,
+                false,
+                true,
+                circular,
+            )
+        };
+        usart
+            .cr3
+            .modify(|r, w| unsafe { w.bits(r.bits() | USART_DMAR) });
+    }
+}
+
+impl TransferPayload for SerialRxDma {
+    fn channel(&self) -> u8 {
+        5
+    }
+
+    fn stop(&mut self) {
+        let usart = unsafe { &(*USART2::ptr()) };
+        usart
+            .cr3
+            .modify(|r, w| unsafe { w.bits(r.bits() & !USART_DMAR) });
+    }
+}
+
+// ADC register bits
+const ADC_ADEN: u32 = 1 << 0;
+const ADC_ADSTART: u32 = 1 << 2;
+const ADC_ADSTP: u32 = 1 << 4;
+const ADC_ADRDY: u32 = 1 << 0;
+const ADC_DMAEN: u32 = 1 << 0;
+const ADC_DMACFG: u32 = 1 << 1;
+const ADC_CONT: u32 = 1 << 13;
+
+/// ADC converting a sequence of channels continuously into a circular buffer
+///
+/// The ADC request is served by DMA channel `N`, either 1 or 2.
+pub struct AdcDma<const N: u8> {
+    adc: Adc,
+    channel: Channel<N>,
+}
+
+impl<const N: u8> AdcDma<N>
+where
+    Channel<N>: Request<AdcRequest>,
+{
+    /// Set up the ADC for DMA transfers
+    ///
+    /// `channels` are the ADC channel numbers to convert, e.g. 0 for PA0, 16 for the temperature
+    /// sensor and 17 for the internal reference. The ADC always converts them in ascending
+    /// order. The temperature sensor and reference need to be enabled via the HAL before.
+    pub fn new(adc: Adc, mut channel: Channel<N>, channels: &[u8]) -> Self {
+        let regs = unsafe { &(*ADC::ptr()) };
+
+        channel.select();
+
+        let mask = channels
+            .iter()
+            .fold(0u32, |mask, &ch| mask | 1 << u32::from(ch));
+        regs.chselr.write(|w| unsafe { w.bits(mask) });
+
+        Self { adc, channel }
+    }
+
+    /// Convert continuously, each half of the buffer should hold a whole number of sequences
+    pub fn circ_read<const L: usize>(
+        mut self,
+        buffer: &'static mut [[u16; L]; 2],
+    ) -> CircTransfer<u16, L, Self> {
+        let regs = unsafe { &(*ADC::ptr()) };
+
+        // Continuous conversions with DMA in circular mode
+        regs.cfgr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | ADC_DMAEN | ADC_DMACFG | ADC_CONT) });
+
+        if regs.cr.read().bits() & ADC_ADEN == 0 {
+            regs.isr.write(|w| unsafe { w.bits(ADC_ADRDY) });
+            regs.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | ADC_ADEN) });
+            while regs.isr.read().bits() & ADC_ADRDY == 0 {}
+        }
+
+        unsafe {
+            self.channel.start::<u16>(
+                &regs.dr as *const _ as u32,
+                buffer.as_mut_ptr() as u32,
+                CircTransfer::<u16, L, Self>::LEN,
+                false,
+                true,
+                true,
+            )
+        };
+
+        regs.cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | ADC_ADSTART) });
+
+        CircTransfer {
+            buffer,
+            payload: self,
+            next: Half::First,
+        }
+    }
+
+    /// Release the ADC and the DMA channel
+    pub fn release(self) -> (Adc, Channel<N>) {
+        (self.adc, self.channel)
+    }
+}
+
+impl<const N: u8> TransferPayload for AdcDma<N> {
+    fn channel(&self) -> u8 {
+        N
+    }
+
+    fn stop(&mut self) {
+        let regs = unsafe { &(*ADC::ptr()) };
+
+        if regs.cr.read().bits() & ADC_ADSTART != 0 {
+            regs.cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | ADC_ADSTP) });
+            while regs.cr.read().bits() & ADC_ADSTP != 0 {}
+        }
+
+        regs.cfgr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(ADC_DMAEN | ADC_DMACFG | ADC_CONT)) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_filled() {
+        assert_eq!(readable_half(Half::First, 0), Ok(None));
+        assert_eq!(readable_half(Half::Second, 0), Ok(None));
+    }
+
+    #[test]
+    fn next_half_filled() {
+        assert_eq!(readable_half(Half::First, HTIF), Ok(Some(Half::First)));
+        assert_eq!(readable_half(Half::Second, TCIF), Ok(Some(Half::Second)));
+    }
+
+    #[test]
+    fn other_half_filled_is_overrun() {
+        assert_eq!(readable_half(Half::First, TCIF), Err(Error::Overrun));
+        assert_eq!(readable_half(Half::Second, HTIF), Err(Error::Overrun));
+        assert_eq!(readable_half(Half::First, HTIF | TCIF), Err(Error::Overrun));
+    }
+
+    #[test]
+    fn transfer_error_wins() {
+        assert_eq!(
+            readable_half(Half::First, TEIF | HTIF),
+            Err(Error::TransferError)
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Quadrature encoders using the encoder mode of TIM2 or TIM3
+//!
+//! The timer counts both edges of both encoder signals on channel 1 and 2, i.e. four counts per
+//! line of the encoder. The count is extended to an `i64` position by accumulating the change of
+//! the counter whenever it passes zero, a third or two thirds of its range, which is signalled
+//! by interrupts. This works regardless of any jitter around the wrap-around. The timer interrupt
+//! has to call [`on_interrupt`]:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn TIM3() {
+//!     encoder::on_interrupt::<TIM3>();
+//! }
+//! ```
+//!
+//! An index pulse can reset the position via [`on_index`], which is meant to be called from a
+//! handler attached to the index pin with [`crate::exti`]. Velocity measurements need
+//! [`crate::time::Monotonic`] to be running, which occupies TIM2, so TIM3 is the natural choice
+//! for the encoder then.
+
+use crate::hal::{
+    rcc::Rcc,
+    stm32::{Interrupt, RCC, TIM2, TIM3},
+};
+
+use crate::pwm::{
+    read, write, Channel, ChannelPin, Instance, ARR, CCER, CCMR1, CNT, CR1, DIER, EGR, PSC, SMCR,
+    SR,
+};
+use crate::time::{Duration, Instant, Monotonic};
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+
+// Offsets of CCR3 and CCR4, the others are shared with `pwm`
+const CCR3: usize = 0x3c;
+const CCR4: usize = 0x40;
+
+// CR1 bits
+const CEN: u32 = 1 << 0;
+const URS: u32 = 1 << 2;
+const DIR: u32 = 1 << 4;
+
+// EGR, SR and DIER bits, the interrupt enables are at the same positions as the flags
+const UG: u32 = 1 << 0;
+const UIF: u32 = 1 << 0;
+const CC3IF: u32 = 1 << 3;
+const CC4IF: u32 = 1 << 4;
+
+// Both channels as inputs from their own pins, filtered over 8 timer clock cycles
+const CCMR1_INPUTS: u32 = 0b01 | 0b0011 << 4 | 0b01 << 8 | 0b0011 << 12;
+
+// CCER polarity of channel 1, which reverses the counting direction
+const CC1P: u32 = 1 << 1;
+
+// SMCR encoder mode 3, counting on both edges of TI1 and TI2
+const SMS_ENCODER_X4: u32 = 0b011;
+
+/// Direction the encoder is turning in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// What an index pulse does
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IndexMode {
+    /// Only count and record the pulses
+    Ignore,
+    /// Reset the position to 0 at the next pulse, e.g. for homing, then ignore the pulses
+    ResetOnce,
+    /// Reset the position to 0 at every pulse
+    ResetEvery,
+}
+
+/// A timer with encoder mode
+pub trait EncoderTimer: Instance {
+    #[doc(hidden)]
+    const INTERRUPT: Interrupt;
+    #[doc(hidden)]
+    const MAX_COUNT: u32;
+    #[doc(hidden)]
+    fn state() -> &'static Mutex<RefCell<State>>;
+}
+
+static TIM2_STATE: Mutex<RefCell<State>> = Mutex::new(RefCell::new(State::new()));
+static TIM3_STATE: Mutex<RefCell<State>> = Mutex::new(RefCell::new(State::new()));
+
+impl EncoderTimer for TIM2 {
+    const INTERRUPT: Interrupt = Interrupt::TIM2;
+    const MAX_COUNT: u32 = 0xffff_ffff;
+
+    fn state() -> &'static Mutex<RefCell<State>> {
+        &TIM2_STATE
+    }
+}
+
+impl EncoderTimer for TIM3 {
+    const INTERRUPT: Interrupt = Interrupt::TIM3;
+    const MAX_COUNT: u32 = 0xffff;
+
+    fn state() -> &'static Mutex<RefCell<State>> {
+        &TIM3_STATE
+    }
+}
+
+/// Position extension and index handling of an encoder timer
+#[doc(hidden)]
+pub struct State {
+    last: u32,
+    position: i64,
+    index_mode: IndexMode,
+    index_count: u32,
+    position_at_index: Option<i64>,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            last: 0,
+            position: 0,
+            index_mode: IndexMode::Ignore,
+            index_count: 0,
+            position_at_index: None,
+        }
+    }
+
+    /// Add the change of the counter since the last call
+    fn accumulate<TIM: EncoderTimer>(&mut self) {
+        let count = read(TIM::base(), CNT);
+        let delta = if TIM::MAX_COUNT == 0xffff {
+            i64::from((count as u16).wrapping_sub(self.last as u16) as i16)
+        } else {
+            i64::from(count.wrapping_sub(self.last) as i32)
+        };
+
+        self.last = count;
+        self.position += delta;
+    }
+}
+
+/// Estimates the velocity from the position change over a time window
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VelocityWindow<const N: usize> {
+    window: Duration,
+    samples: [(Instant, i64); N],
+    first: usize,
+    len: usize,
+    velocity: i32,
+}
+
+impl<const N: usize> VelocityWindow<N> {
+    /// Average over at least `window`, keeping up to `N` samples
+    ///
+    /// The window should be at least `N` times the interval the velocity is queried at, otherwise
+    /// samples are dropped before they leave the window and the average covers a shorter time.
+    pub const fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: [(Instant::from_micros(0), 0); N],
+            first: 0,
+            len: 0,
+            velocity: 0,
+        }
+    }
+
+    /// Add the `position` at `now` and return the velocity in counts per second
+    pub fn update(&mut self, now: Instant, position: i64) -> i32 {
+        // Keep the newest sample which is at least the window old as reference
+        while self.len >= 2 {
+            let (time, _) = self.samples[(self.first + 1) % N];
+            match now.checked_duration_since(time) {
+                Some(age) if age >= self.window => {
+                    self.first = (self.first + 1) % N;
+                    self.len -= 1;
+                }
+                _ => break,
+            }
+        }
+
+        if self.len > 0 {
+            let (time, reference) = self.samples[self.first];
+            let elapsed = now.checked_duration_since(time).unwrap_or(Duration::ZERO);
+            if elapsed > Duration::ZERO {
+                let velocity =
+                    (position - reference).saturating_mul(1_000_000) / elapsed.as_micros() as i64;
+                self.velocity = velocity.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32;
+            }
+        }
+
+        if self.len == N {
+            self.first = (self.first + 1) % N;
+            self.len -= 1;
+        }
+        self.samples[(self.first + self.len) % N] = (now, position);
+        self.len += 1;
+
+        self.velocity
+    }
+}
+
+/// A quadrature encoder on channel 1 and 2 of a timer
+pub struct Encoder<TIM, CH1, CH2> {
+    tim: TIM,
+    pins: (CH1, CH2),
+    velocity: VelocityWindow<16>,
+}
+
+impl<TIM, CH1, CH2> Encoder<TIM, CH1, CH2>
+where
+    TIM: EncoderTimer,
+    CH1: ChannelPin<TIM, Channel = Channel<TIM, 1>>,
+    CH2: ChannelPin<TIM, Channel = Channel<TIM, 2>>,
+{
+    /// Start counting the encoder on `pins` from position 0
+    ///
+    /// The interrupt of the timer is unmasked in the NVIC. The velocity is averaged over 100 ms.
+    pub fn new(tim: TIM, pins: (CH1, CH2), _rcc: &mut Rcc) -> Self {
+        let rccr = unsafe { &(*RCC::ptr()) };
+        TIM::enable(rccr);
+
+        let base = TIM::base();
+        write(base, CCMR1, CCMR1_INPUTS);
+        write(base, SMCR, SMS_ENCODER_X4);
+        write(base, PSC, 0);
+        write(base, ARR, TIM::MAX_COUNT);
+
+        // Interrupts at a third and two thirds of the range, additionally to the wrap-around
+        write(base, CCR3, TIM::MAX_COUNT / 3);
+        write(base, CCR4, TIM::MAX_COUNT / 3 * 2);
+
+        write(base, CR1, URS);
+        write(base, EGR, UG);
+        write(base, SR, 0);
+
+        cortex_m::interrupt::free(|cs| {
+            *TIM::state().borrow(cs).borrow_mut() = State::new();
+        });
+
+        write(base, DIER, UIF | CC3IF | CC4IF);
+        write(base, CR1, URS | CEN);
+
+        cortex_m::peripheral::NVIC::unpend(TIM::INTERRUPT);
+        unsafe {
+            cortex_m::peripheral::NVIC::unmask(TIM::INTERRUPT);
+        }
+
+        Self {
+            tim,
+            pins,
+            velocity: VelocityWindow::new(Duration::from_millis(100)),
+        }
+    }
+
+    /// Count in the opposite direction
+    pub fn with_inverted_direction(self) -> Self {
+        let base = TIM::base();
+        write(base, CCER, read(base, CCER) | CC1P);
+        self
+    }
+
+    /// Average the velocity over `window`
+    pub fn with_velocity_window(mut self, window: Duration) -> Self {
+        self.velocity = VelocityWindow::new(window);
+        self
+    }
+
+    /// The current position in counts, four per line of the encoder
+    pub fn position(&self) -> i64 {
+        cortex_m::interrupt::free(|cs| {
+            let mut state = TIM::state().borrow(cs).borrow_mut();
+            state.accumulate::<TIM>();
+            state.position
+        })
+    }
+
+    /// Change the current position to `position`
+    pub fn set_position(&mut self, position: i64) {
+        cortex_m::interrupt::free(|cs| {
+            let mut state = TIM::state().borrow(cs).borrow_mut();
+            state.accumulate::<TIM>();
+            state.position = position;
+        });
+    }
+
+    /// The direction of the last count
+    pub fn direction(&self) -> Direction {
+        if read(TIM::base(), CR1) & DIR == 0 {
+            Direction::Forward
+        } else {
+            Direction::Backward
+        }
+    }
+
+    /// The velocity in counts per second, averaged over the velocity window
+    ///
+    /// Each call adds a sample, so this should be called regularly, e.g. every 10 ms.
+    pub fn velocity(&mut self) -> i32 {
+        let position = self.position();
+        self.velocity.update(Monotonic::now(), position)
+    }
+
+    /// Select what an index pulse does
+    pub fn set_index_mode(&mut self, mode: IndexMode) {
+        cortex_m::interrupt::free(|cs| {
+            TIM::state().borrow(cs).borrow_mut().index_mode = mode;
+        });
+    }
+
+    /// Number of index pulses so far
+    pub fn index_count(&self) -> u32 {
+        cortex_m::interrupt::free(|cs| TIM::state().borrow(cs).borrow().index_count)
+    }
+
+    /// Position at the last index pulse, before any reset
+    ///
+    /// With [`IndexMode::ResetEvery`] this is the number of counts per revolution, deviations
+    /// point to missed counts.
+    pub fn position_at_index(&self) -> Option<i64> {
+        cortex_m::interrupt::free(|cs| TIM::state().borrow(cs).borrow().position_at_index)
+    }
+
+    /// Stop counting and release the timer and the pins
+    pub fn release(self) -> (TIM, (CH1, CH2)) {
+        cortex_m::peripheral::NVIC::mask(TIM::INTERRUPT);
+
+        let base = TIM::base();
+        write(base, CR1, 0);
+        write(base, DIER, 0);
+        write(base, SMCR, 0);
+        write(base, CCER, 0);
+
+        let rccr = unsafe { &(*RCC::ptr()) };
+        TIM::disable(rccr);
+
+        (self.tim, self.pins)
+    }
+}
+
+/// Extend the count, to be called from the interrupt handler of the timer
+pub fn on_interrupt<TIM: EncoderTimer>() {
+    let base = TIM::base();
+    write(base, SR, !(UIF | CC3IF | CC4IF));
+
+    cortex_m::interrupt::free(|cs| {
+        TIM::state().borrow(cs).borrow_mut().accumulate::<TIM>();
+    });
+}
+
+/// Handle an index pulse according to the [`IndexMode`], to be called from the EXTI handler of the
+/// index pin
+pub fn on_index<TIM: EncoderTimer>() {
+    cortex_m::interrupt::free(|cs| {
+        let mut state = TIM::state().borrow(cs).borrow_mut();
+        state.accumulate::<TIM>();
+
+        state.index_count = state.index_count.wrapping_add(1);
+        state.position_at_index = Some(state.position);
+
+        match state.index_mode {
+            IndexMode::Ignore => {}
+            IndexMode::ResetOnce => {
+                state.position = 0;
+                state.index_mode = IndexMode::Ignore;
+            }
+            IndexMode::ResetEvery => state.position = 0,
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(millis: u64) -> Instant {
+        Instant::from_micros(millis * 1000)
+    }
+
+    #[test]
+    fn constant_velocity() {
+        let mut velocity: VelocityWindow<4> = VelocityWindow::new(Duration::from_millis(100));
+
+        assert_eq!(velocity.update(at(0), 0), 0);
+        for step in 1..8 {
+            assert_eq!(velocity.update(at(step * 50), step as i64 * 50), 1_000);
+        }
+    }
+
+    #[test]
+    fn reverse_direction() {
+        let mut velocity: VelocityWindow<4> = VelocityWindow::new(Duration::from_millis(100));
+
+        velocity.update(at(0), 0);
+        assert_eq!(velocity.update(at(10), -10), -1_000);
+    }
+
+    #[test]
+    fn stopping_decays_to_zero() {
+        let mut velocity: VelocityWindow<4> = VelocityWindow::new(Duration::from_millis(100));
+
+        for step in 0..4 {
+            velocity.update(at(step * 50), step as i64 * 50);
+        }
+        assert_eq!(velocity.update(at(200), 150), 500);
+        assert_eq!(velocity.update(at(250), 150), 0);
+    }
+
+    #[test]
+    fn same_instant_keeps_velocity() {
+        let mut velocity: VelocityWindow<4> = VelocityWindow::new(Duration::from_millis(100));
+
+        velocity.update(at(5), 0);
+        assert_eq!(velocity.update(at(5), 100), 0);
+    }
+
+    #[test]
+    fn full_window_drops_oldest_sample() {
+        let mut velocity: VelocityWindow<2> = VelocityWindow::new(Duration::from_millis(100));
+
+        velocity.update(at(0), 0);
+        velocity.update(at(10), 10);
+        assert_eq!(velocity.update(at(20), 40), 2_000);
+        // The sample at 0 ms is gone, so this is relative to the one at 10 ms
+        assert_eq!(velocity.update(at(30), 50), 2_000);
+    }
+
+    #[test]
+    fn saturates() {
+        let mut velocity: VelocityWindow<2> = VelocityWindow::new(Duration::from_millis(100));
+
+        velocity.update(Instant::from_micros(0), 0);
+        assert_eq!(velocity.update(Instant::from_micros(1), 1 << 40), i32::MAX);
+    }
+}
@@ -0,0 +1,671 @@
+//! External interrupts on the header pins
+//!
+//! Each of the 16 EXTI lines can be connected to the pin with the same number on one of the ports,
+//! e.g. line 0 to either A0 (PA0) or D3 (PB0). [`Exti::new`] hands out one token per line and
+//! attaching a pin consumes the token of its line, so using two pins sharing a line is a compile
+//! time error.
+//!
+//! Handlers are plain functions called from the EXTI interrupts, which are unmasked in the NVIC as
+//! needed. All three interrupt handlers have to call [`Exti::on_interrupt`]:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn EXTI4_15() {
+//!     Exti::on_interrupt();
+//! }
+//! ```
+//!
+//! Debouncing relies on the monotonic clock in [`crate::time`], which needs to be running, and on
+//! [`Exti::poll`] being called periodically, which samples the pins once they have settled:
+//!
+//! ```ignore
+//! #[exception]
+//! fn SysTick() {
+//!     Exti::poll();
+//! }
+//! ```
+
+use crate::hal::{
+    gpio::{gpioa, gpiob, gpiof, Alternate, Input, AF1},
+    rcc::Rcc,
+    stm32::{Interrupt, EXTI, GPIOA, GPIOB, GPIOF, RCC, SYSCFG},
+};
+
+use crate::time::{Duration, Instant, Monotonic};
+
+use core::cell::RefCell;
+
+use cortex_m::{interrupt::Mutex, peripheral::NVIC};
+
+// SYSCFG clock enable
+const SYSCFGEN: u32 = 1 << 0;
+
+// Port numbers in the EXTICR registers
+const PORT_A: u8 = 0;
+const PORT_B: u8 = 1;
+const PORT_F: u8 = 5;
+
+/// Level of a pin
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Level {
+    Low,
+    High,
+}
+
+/// Condition calling the handler
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Trigger {
+    RisingEdge,
+    FallingEdge,
+    BothEdges,
+    /// Repeatedly, as long as the pin is high
+    HighLevel,
+    /// Repeatedly, as long as the pin is low
+    LowLevel,
+}
+
+/// Handler of an external interrupt, called with the level of the pin
+pub type Handler = fn(Level);
+
+/// Token of EXTI line `N`
+pub struct Line<const N: u8> {
+    _private: (),
+}
+
+impl<const N: u8> Line<N> {
+    // Lines 16 and up are connected to peripherals and not handed out by `Exti::new`
+    const INTERNAL: () = assert!(N >= 16, "GPIO lines are handed out by `Exti::new`");
+
+    /// Token of a line connected to a peripheral, e.g. line 17 to the RTC alarm
+    pub(crate) fn internal() -> Self {
+        let () = Self::INTERNAL;
+        Self { _private: () }
+    }
+}
+
+/// Tokens of all EXTI lines connected to GPIOs
+pub struct Lines {
+    pub line0: Line<0>,
+    pub line1: Line<1>,
+    pub line2: Line<2>,
+    pub line3: Line<3>,
+    pub line4: Line<4>,
+    pub line5: Line<5>,
+    pub line6: Line<6>,
+    pub line7: Line<7>,
+    pub line8: Line<8>,
+    pub line9: Line<9>,
+    pub line10: Line<10>,
+    pub line11: Line<11>,
+    pub line12: Line<12>,
+    pub line13: Line<13>,
+    pub line14: Line<14>,
+    pub line15: Line<15>,
+}
+
+/// A pin which can raise an external interrupt
+pub trait ExtiPin {
+    /// Token of the EXTI line the pin is connected to
+    type Line;
+    /// Number of the EXTI line
+    const LINE: u8;
+    /// Port of the pin as selected in the EXTICR registers
+    const PORT: u8;
+}
+
+macro_rules! exti_pins {
+    ($($gpio:ident::$PXi:ident: ($port:expr, $line:expr),)+) => {
+        $(
+            impl<MODE> ExtiPin for $gpio::$PXi<Input<MODE>> {
+                type Line = Line<$line>;
+                const LINE: u8 = $line;
+                const PORT: u8 = $port;
+            }
+        )+
+    };
+}
+
+// All pins on the headers
+exti_pins!(
+    gpioa::PA0: (PORT_A, 0),
+    gpioa::PA1: (PORT_A, 1),
+    gpioa::PA2: (PORT_A, 2),
+    gpioa::PA3: (PORT_A, 3),
+    gpioa::PA4: (PORT_A, 4),
+    gpioa::PA5: (PORT_A, 5),
+    gpioa::PA6: (PORT_A, 6),
+    gpioa::PA7: (PORT_A, 7),
+    gpioa::PA8: (PORT_A, 8),
+    gpioa::PA9: (PORT_A, 9),
+    gpioa::PA10: (PORT_A, 10),
+    gpioa::PA11: (PORT_A, 11),
+    gpioa::PA12: (PORT_A, 12),
+    gpiob::PB0: (PORT_B, 0),
+    gpiob::PB1: (PORT_B, 1),
+    gpiob::PB3: (PORT_B, 3),
+    gpiob::PB4: (PORT_B, 4),
+    gpiob::PB5: (PORT_B, 5),
+    gpiob::PB6: (PORT_B, 6),
+    gpiob::PB7: (PORT_B, 7),
+    gpiof::PF0: (PORT_F, 0),
+    gpiof::PF1: (PORT_F, 1),
+);
+
+// USART2 RX of the ST-Link virtual COM port, for waking up on received characters
+impl ExtiPin for gpioa::PA15<Alternate<AF1>> {
+    type Line = Line<15>;
+    const LINE: u8 = 15;
+    const PORT: u8 = PORT_A;
+}
+
+/// Filters out the bouncing of mechanical contacts
+///
+/// Every edge restarts the debounce window. Once the pin has been quiet for the whole window its
+/// level is sampled again and reported if it differs from the last accepted one, so glitches
+/// shorter than the window and contacts still bouncing are ignored.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Debouncer {
+    window: Duration,
+    stable: Level,
+    last_edge: Option<Instant>,
+}
+
+impl Debouncer {
+    /// Create a debouncer for a pin currently at `level`
+    pub const fn new(window: Duration, level: Level) -> Self {
+        Self {
+            window,
+            stable: level,
+            last_edge: None,
+        }
+    }
+
+    /// The level last accepted
+    pub fn level(&self) -> Level {
+        self.stable
+    }
+
+    /// Whether an edge occurred less than the window ago, i.e. [`Debouncer::poll`] is pending
+    pub fn is_settling(&self) -> bool {
+        self.last_edge.is_some()
+    }
+
+    /// The pin changed at `now`, restarting the window
+    pub fn edge(&mut self, now: Instant) {
+        self.last_edge = Some(now);
+    }
+
+    /// The pin is at `level` at `now`, returns the new level if the window has expired since the
+    /// last edge and the level differs from the one last accepted
+    pub fn poll(&mut self, now: Instant, level: Level) -> Option<Level> {
+        let last_edge = self.last_edge?;
+        match now.checked_duration_since(last_edge) {
+            Some(elapsed) if elapsed >= self.window => {}
+            _ => return None,
+        }
+
+        self.last_edge = None;
+        if level == self.stable {
+            return None;
+        }
+
+        self.stable = level;
+        Some(level)
+    }
+}
+
+/// Configuration of an attached line
+#[derive(Copy, Clone)]
+struct Slot {
+    port: u8,
+    trigger: Trigger,
+    handler: Handler,
+    debouncer: Option<Debouncer>,
+}
+
+static SLOTS: Mutex<RefCell<[Option<Slot>; 16]>> = Mutex::new(RefCell::new([None; 16]));
+
+/// The interrupt raised by EXTI `line`
+pub fn interrupt(line: u8) -> Interrupt {
+    match line {
+        0 | 1 => Interrupt::EXTI0_1,
+        2 | 3 => Interrupt::EXTI2_3,
+        _ => Interrupt::EXTI4_15,
+    }
+}
+
+/// Current level of the pin on `port` connected to `line`
+fn read_level(port: u8, line: u8) -> Level {
+    let idr = unsafe {
+        match port {
+            PORT_A => (*GPIOA::ptr()).idr.read().bits(),
+            PORT_B => (*GPIOB::ptr()).idr.read().bits(),
+            _ => (*GPIOF::ptr()).idr.read().bits(),
+        }
+    };
+
+    if idr & 1 << line != 0 {
+        Level::High
+    } else {
+        Level::Low
+    }
+}
+
+/// The EXTI controller routing GPIOs to interrupts
+pub struct Exti {
+    exti: EXTI,
+    syscfg: SYSCFG,
+}
+
+impl Exti {
+    /// Take over EXTI and SYSCFG, handing out the tokens of the GPIO lines
+    pub fn new(exti: EXTI, syscfg: SYSCFG, _rcc: &mut Rcc) -> (Self, Lines) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb2enr
+            .modify(|r, w| unsafe { w.bits(r.bits() | SYSCFGEN) });
+
+        let lines = Lines {
+            line0: Line { _private: () },
+            line1: Line { _private: () },
+            line2: Line { _private: () },
+            line3: Line { _private: () },
+            line4: Line { _private: () },
+            line5: Line { _private: () },
+            line6: Line { _private: () },
+            line7: Line { _private: () },
+            line8: Line { _private: () },
+            line9: Line { _private: () },
+            line10: Line { _private: () },
+            line11: Line { _private: () },
+            line12: Line { _private: () },
+            line13: Line { _private: () },
+            line14: Line { _private: () },
+            line15: Line { _private: () },
+        };
+
+        (Self { exti, syscfg }, lines)
+    }
+
+    /// Connect the EXTI line of `P` to the port of the pin, for drivers handling the line
+    /// themselves instead of attaching a handler
+    pub fn route<P: ExtiPin>(&mut self, _line: &P::Line) {
+        // Each EXTICR register holds the port selection of four lines
+        let shift = u32::from(P::LINE % 4) * 4;
+        let port = u32::from(P::PORT) << shift;
+        let mask = !(0xf << shift);
+        match P::LINE / 4 {
+            0 => self
+                .syscfg
+                .exticr1
+                .modify(|r, w| unsafe { w.bits(r.bits() & mask | port) }),
+            1 => self
+                .syscfg
+                .exticr2
+                .modify(|r, w| unsafe { w.bits(r.bits() & mask | port) }),
+            2 => self
+                .syscfg
+                .exticr3
+                .modify(|r, w| unsafe { w.bits(r.bits() & mask | port) }),
+            _ => self
+                .syscfg
+                .exticr4
+                .modify(|r, w| unsafe { w.bits(r.bits() & mask | port) }),
+        }
+    }
+
+    /// Select the edges triggering `line`, none disables it
+    pub fn set_edges<const N: u8>(&mut self, _line: &Line<N>, rising: bool, falling: bool) {
+        self.write_edges(N, rising, falling);
+    }
+
+    /// Let `line` raise its interrupt, which still has to be unmasked in the NVIC
+    pub fn enable_interrupt<const N: u8>(&mut self, _line: &Line<N>) {
+        self.write_interrupt(N, true);
+    }
+
+    /// Stop `line` from raising its interrupt
+    pub fn disable_interrupt<const N: u8>(&mut self, _line: &Line<N>) {
+        self.write_interrupt(N, false);
+    }
+
+    /// Let `line` generate an event, which wakes up WFE without an interrupt handler
+    pub fn enable_event<const N: u8>(&mut self, _line: &Line<N>) {
+        self.exti
+            .emr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << N) });
+    }
+
+    /// Stop `line` from generating events
+    pub fn disable_event<const N: u8>(&mut self, _line: &Line<N>) {
+        self.exti
+            .emr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << N)) });
+    }
+
+    /// Whether `line` has been triggered since it was last cleared
+    pub fn is_pending<const N: u8>(&self, _line: &Line<N>) -> bool {
+        self.exti.pr.read().bits() & 1 << N != 0
+    }
+
+    /// Clear the pending flag of `line`
+    pub fn clear_pending<const N: u8>(&mut self, _line: &Line<N>) {
+        self.write_pending(N);
+    }
+
+    /// Call `handler` from the EXTI interrupt whenever `trigger` occurs on `pin`
+    ///
+    /// The line is enabled right away and its interrupt unmasked in the NVIC.
+    pub fn attach<P: ExtiPin>(
+        &mut self,
+        pin: P,
+        line: P::Line,
+        trigger: Trigger,
+        handler: Handler,
+    ) -> ExtiInput<P> {
+        self.route::<P>(&line);
+        let token = line;
+        let line = P::LINE;
+
+        // Level triggers are emulated by re-pending the line while the level persists
+        let (rising, falling) = match trigger {
+            Trigger::RisingEdge | Trigger::HighLevel => (true, false),
+            Trigger::FallingEdge | Trigger::LowLevel => (false, true),
+            Trigger::BothEdges => (true, true),
+        };
+        self.write_edges(line, rising, falling);
+
+        cortex_m::interrupt::free(|cs| {
+            SLOTS.borrow(cs).borrow_mut()[usize::from(line)] = Some(Slot {
+                port: P::PORT,
+                trigger,
+                handler,
+                debouncer: None,
+            });
+        });
+
+        self.write_pending(line);
+        self.write_interrupt(line, true);
+
+        NVIC::unpend(interrupt(line));
+        unsafe {
+            NVIC::unmask(interrupt(line));
+        }
+
+        ExtiInput { pin, line: token }
+    }
+
+    /// Set the NVIC priority of the interrupt shared by EXTI `line` and its neighbours
+    pub fn set_priority(&mut self, nvic: &mut NVIC, line: u8, priority: u8) {
+        unsafe {
+            nvic.set_priority(interrupt(line), priority);
+        }
+    }
+
+    /// Release EXTI and SYSCFG, all pins have to be detached before
+    pub fn release(self) -> (EXTI, SYSCFG) {
+        (self.exti, self.syscfg)
+    }
+
+    /// Dispatch all pending lines to their handlers, to be called from the `EXTI0_1`, `EXTI2_3`
+    /// and `EXTI4_15` interrupt handlers
+    pub fn on_interrupt() {
+        let exti = unsafe { &(*EXTI::ptr()) };
+
+        for line in 0..16u8 {
+            let bit = 1 << line;
+            if exti.pr.read().bits() & exti.imr.read().bits() & bit == 0 {
+                continue;
+            }
+
+            // Lines handled by other drivers are left alone
+            let slot = cortex_m::interrupt::free(|cs| SLOTS.borrow(cs).borrow()[usize::from(line)]);
+            let slot = match slot {
+                Some(slot) => slot,
+                None => continue,
+            };
+
+            // Clear first, so an edge during the handler is not lost
+            exti.pr.write(|w| unsafe { w.bits(bit) });
+
+            // Debounced lines only restart their window here, the level is sampled by `poll`
+            if slot.debouncer.is_some() {
+                let now = Monotonic::now();
+                cortex_m::interrupt::free(|cs| {
+                    if let Some(debouncer) = SLOTS.borrow(cs).borrow_mut()[usize::from(line)]
+                        .as_mut()
+                        .and_then(|slot| slot.debouncer.as_mut())
+                    {
+                        debouncer.edge(now);
+                    }
+                });
+                continue;
+            }
+
+            let level = match slot.trigger {
+                Trigger::RisingEdge | Trigger::HighLevel => Level::High,
+                Trigger::FallingEdge | Trigger::LowLevel => Level::Low,
+                Trigger::BothEdges => read_level(slot.port, line),
+            };
+
+            (slot.handler)(level);
+
+            let repeat = match slot.trigger {
+                Trigger::HighLevel => read_level(slot.port, line) == Level::High,
+                Trigger::LowLevel => read_level(slot.port, line) == Level::Low,
+                _ => false,
+            };
+            if repeat {
+                exti.swier.write(|w| unsafe { w.bits(bit) });
+            }
+        }
+    }
+
+    /// Sample the debounced lines whose window has expired and call their handlers, to be called
+    /// periodically, e.g. from the `SysTick` handler
+    ///
+    /// The period should be well below the debounce window, as it delays the handler by up to one
+    /// period.
+    pub fn poll() {
+        let exti = unsafe { &(*EXTI::ptr()) };
+        let now = Monotonic::now();
+
+        for line in 0..16u8 {
+            if exti.imr.read().bits() & 1 << line == 0 {
+                continue;
+            }
+
+            let accepted = cortex_m::interrupt::free(|cs| {
+                let mut slots = SLOTS.borrow(cs).borrow_mut();
+                let slot = slots[usize::from(line)].as_mut()?;
+                let level = read_level(slot.port, line);
+                let accepted = slot.debouncer.as_mut()?.poll(now, level)?;
+                Some((slot.trigger, slot.handler, accepted))
+            });
+
+            let (trigger, handler, level) = match accepted {
+                Some(accepted) => accepted,
+                None => continue,
+            };
+            let wanted = match (trigger, level) {
+                (Trigger::RisingEdge, Level::High) => true,
+                (Trigger::FallingEdge, Level::Low) => true,
+                (Trigger::BothEdges, _) => true,
+                _ => false,
+            };
+            if wanted {
+                handler(level);
+            }
+        }
+    }
+
+    fn write_interrupt(&mut self, line: u8, enable: bool) {
+        let bit = 1 << line;
+        self.exti.imr.modify(|r, w| unsafe {
+            w.bits(if enable {
+                r.bits() | bit
+            } else {
+                r.bits() & !bit
+            })
+        });
+    }
+
+    fn write_pending(&mut self, line: u8) {
+        self.exti.pr.write(|w| unsafe { w.bits(1 << line) });
+    }
+
+    fn write_edges(&mut self, line: u8, rising: bool, falling: bool) {
+        let bit = 1 << line;
+        self.exti.rtsr.modify(|r, w| unsafe {
+            w.bits(if rising {
+                r.bits() | bit
+            } else {
+                r.bits() & !bit
+            })
+        });
+        self.exti.ftsr.modify(|r, w| unsafe {
+            w.bits(if falling {
+                r.bits() | bit
+            } else {
+                r.bits() & !bit
+            })
+        });
+    }
+}
+
+/// A pin attached to its EXTI line
+pub struct ExtiInput<P: ExtiPin> {
+    pin: P,
+    line: P::Line,
+}
+
+impl<P: ExtiPin> ExtiInput<P> {
+    /// Only report a change once the pin has been stable for `window`, for mechanical buttons
+    ///
+    /// Only edge triggers can be debounced. The line now triggers on both edges so every edge
+    /// restarts the window, the handler is called from [`Exti::poll`] and still only for the
+    /// requested edges.
+    pub fn with_debounce(self, exti: &mut Exti, window: Duration) -> Self {
+        let line = P::LINE;
+
+        let debounced = cortex_m::interrupt::free(|cs| {
+            match SLOTS.borrow(cs).borrow_mut()[usize::from(line)].as_mut() {
+                Some(slot)
+                    if slot.trigger != Trigger::HighLevel && slot.trigger != Trigger::LowLevel =>
+                {
+                    slot.debouncer = Some(Debouncer::new(window, read_level(P::PORT, line)));
+                    true
+                }
+                _ => false,
+            }
+        });
+
+        if debounced {
+            exti.write_edges(line, true, true);
+        }
+        self
+    }
+
+    /// The pin
+    pub fn pin(&self) -> &P {
+        &self.pin
+    }
+
+    /// Current level of the pin
+    pub fn level(&self) -> Level {
+        read_level(P::PORT, P::LINE)
+    }
+
+    /// Stop calling the handler, keeping the configuration
+    pub fn disable(&mut self, exti: &mut Exti) {
+        exti.write_interrupt(P::LINE, false);
+    }
+
+    /// Call the handler again, discarding events which occurred while disabled
+    pub fn enable(&mut self, exti: &mut Exti) {
+        exti.write_pending(P::LINE);
+        exti.write_interrupt(P::LINE, true);
+    }
+
+    /// Detach the handler and return the pin and the token of its line
+    ///
+    /// The interrupt is masked in the NVIC if no other line of it is in use.
+    pub fn detach(mut self, exti: &mut Exti) -> (P, P::Line) {
+        let line = P::LINE;
+
+        self.disable(exti);
+        exti.write_edges(line, false, false);
+
+        let in_use = cortex_m::interrupt::free(|cs| {
+            let mut slots = SLOTS.borrow(cs).borrow_mut();
+            slots[usize::from(line)] = None;
+            (0..16u8).any(|other| {
+                interrupt(other) == interrupt(line) && slots[usize::from(other)].is_some()
+            })
+        });
+        if !in_use {
+            NVIC::mask(interrupt(line));
+        }
+
+        (self.pin, self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(20);
+
+    fn at(millis: u64) -> Instant {
+        Instant::from_micros(millis * 1000)
+    }
+
+    #[test]
+    fn glitch_is_ignored() {
+        let mut debouncer = Debouncer::new(WINDOW, Level::High);
+
+        // A short low pulse, the pin is back high long before the window expires
+        debouncer.edge(at(0));
+        debouncer.edge(at(1));
+        assert_eq!(debouncer.poll(at(5), Level::High), None);
+        assert_eq!(debouncer.poll(at(25), Level::High), None);
+
+        assert_eq!(debouncer.level(), Level::High);
+        assert!(!debouncer.is_settling());
+    }
+
+    #[test]
+    fn press_is_reported_once_settled() {
+        let mut debouncer = Debouncer::new(WINDOW, Level::High);
+
+        // Bouncing for 3 ms before staying low
+        for millis in 0..4 {
+            debouncer.edge(at(100 + millis));
+        }
+        assert_eq!(debouncer.poll(at(110), Level::Low), None);
+        assert_eq!(debouncer.poll(at(122), Level::Low), None);
+        assert_eq!(debouncer.poll(at(123), Level::Low), Some(Level::Low));
+        assert_eq!(debouncer.poll(at(130), Level::Low), None);
+
+        assert_eq!(debouncer.level(), Level::Low);
+    }
+
+    #[test]
+    fn bouncing_restarts_window() {
+        let mut debouncer = Debouncer::new(WINDOW, Level::Low);
+
+        debouncer.edge(at(0));
+        debouncer.edge(at(15));
+        assert_eq!(debouncer.poll(at(20), Level::High), None);
+        assert!(debouncer.is_settling());
+        assert_eq!(debouncer.poll(at(35), Level::High), Some(Level::High));
+    }
+
+    #[test]
+    fn no_edge_no_change() {
+        let mut debouncer = Debouncer::new(WINDOW, Level::High);
+
+        assert_eq!(debouncer.poll(at(1000), Level::Low), None);
+        assert_eq!(debouncer.level(), Level::High);
+    }
+}
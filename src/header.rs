@@ -0,0 +1,133 @@
+//! Names of the pins on the Arduino Nano compatible headers of the board
+//!
+//! The aliases only rename the pin types of the HAL, e.g. `D2<Input<PullUp>>` is the same type as
+//! `PA12<Input<PullUp>>`. In the default solder bridge configuration A7 (PA2) is used by the
+//! ST-Link virtual COM port and A4/A5 are connected to D4/D5 (see the user manual UM1956).
+
+use crate::hal::gpio::{
+    gpioa::{PA0, PA1, PA10, PA11, PA12, PA2, PA3, PA4, PA5, PA6, PA7, PA8, PA9},
+    gpiob::{PB0, PB1, PB3, PB4, PB5, PB6, PB7},
+    gpiof::{PF0, PF1},
+};
+
+pub type D0<MODE> = PA10<MODE>;
+pub type D1<MODE> = PA9<MODE>;
+pub type D2<MODE> = PA12<MODE>;
+pub type D3<MODE> = PB0<MODE>;
+pub type D4<MODE> = PB7<MODE>;
+pub type D5<MODE> = PB6<MODE>;
+pub type D6<MODE> = PB1<MODE>;
+pub type D7<MODE> = PF0<MODE>;
+pub type D8<MODE> = PF1<MODE>;
+pub type D9<MODE> = PA8<MODE>;
+pub type D10<MODE> = PA11<MODE>;
+pub type D11<MODE> = PB5<MODE>;
+pub type D12<MODE> = PB4<MODE>;
+/// Also drives the user LED LD3
+pub type D13<MODE> = PB3<MODE>;
+
+pub type A0<MODE> = PA0<MODE>;
+pub type A1<MODE> = PA1<MODE>;
+pub type A2<MODE> = PA3<MODE>;
+pub type A3<MODE> = PA4<MODE>;
+pub type A4<MODE> = PA5<MODE>;
+pub type A5<MODE> = PA6<MODE>;
+pub type A6<MODE> = PA7<MODE>;
+pub type A7<MODE> = PA2<MODE>;
+
+/// A header pin selected at runtime, e.g. by name from a command line
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pin {
+    D0,
+    D1,
+    D2,
+    D3,
+    D4,
+    D5,
+    D6,
+    D7,
+    D8,
+    D9,
+    D10,
+    D11,
+    D12,
+    D13,
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    A6,
+    A7,
+}
+
+impl Pin {
+    /// All header pins
+    pub const ALL: [Pin; 22] = [
+        Pin::D0,
+        Pin::D1,
+        Pin::D2,
+        Pin::D3,
+        Pin::D4,
+        Pin::D5,
+        Pin::D6,
+        Pin::D7,
+        Pin::D8,
+        Pin::D9,
+        Pin::D10,
+        Pin::D11,
+        Pin::D12,
+        Pin::D13,
+        Pin::A0,
+        Pin::A1,
+        Pin::A2,
+        Pin::A3,
+        Pin::A4,
+        Pin::A5,
+        Pin::A6,
+        Pin::A7,
+    ];
+
+    /// The name printed on the board
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pin::D0 => "D0",
+            Pin::D1 => "D1",
+            Pin::D2 => "D2",
+            Pin::D3 => "D3",
+            Pin::D4 => "D4",
+            Pin::D5 => "D5",
+            Pin::D6 => "D6",
+            Pin::D7 => "D7",
+            Pin::D8 => "D8",
+            Pin::D9 => "D9",
+            Pin::D10 => "D10",
+            Pin::D11 => "D11",
+            Pin::D12 => "D12",
+            Pin::D13 => "D13",
+            Pin::A0 => "A0",
+            Pin::A1 => "A1",
+            Pin::A2 => "A2",
+            Pin::A3 => "A3",
+            Pin::A4 => "A4",
+            Pin::A5 => "A5",
+            Pin::A6 => "A6",
+            Pin::A7 => "A7",
+        }
+    }
+
+    /// Look up a pin by its name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|pin| pin.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl core::fmt::Display for Pin {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
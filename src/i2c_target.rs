@@ -0,0 +1,392 @@
+//! Interrupt driven I2C1 target (slave) mode with a register map abstraction
+//!
+//! Most I2C sensors and peripherals expose a set of byte wide registers: a write transfer starts
+//! with the register index followed by values to store, a read transfer returns the values
+//! starting at the index written last, the index incrementing with every byte. [`RegisterState`]
+//! implements this protocol independently of the hardware on top of a [`RegisterMap`], while
+//! [`I2cTarget`] feeds it from the I2C1 interrupt.
+
+use crate::hal::{i2c::I2c, stm32::I2C1};
+
+use crate::i2c_tools::{
+    ADDCODE_SHIFT, ADDR, ARLO, BERR, DIR, ICR_ALL, NACKF, OVR, PE, RXNE, STOPF, TXE, TXIS,
+};
+
+// ICR clear flag of ADDR
+const ADDRCF: u32 = 1 << 3;
+
+// CR1 fields
+const TXIE: u32 = 1 << 1;
+const RXIE: u32 = 1 << 2;
+const ADDRIE: u32 = 1 << 3;
+const NACKIE: u32 = 1 << 4;
+const STOPIE: u32 = 1 << 5;
+const ERRIE: u32 = 1 << 7;
+const NOSTRETCH: u32 = 1 << 17;
+const ALL_IE: u32 = TXIE | RXIE | ADDRIE | NACKIE | STOPIE | ERRIE;
+
+// OAR1 and OAR2 enable
+const OAEN: u32 = 1 << 15;
+
+/// Direction of a transfer, seen from the controller
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    /// The controller reads from us
+    Read,
+    /// The controller writes to us
+    Write,
+}
+
+/// Contents of an emulated device
+pub trait RegisterMap {
+    /// Value of `register`, to be sent to the controller addressing us as `address`
+    fn read(&mut self, address: u8, register: u8) -> u8;
+
+    /// Store `value` written by the controller into `register`
+    fn write(&mut self, address: u8, register: u8, value: u8);
+
+    /// Called when the controller starts a transfer to one of our addresses
+    fn address_match(&mut self, _address: u8, _direction: Direction) {}
+
+    /// Called at the end of a transfer
+    fn stop(&mut self, _address: u8) {}
+}
+
+/// A plain bank of registers, with writes beyond its end ignored and reads returning 0xFF
+impl<const N: usize> RegisterMap for [u8; N] {
+    fn read(&mut self, _address: u8, register: u8) -> u8 {
+        self.get(usize::from(register)).copied().unwrap_or(0xff)
+    }
+
+    fn write(&mut self, _address: u8, register: u8, value: u8) {
+        if let Some(slot) = self.get_mut(usize::from(register)) {
+            *slot = value;
+        }
+    }
+}
+
+/// Register protocol state machine, independent of the hardware
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RegisterState {
+    address: u8,
+    pointer: u8,
+    expect_pointer: bool,
+}
+
+impl Default for RegisterState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegisterState {
+    /// Create a state machine with the register index at 0
+    pub const fn new() -> Self {
+        Self {
+            address: 0,
+            pointer: 0,
+            expect_pointer: false,
+        }
+    }
+
+    /// Index of the register accessed next
+    pub fn pointer(&self) -> u8 {
+        self.pointer
+    }
+
+    /// A transfer to `address` starts; the first byte written is the register index
+    pub fn address_matched<M: RegisterMap>(
+        &mut self,
+        map: &mut M,
+        address: u8,
+        direction: Direction,
+    ) {
+        self.address = address;
+        self.expect_pointer = direction == Direction::Write;
+        map.address_match(address, direction);
+    }
+
+    /// A byte was received from the controller
+    pub fn received<M: RegisterMap>(&mut self, map: &mut M, byte: u8) {
+        if self.expect_pointer {
+            self.pointer = byte;
+            self.expect_pointer = false;
+        } else {
+            map.write(self.address, self.pointer, byte);
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+
+    /// The next byte to send to the controller
+    pub fn transmit<M: RegisterMap>(&mut self, map: &mut M) -> u8 {
+        let value = map.read(self.address, self.pointer);
+        self.pointer = self.pointer.wrapping_add(1);
+        value
+    }
+
+    /// The byte handed out last by [`RegisterState::transmit`] was not picked up by the
+    /// controller, so the next read continues with its register
+    ///
+    /// [`RegisterMap::read`] has been called for the register anyway.
+    pub fn unsent(&mut self) {
+        self.pointer = self.pointer.wrapping_sub(1);
+    }
+
+    /// The transfer ended with a stop condition or an error
+    pub fn stopped<M: RegisterMap>(&mut self, map: &mut M) {
+        self.expect_pointer = false;
+        map.stop(self.address);
+    }
+}
+
+/// I2C1 acting as target with one or two own 7-bit addresses
+pub struct I2cTarget<SCLPIN, SDAPIN, M> {
+    i2c: I2c<I2C1, SCLPIN, SDAPIN>,
+    map: M,
+    state: RegisterState,
+}
+
+impl<SCLPIN, SDAPIN, M: RegisterMap> I2cTarget<SCLPIN, SDAPIN, M> {
+    /// Turn a bus set up via [`I2c::i2c1`] into a target answering to `address`
+    ///
+    /// Clock stretching is enabled and the `I2C1` interrupt is unmasked in the NVIC.
+    pub fn new(i2c: I2c<I2C1, SCLPIN, SDAPIN>, address: u8, map: M) -> Self {
+        let mut target = Self {
+            i2c,
+            map,
+            state: RegisterState::new(),
+        };
+
+        target.configure(|regs| {
+            regs.oar1
+                .write(|w| unsafe { w.bits(OAEN | u32::from(address & 0x7f) << 1) });
+            regs.oar2.write(|w| unsafe { w.bits(0) });
+            regs.cr1
+                .modify(|r, w| unsafe { w.bits(r.bits() & !NOSTRETCH | ALL_IE) });
+        });
+
+        cortex_m::peripheral::NVIC::unpend(crate::hal::stm32::Interrupt::I2C1);
+        unsafe {
+            cortex_m::peripheral::NVIC::unmask(crate::hal::stm32::Interrupt::I2C1);
+        }
+
+        target
+    }
+
+    /// Answer to `address` as well
+    pub fn with_second_address(mut self, address: u8) -> Self {
+        self.configure(|regs| {
+            regs.oar2
+                .write(|w| unsafe { w.bits(OAEN | u32::from(address & 0x7f) << 1) });
+        });
+        self
+    }
+
+    /// Enable or disable clock stretching
+    ///
+    /// Without clock stretching the interrupt has to be served within a byte period, otherwise
+    /// received bytes are lost or stale data is sent.
+    pub fn set_clock_stretching(&mut self, enabled: bool) {
+        self.configure(|regs| {
+            regs.cr1.modify(|r, w| unsafe {
+                w.bits(if enabled {
+                    r.bits() & !NOSTRETCH
+                } else {
+                    r.bits() | NOSTRETCH
+                })
+            });
+        });
+    }
+
+    /// The emulated registers
+    pub fn map(&mut self) -> &mut M {
+        &mut self.map
+    }
+
+    /// Index of the register accessed next
+    pub fn pointer(&self) -> u8 {
+        self.state.pointer()
+    }
+
+    /// Stop acting as target and release the bus and the register map
+    pub fn release(mut self) -> (I2c<I2C1, SCLPIN, SDAPIN>, M) {
+        cortex_m::peripheral::NVIC::mask(crate::hal::stm32::Interrupt::I2C1);
+        self.configure(|regs| {
+            regs.cr1
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(ALL_IE | NOSTRETCH)) });
+            regs.oar1.write(|w| unsafe { w.bits(0) });
+            regs.oar2.write(|w| unsafe { w.bits(0) });
+        });
+        (self.i2c, self.map)
+    }
+
+    /// Handle the I2C1 interrupt, to be called from the `I2C1` interrupt handler
+    pub fn on_interrupt(&mut self) {
+        let regs = unsafe { &(*I2C1::ptr()) };
+        let isr = regs.isr.read().bits();
+
+        if isr & (BERR | ARLO | OVR) != 0 {
+            regs.icr.write(|w| unsafe { w.bits(ICR_ALL) });
+            self.flush_tx();
+            self.state.stopped(&mut self.map);
+            return;
+        }
+
+        if isr & RXNE != 0 {
+            let byte = regs.rxdr.read().bits() as u8;
+            self.state.received(&mut self.map, byte);
+        }
+
+        if isr & ADDR != 0 {
+            let address = ((isr >> ADDCODE_SHIFT) & 0x7f) as u8;
+            let direction = if isr & DIR != 0 {
+                // Don't send data left over from a previous transfer
+                self.flush_tx();
+                Direction::Read
+            } else {
+                Direction::Write
+            };
+            self.state
+                .address_matched(&mut self.map, address, direction);
+
+            // The clock is stretched until ADDR is cleared
+            regs.icr.write(|w| unsafe { w.bits(ADDRCF) });
+            return;
+        }
+
+        if isr & TXIS != 0 {
+            let value = self.state.transmit(&mut self.map);
+            regs.txdr.write(|w| unsafe { w.bits(u32::from(value)) });
+        }
+
+        if isr & NACKF != 0 {
+            // The controller is done reading, the byte still waiting in TXDR was not sent
+            if isr & TXE == 0 {
+                self.state.unsent();
+                self.flush_tx();
+            }
+            regs.icr.write(|w| w.nackcf().set_bit());
+        }
+
+        if isr & STOPF != 0 {
+            regs.icr.write(|w| w.stopcf().set_bit());
+            self.state.stopped(&mut self.map);
+        }
+    }
+
+    /// Discard the contents of TXDR
+    fn flush_tx(&mut self) {
+        let regs = unsafe { &(*I2C1::ptr()) };
+        regs.isr.write(|w| unsafe { w.bits(TXE) });
+    }
+
+    /// Change settings which require the peripheral to be disabled
+    fn configure(&mut self, f: impl FnOnce(&crate::hal::stm32::i2c1::RegisterBlock)) {
+        let regs = unsafe { &(*I2C1::ptr()) };
+
+        regs.cr1.modify(|r, w| unsafe { w.bits(r.bits() & !PE) });
+        f(regs);
+        regs.cr1.modify(|r, w| unsafe { w.bits(r.bits() | PE) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: u8 = 0x42;
+
+    fn write(state: &mut RegisterState, map: &mut [u8; 8], bytes: &[u8]) {
+        state.address_matched(map, ADDRESS, Direction::Write);
+        for &byte in bytes {
+            state.received(map, byte);
+        }
+        state.stopped(map);
+    }
+
+    fn read(state: &mut RegisterState, map: &mut [u8; 8], count: usize) -> Vec<u8> {
+        state.address_matched(map, ADDRESS, Direction::Read);
+        (0..count).map(|_| state.transmit(map)).collect()
+    }
+
+    #[test]
+    fn pointer_write_then_read() {
+        let mut map = [0, 1, 2, 3, 4, 5, 6, 7];
+        let mut state = RegisterState::new();
+
+        write(&mut state, &mut map, &[5]);
+        assert_eq!(state.pointer(), 5);
+        assert_eq!(map, [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        assert_eq!(read(&mut state, &mut map, 1), [5]);
+    }
+
+    #[test]
+    fn auto_increment() {
+        let mut map = [0; 8];
+        let mut state = RegisterState::new();
+
+        write(&mut state, &mut map, &[2, 0xa0, 0xa1, 0xa2]);
+        assert_eq!(map, [0, 0, 0xa0, 0xa1, 0xa2, 0, 0, 0]);
+        assert_eq!(state.pointer(), 5);
+
+        // A read continues after the last register written
+        write(&mut state, &mut map, &[1]);
+        assert_eq!(read(&mut state, &mut map, 3), [0, 0xa0, 0xa1]);
+        state.stopped(&mut map);
+        assert_eq!(read(&mut state, &mut map, 2), [0xa2, 0]);
+    }
+
+    #[test]
+    fn unsent_byte_is_read_again() {
+        let mut map = [0x10, 0x11, 0x12, 0x13, 0, 0, 0, 0];
+        let mut state = RegisterState::new();
+
+        // The controller NACKs after two bytes while the third waits in TXDR
+        write(&mut state, &mut map, &[0]);
+        assert_eq!(read(&mut state, &mut map, 3), [0x10, 0x11, 0x12]);
+        state.unsent();
+        state.stopped(&mut map);
+
+        assert_eq!(read(&mut state, &mut map, 2), [0x12, 0x13]);
+    }
+
+    #[test]
+    fn early_stop_keeps_pointer() {
+        let mut map = [0x10, 0x11, 0x12, 0x13, 0, 0, 0, 0];
+        let mut state = RegisterState::new();
+
+        // The controller stops before the first byte, which was already loaded into TXDR
+        write(&mut state, &mut map, &[3]);
+        assert_eq!(read(&mut state, &mut map, 1), [0x13]);
+        state.unsent();
+        state.stopped(&mut map);
+        assert_eq!(state.pointer(), 3);
+
+        // A write of the index only doesn't store anything
+        write(&mut state, &mut map, &[1]);
+        assert_eq!(map, [0x10, 0x11, 0x12, 0x13, 0, 0, 0, 0]);
+        assert_eq!(read(&mut state, &mut map, 1), [0x11]);
+    }
+
+    #[test]
+    fn out_of_range_access() {
+        let mut map = [0; 8];
+        let mut state = RegisterState::new();
+
+        write(&mut state, &mut map, &[6, 1, 2, 3, 4]);
+        assert_eq!(map, [0, 0, 0, 0, 0, 0, 1, 2]);
+
+        write(&mut state, &mut map, &[7]);
+        assert_eq!(read(&mut state, &mut map, 3), [2, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn pointer_wraps() {
+        let mut map = [0; 8];
+        let mut state = RegisterState::new();
+
+        write(&mut state, &mut map, &[0xff]);
+        assert_eq!(read(&mut state, &mut map, 2), [0xff, 0]);
+    }
+}
@@ -0,0 +1,255 @@
+//! I2C1 master which gives up instead of hanging on a misbehaving bus
+//!
+//! The blocking HAL driver polls its status flags without any limit, so a target holding SCL or
+//! SDA low stalls the firmware forever. [`TimeoutI2c`] uses the clock timeout detection of the
+//! peripheral for SCL held low, a polling limit for everything else and reports arbitration loss
+//! and bus errors. After a failed transfer the bus is freed via [`i2c_tools::recover`] if
+//! necessary and the peripheral is re-initialised, so the next transfer starts from a clean slate.
+//!
+//! [`i2c_tools::recover`]: crate::i2c_tools::recover
+
+use crate::hal::{
+    rcc::Clocks,
+    stm32::{I2C1, RCC},
+};
+
+use crate::i2c_tools::{
+    self, BoardI2c, BusState, ARLO, AUTOEND, BERR, ICR_ALL, NACKF, NBYTES_SHIFT, PE, RD_WRN,
+    RELOAD, RXNE, START, STOPF, TC, TCR, TIMEOUT, TXIS,
+};
+use crate::time::Duration;
+
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+// TIMEOUTR fields
+const TIMEOUTA_MASK: u32 = 0xfff;
+const TIMOUTEN: u32 = 1 << 15;
+
+// I2C1SW in RCC_CFGR3, selecting SYSCLK instead of HSI as I2C1 clock
+const I2C1SW: u32 = 1 << 4;
+const HSI: u32 = 8_000_000;
+
+// Number of status register polls before giving up on conditions not covered by the hardware
+// timeout, like SDA being held low
+const POLL_LIMIT: u32 = 1_000_000;
+
+/// Errors of the I2C master
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The target did not acknowledge its address or a data byte
+    Nack,
+    /// A start or stop condition was detected at an unexpected position
+    Bus,
+    /// Another master won the arbitration
+    ArbitrationLoss,
+    /// SCL was held low for longer than the configured timeout or the transfer did not progress
+    Timeout,
+}
+
+/// I2C1 master with timeout detection and automatic bus recovery
+pub struct TimeoutI2c {
+    i2c: BoardI2c,
+    clocks: Clocks,
+}
+
+impl TimeoutI2c {
+    /// Take over a bus set up via [`I2c::i2c1`] and detect SCL being held low for `timeout`
+    ///
+    /// Depending on the clock of I2C1 the timeout is limited to about 1 s at 8 MHz and 170 ms at
+    /// 48 MHz.
+    ///
+    /// [`I2c::i2c1`]: crate::hal::i2c::I2c::i2c1
+    pub fn new(i2c: BoardI2c, clocks: &Clocks, timeout: Duration) -> Self {
+        let regs = unsafe { &(*I2C1::ptr()) };
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        let kernel_clock = if rcc.cfgr3.read().bits() & I2C1SW != 0 {
+            clocks.sysclk().0
+        } else {
+            HSI
+        };
+
+        // The timeout is counted in units of 2048 kernel clock cycles
+        let ticks = timeout.as_micros() * u64::from(kernel_clock) / 1_000_000 / 2048;
+        let timeouta = (ticks.saturating_sub(1) as u32).min(TIMEOUTA_MASK);
+
+        // TIMEOUTA can only be changed while the detection is disabled
+        regs.timeoutr.write(|w| unsafe { w.bits(timeouta) });
+        regs.timeoutr
+            .write(|w| unsafe { w.bits(timeouta | TIMOUTEN) });
+
+        Self {
+            i2c,
+            clocks: *clocks,
+        }
+    }
+
+    /// Release the bus, disabling the timeout detection
+    pub fn release(self) -> BoardI2c {
+        let regs = unsafe { &(*I2C1::ptr()) };
+        regs.timeoutr.write(|w| unsafe { w.bits(0) });
+        self.i2c
+    }
+
+    /// Wait until `flag` is set, bailing out on errors
+    fn wait(&mut self, flag: u32) -> Result<(), Error> {
+        let regs = unsafe { &(*I2C1::ptr()) };
+
+        for _ in 0..POLL_LIMIT {
+            let isr = regs.isr.read().bits();
+
+            if isr & ARLO != 0 {
+                return Err(Error::ArbitrationLoss);
+            } else if isr & BERR != 0 {
+                return Err(Error::Bus);
+            } else if isr & TIMEOUT != 0 {
+                return Err(Error::Timeout);
+            } else if isr & NACKF != 0 {
+                return Err(Error::Nack);
+            } else if isr & flag != 0 {
+                return Ok(());
+            }
+        }
+
+        Err(Error::Timeout)
+    }
+
+    /// Set up the next chunk of at most 255 bytes, `stop` selecting a stop condition at the end
+    fn chunk(&mut self, address: u8, remaining: usize, read: bool, start: bool, stop: bool) {
+        let regs = unsafe { &(*I2C1::ptr()) };
+
+        let mut cr2 = u32::from(address) << 1 | (remaining.min(255) as u32) << NBYTES_SHIFT;
+        if read {
+            cr2 |= RD_WRN;
+        }
+        if start {
+            cr2 |= START;
+        }
+        if remaining > 255 {
+            cr2 |= RELOAD;
+        } else if stop {
+            cr2 |= AUTOEND;
+        }
+
+        regs.cr2.write(|w| unsafe { w.bits(cr2) });
+    }
+
+    /// Write `bytes`, either ending with a stop condition or ready for a repeated start
+    fn write_bytes(&mut self, address: u8, bytes: &[u8], stop: bool) -> Result<(), Error> {
+        let regs = unsafe { &(*I2C1::ptr()) };
+
+        self.chunk(address, bytes.len(), false, true, stop);
+
+        for (i, &byte) in bytes.iter().enumerate() {
+            if i > 0 && i % 255 == 0 {
+                self.wait(TCR)?;
+                self.chunk(address, bytes.len() - i, false, false, stop);
+            }
+            self.wait(TXIS)?;
+            regs.txdr.write(|w| unsafe { w.bits(u32::from(byte)) });
+        }
+
+        self.finish(stop)
+    }
+
+    /// Read into `buffer`, ending with a stop condition
+    fn read_bytes(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        let regs = unsafe { &(*I2C1::ptr()) };
+
+        let len = buffer.len();
+        self.chunk(address, len, true, true, true);
+
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            if i > 0 && i % 255 == 0 {
+                self.wait(TCR)?;
+                self.chunk(address, len - i, true, false, true);
+            }
+            self.wait(RXNE)?;
+            *byte = regs.rxdr.read().bits() as u8;
+        }
+
+        self.finish(true)
+    }
+
+    /// Wait for the end of the transfer
+    fn finish(&mut self, stop: bool) -> Result<(), Error> {
+        let regs = unsafe { &(*I2C1::ptr()) };
+
+        if stop {
+            self.wait(STOPF)?;
+            regs.icr.write(|w| unsafe { w.bits(ICR_ALL) });
+            Ok(())
+        } else {
+            self.wait(TC)
+        }
+    }
+
+    /// Run a transfer, cleaning up after any failure
+    fn run(&mut self, f: impl FnOnce(&mut Self) -> Result<(), Error>) -> Result<(), Error> {
+        let regs = unsafe { &(*I2C1::ptr()) };
+        regs.icr.write(|w| unsafe { w.bits(ICR_ALL) });
+
+        let res = f(self);
+        if let Err(error) = res {
+            self.abort(error);
+        }
+        res
+    }
+
+    /// Bring the bus and the peripheral back into a usable state after `error`
+    fn abort(&mut self, error: Error) {
+        let regs = unsafe { &(*I2C1::ptr()) };
+
+        if error == Error::Nack {
+            // After a NACK the stop condition is generated by the hardware
+            for _ in 0..POLL_LIMIT {
+                if regs.isr.read().bits() & STOPF != 0 {
+                    break;
+                }
+            }
+        }
+
+        // Toggling PE resets the state machine and all flags while keeping the configuration
+        regs.cr1.modify(|r, w| unsafe { w.bits(r.bits() & !PE) });
+        if i2c_tools::bus_state(&self.i2c) != BusState::Idle {
+            i2c_tools::recover(&mut self.i2c, &self.clocks);
+        }
+        regs.cr1.modify(|r, w| unsafe { w.bits(r.bits() | PE) });
+        regs.icr.write(|w| unsafe { w.bits(ICR_ALL) });
+    }
+}
+
+impl Write for TimeoutI2c {
+    type Error = Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.run(|i2c| i2c.write_bytes(address, bytes, true))
+    }
+}
+
+impl Read for TimeoutI2c {
+    type Error = Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.run(|i2c| i2c.read_bytes(address, buffer))
+    }
+}
+
+impl WriteRead for TimeoutI2c {
+    type Error = Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.run(|i2c| {
+            i2c.write_bytes(address, bytes, buffer.is_empty())?;
+            if !buffer.is_empty() {
+                i2c.read_bytes(address, buffer)?;
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,371 @@
+//! Scanning and diagnostics of the I2C1 bus at PF1 (SCL) and PF0 (SDA)
+//!
+//! Addresses are probed the same way as `i2cdetect` does by default: a read of a single byte for
+//! the ranges commonly used by EEPROMs, which might interpret a write as the start of a write
+//! cycle, and a write without any data everywhere else. The reserved addresses 0x00-0x07 and
+//! 0x78-0x7F are skipped.
+
+use crate::hal::{
+    gpio::{gpiof::PF0, gpiof::PF1, Alternate, AF1},
+    i2c::I2c,
+    rcc::Clocks,
+    stm32::{GPIOF, I2C1},
+};
+
+use core::fmt;
+
+/// I2C1 on the pins of the Nucleo board
+pub type BoardI2c = I2c<I2C1, PF1<Alternate<AF1>>, PF0<Alternate<AF1>>>;
+
+// ISR flags, shared with the other I2C drivers
+pub(crate) const TXE: u32 = 1 << 0;
+pub(crate) const TXIS: u32 = 1 << 1;
+pub(crate) const RXNE: u32 = 1 << 2;
+pub(crate) const ADDR: u32 = 1 << 3;
+pub(crate) const NACKF: u32 = 1 << 4;
+pub(crate) const STOPF: u32 = 1 << 5;
+pub(crate) const TC: u32 = 1 << 6;
+pub(crate) const TCR: u32 = 1 << 7;
+pub(crate) const BERR: u32 = 1 << 8;
+pub(crate) const ARLO: u32 = 1 << 9;
+pub(crate) const OVR: u32 = 1 << 10;
+pub(crate) const TIMEOUT: u32 = 1 << 12;
+pub(crate) const DIR: u32 = 1 << 16;
+pub(crate) const ADDCODE_SHIFT: u32 = 17;
+
+// All ICR clear flags
+pub(crate) const ICR_ALL: u32 = 0x3f38;
+
+// CR1 and CR2 fields
+pub(crate) const PE: u32 = 1 << 0;
+pub(crate) const RD_WRN: u32 = 1 << 10;
+pub(crate) const START: u32 = 1 << 13;
+pub(crate) const NBYTES_SHIFT: u32 = 16;
+pub(crate) const RELOAD: u32 = 1 << 24;
+pub(crate) const AUTOEND: u32 = 1 << 25;
+
+// GPIOF pins
+const SDA: u32 = 1 << 0;
+const SCL: u32 = 1 << 1;
+
+// Number of status register polls before a probe is considered stuck
+const POLL_LIMIT: u32 = 100_000;
+
+/// Diagnostic errors
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// SDA is held low, typically by a target which was interrupted in the middle of a transfer
+    SdaStuckLow,
+    /// SCL is held low, e.g. due to a missing pull-up or a target stretching the clock forever
+    SclStuckLow,
+    /// A start or stop condition was detected at an unexpected position
+    Bus,
+    /// Another master won the arbitration
+    ArbitrationLoss,
+    /// A probe did not finish in time
+    Timeout,
+}
+
+/// Level of the bus lines while idle
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BusState {
+    /// Both lines are released
+    Idle,
+    /// SDA is held low
+    SdaStuckLow,
+    /// SCL is held low
+    SclStuckLow,
+}
+
+/// Method used to probe an address
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Probe {
+    /// Address with write direction followed by a stop condition
+    QuickWrite,
+    /// Read of a single byte
+    ReadByte,
+}
+
+impl Probe {
+    /// Method to probe `address` with, `None` for reserved addresses
+    pub fn for_address(address: u8) -> Option<Self> {
+        match address {
+            0x00..=0x07 | 0x78..=0x7f => None,
+            0x30..=0x37 | 0x50..=0x5f => Some(Probe::ReadByte),
+            _ => Some(Probe::QuickWrite),
+        }
+    }
+}
+
+/// Outcome of probing a single address
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Status {
+    /// The address was acknowledged
+    Present,
+    /// Nobody answered
+    Absent,
+    /// The address is reserved and was not probed
+    Skipped,
+}
+
+/// Result of a bus scan
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ScanResult {
+    present: u128,
+}
+
+impl ScanResult {
+    /// Status of `address`
+    pub fn status(&self, address: u8) -> Status {
+        if Probe::for_address(address).is_none() {
+            Status::Skipped
+        } else if self.is_present(address) {
+            Status::Present
+        } else {
+            Status::Absent
+        }
+    }
+
+    /// Whether a device acknowledged `address`
+    pub fn is_present(&self, address: u8) -> bool {
+        address < 0x80 && self.present & (1 << address) != 0
+    }
+
+    /// Number of devices found
+    pub fn count(&self) -> u32 {
+        self.present.count_ones()
+    }
+
+    /// Addresses of all devices found in ascending order
+    pub fn devices(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..0x80).filter(move |&address| self.is_present(address))
+    }
+}
+
+/// Renders the result as table in the style of `i2cdetect`, with `\r\n` line endings for terminals
+impl fmt::Display for ScanResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("     0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f\r\n")?;
+
+        for row in 0..8u8 {
+            write!(f, "{:02x}:", row << 4)?;
+            for address in (row << 4)..(row << 4) + 16 {
+                match self.status(address) {
+                    Status::Present => write!(f, " {:02x}", address)?,
+                    Status::Absent => f.write_str(" --")?,
+                    Status::Skipped => f.write_str("   ")?,
+                }
+            }
+            f.write_str("\r\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Level of the bus lines, which should both be high while the bus is idle
+pub fn bus_state(_i2c: &BoardI2c) -> BusState {
+    let idr = unsafe { &(*GPIOF::ptr()) }.idr.read().bits();
+
+    if idr & SCL == 0 {
+        BusState::SclStuckLow
+    } else if idr & SDA == 0 {
+        BusState::SdaStuckLow
+    } else {
+        BusState::Idle
+    }
+}
+
+/// Probe all non-reserved addresses
+///
+/// The bus state is checked first, so a stuck bus is reported instead of a bus full of devices
+/// or timeouts.
+pub fn scan(i2c: &mut BoardI2c) -> Result<ScanResult, Error> {
+    match bus_state(i2c) {
+        BusState::Idle => {}
+        BusState::SdaStuckLow => return Err(Error::SdaStuckLow),
+        BusState::SclStuckLow => return Err(Error::SclStuckLow),
+    }
+
+    let mut result = ScanResult::default();
+    for address in 0..0x80 {
+        if let Some(method) = Probe::for_address(address) {
+            if probe(i2c, address, method)? {
+                result.present |= 1 << address;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Probe a single address with the given method, returning whether it was acknowledged
+pub fn probe(_i2c: &mut BoardI2c, address: u8, method: Probe) -> Result<bool, Error> {
+    let i2c = unsafe { &(*I2C1::ptr()) };
+
+    i2c.icr.write(|w| unsafe { w.bits(ICR_ALL) });
+
+    let mut cr2 = u32::from(address & 0x7f) << 1 | AUTOEND | START;
+    if method == Probe::ReadByte {
+        cr2 |= RD_WRN | 1 << NBYTES_SHIFT;
+    }
+    i2c.cr2.write(|w| unsafe { w.bits(cr2) });
+
+    // With AUTOEND the stop condition is generated after the last byte or a NACK
+    let mut polls = 0;
+    let isr = loop {
+        let isr = i2c.isr.read().bits();
+
+        if isr & RXNE != 0 {
+            let _ = i2c.rxdr.read();
+        }
+
+        if isr & (STOPF | BERR | ARLO) != 0 {
+            break isr;
+        }
+
+        polls += 1;
+        if polls == POLL_LIMIT {
+            reset_peripheral();
+            return Err(Error::Timeout);
+        }
+    };
+
+    i2c.icr.write(|w| unsafe { w.bits(ICR_ALL) });
+
+    if isr & ARLO != 0 {
+        Err(Error::ArbitrationLoss)
+    } else if isr & BERR != 0 {
+        Err(Error::Bus)
+    } else {
+        Ok(isr & NACKF == 0)
+    }
+}
+
+/// Free a stuck bus by clocking out up to 9 bits followed by a stop condition
+///
+/// This releases targets which were interrupted in the middle of a byte, e.g. by a reset of the
+/// MCU, and hold SDA low waiting for the remaining clocks. The pins are driven open-drain as
+/// GPIOs meanwhile and handed back to I2C1 afterwards, whose state machine is reset as well.
+pub fn recover(i2c: &mut BoardI2c, clocks: &Clocks) -> BusState {
+    let gpiof = unsafe { &(*GPIOF::ptr()) };
+    let regs = unsafe { &(*I2C1::ptr()) };
+
+    // Clock at roughly 100 kHz
+    let half_period = (clocks.sysclk().0 / 200_000).max(1);
+    let pause = || cortex_m::asm::delay(half_period);
+
+    regs.cr1.modify(|r, w| unsafe { w.bits(r.bits() & !PE) });
+
+    let moder = gpiof.moder.read().bits();
+    let otyper = gpiof.otyper.read().bits();
+
+    // Open-drain outputs, released
+    gpiof.bsrr.write(|w| unsafe { w.bits(SDA | SCL) });
+    gpiof
+        .otyper
+        .write(|w| unsafe { w.bits(otyper | SDA | SCL) });
+    gpiof
+        .moder
+        .write(|w| unsafe { w.bits(moder & !0b1111 | 0b0101) });
+
+    let sda_high = || gpiof.idr.read().bits() & SDA != 0;
+    let release_scl = || {
+        gpiof.bsrr.write(|w| unsafe { w.bits(SCL) });
+        // Let targets stretch the clock, but not forever
+        for _ in 0..POLL_LIMIT {
+            if gpiof.idr.read().bits() & SCL != 0 {
+                break;
+            }
+        }
+    };
+
+    for _ in 0..9 {
+        if sda_high() {
+            break;
+        }
+        gpiof.brr.write(|w| unsafe { w.bits(SCL) });
+        pause();
+        release_scl();
+        pause();
+    }
+
+    // Stop condition: SDA rising while SCL is high
+    gpiof.brr.write(|w| unsafe { w.bits(SCL) });
+    pause();
+    gpiof.brr.write(|w| unsafe { w.bits(SDA) });
+    pause();
+    release_scl();
+    pause();
+    gpiof.bsrr.write(|w| unsafe { w.bits(SDA) });
+    pause();
+
+    gpiof.moder.write(|w| unsafe { w.bits(moder) });
+    gpiof.otyper.write(|w| unsafe { w.bits(otyper) });
+
+    regs.cr1.modify(|r, w| unsafe { w.bits(r.bits() | PE) });
+
+    bus_state(i2c)
+}
+
+/// Reset the state machine of I2C1 by toggling PE, keeping the configuration
+fn reset_peripheral() {
+    let i2c = unsafe { &(*I2C1::ptr()) };
+
+    i2c.cr1.modify(|r, w| unsafe { w.bits(r.bits() & !PE) });
+    // PE has to stay low for at least 3 APB cycles
+    cortex_m::asm::delay(3);
+    i2c.cr1.modify(|r, w| unsafe { w.bits(r.bits() | PE) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(addresses: &[u8]) -> ScanResult {
+        ScanResult {
+            present: addresses.iter().fold(0, |present, &a| present | 1 << a),
+        }
+    }
+
+    #[test]
+    fn status_of_addresses() {
+        let result = result(&[0x3c, 0x40]);
+
+        assert_eq!(result.status(0x3c), Status::Present);
+        assert_eq!(result.status(0x41), Status::Absent);
+        assert_eq!(result.status(0x03), Status::Skipped);
+        assert_eq!(result.status(0x7f), Status::Skipped);
+        assert_eq!(result.count(), 2);
+        assert_eq!(result.devices().collect::<Vec<_>>(), [0x3c, 0x40]);
+    }
+
+    #[test]
+    fn renders_like_i2cdetect() {
+        let table = format!("{}", result(&[0x3c, 0x40]));
+        let rows: Vec<&str> = table.split("\r\n").collect();
+
+        assert_eq!(rows.len(), 10);
+        assert_eq!(
+            rows[0],
+            "     0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f"
+        );
+        assert_eq!(
+            rows[1],
+            "00:                         -- -- -- -- -- -- -- --"
+        );
+        assert_eq!(
+            rows[4],
+            "30: -- -- -- -- -- -- -- -- -- -- -- -- 3c -- -- --"
+        );
+        assert_eq!(
+            rows[5],
+            "40: 40 -- -- -- -- -- -- -- -- -- -- -- -- -- -- --"
+        );
+        assert_eq!(
+            rows[8],
+            "70: -- -- -- -- -- -- -- --                        "
+        );
+        assert_eq!(rows[9], "");
+    }
+}
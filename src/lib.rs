@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(non_camel_case_types)]
 
 pub use stm32f0xx_hal as hal;
@@ -9,3 +9,39 @@ pub use crate::hal::stm32::*;
 pub use crate::hal::*;
 pub use cortex_m::*;
 pub use cortex_m_rt::*;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod capture;
+pub mod display;
+pub mod dma;
+pub mod encoder;
+pub mod exti;
+pub mod header;
+pub mod i2c_target;
+pub mod i2c_timeout;
+pub mod i2c_tools;
+#[cfg(feature = "rtic")]
+pub mod monotonic;
+pub mod power;
+pub mod pwm;
+pub mod rtc;
+pub mod servo;
+pub mod seven_segment;
+pub mod shared_bus;
+pub mod shell;
+pub mod signature;
+pub mod time;
+pub mod watchdog;
+#[cfg(feature = "smart-leds-trait")]
+pub mod ws2812;
+pub mod wwdg;
+
+/// Frequency of the clock feeding the timers, which runs at twice PCLK if the APB is prescaled
+pub(crate) fn timer_clock(clocks: &crate::hal::rcc::Clocks) -> u32 {
+    if clocks.hclk().0 == clocks.pclk().0 {
+        clocks.pclk().0
+    } else {
+        clocks.pclk().0 * 2
+    }
+}
@@ -0,0 +1,105 @@
+//! RTIC monotonic timer
+//!
+//! [`MonoTimer`] implements [`rtic_monotonic::Monotonic`] on top of the 32-bit TIM2, counting at
+//! 1 MHz and using capture/compare channel 1 for the timer queue deadlines. The counter wraps
+//! after about 71 minutes which is handled by the wrapping aware comparisons of the `fugit` types,
+//! so deadlines up to 35 minutes into the future can be scheduled.
+//!
+//! ```ignore
+//! #[monotonic(binds = TIM2, default = true)]
+//! type Mono = nucleo_f042k6::monotonic::MonoTimer;
+//! ```
+//!
+//! TIM2 is also used by [`crate::time::Monotonic`], only one of them can be in use at a time.
+
+use crate::hal::{
+    rcc::Rcc,
+    stm32::{RCC, TIM2},
+};
+
+use rtic_monotonic::Monotonic;
+
+/// Tick rate of the monotonic timer
+pub const TICK_HZ: u32 = 1_000_000;
+
+/// A point in time of [`MonoTimer`]
+pub type Instant = fugit::TimerInstantU32<TICK_HZ>;
+
+/// A span of time of [`MonoTimer`]
+pub type Duration = fugit::TimerDurationU32<TICK_HZ>;
+
+/// Monotonic timer for RTIC based on TIM2
+pub struct MonoTimer {
+    tim: TIM2,
+}
+
+impl MonoTimer {
+    /// Configure TIM2 as free running 1 MHz counter
+    ///
+    /// The timer clock needs to be a multiple of 1 MHz, which is the case for all the usual
+    /// system clock settings.
+    pub fn new(tim: TIM2, rcc: &mut Rcc) -> Self {
+        let tim_clk = crate::timer_clock(&rcc.clocks);
+        let rccr = unsafe { &(*RCC::ptr()) };
+
+        rccr.apb1enr.modify(|_, w| w.tim2en().set_bit());
+        rccr.apb1rstr.modify(|_, w| w.tim2rst().set_bit());
+        rccr.apb1rstr.modify(|_, w| w.tim2rst().clear_bit());
+
+        tim.psc.write(|w| unsafe { w.bits(tim_clk / TICK_HZ - 1) });
+        tim.arr.write(|w| unsafe { w.bits(0xffff_ffff) });
+
+        // Load the prescaler without raising an update interrupt
+        tim.cr1.modify(|_, w| w.urs().set_bit());
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.write(|w| unsafe { w.bits(0) });
+
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+
+        Self { tim }
+    }
+
+    /// Stop the timer and release it
+    pub fn release(self) -> TIM2 {
+        self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+        self.tim.dier.modify(|_, w| w.cc1ie().clear_bit());
+        self.tim
+    }
+}
+
+impl Monotonic for MonoTimer {
+    type Instant = Instant;
+    type Duration = Duration;
+
+    fn now(&mut self) -> Self::Instant {
+        Instant::from_ticks(self.tim.cnt.read().bits())
+    }
+
+    fn set_compare(&mut self, instant: Self::Instant) {
+        self.tim
+            .ccr1
+            .write(|w| unsafe { w.bits(instant.duration_since_epoch().ticks()) });
+    }
+
+    fn clear_compare_flag(&mut self) {
+        self.tim.sr.modify(|_, w| w.cc1if().clear_bit());
+    }
+
+    fn zero() -> Self::Instant {
+        Instant::from_ticks(0)
+    }
+
+    unsafe fn reset(&mut self) {
+        self.tim.cnt.write(|w| w.bits(0));
+        self.tim.sr.modify(|_, w| w.cc1if().clear_bit());
+        self.tim.dier.modify(|_, w| w.cc1ie().set_bit());
+    }
+
+    fn enable_timer(&mut self) {
+        self.tim.dier.modify(|_, w| w.cc1ie().set_bit());
+    }
+
+    fn disable_timer(&mut self) {
+        self.tim.dier.modify(|_, w| w.cc1ie().clear_bit());
+    }
+}
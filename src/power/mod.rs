@@ -0,0 +1,10 @@
+//! Power measurement and management of the board
+//!
+//! [`PowerMonitor`] profiles the consumption of the firmware with an INA260 on I2C1,
+//! [`LowPower`] enters the Sleep, Stop and Standby modes of the MCU.
+
+mod modes;
+mod monitor;
+
+pub use modes::{LowPower, Regulator, Wakeup, WakeupPin};
+pub use monitor::{Alert, AlertPin, Error, PowerMonitor, Sample, Statistics, DEFAULT_ADDRESS};
@@ -0,0 +1,309 @@
+//! Sleep, Stop and Standby modes
+//!
+//! Stop mode resets the clock configuration to the 8 MHz HSI, [`LowPower::stop`] and
+//! [`LowPower::stop_until`] restore it before returning. The drivers waiting for their own
+//! wakeup sources, e.g. [`crate::rtc::Rtc::stop_until_alarm`], build on them.
+
+use crate::exti::{Exti, Line};
+
+use crate::hal::{
+    gpio::{gpioa::PA15, Alternate, AF1},
+    rcc::Rcc,
+    stm32::{Interrupt, EXTI, PWR, RCC},
+};
+
+use crate::time::{Duration, Monotonic};
+
+use cortex_m::peripheral::{NVIC, SCB};
+
+// RCC clock enable of the PWR peripheral
+const PWREN: u32 = 1 << 28;
+
+// RCC_CR and RCC_CR2 oscillator enables and ready flags
+const HSEON: u32 = 1 << 16;
+const HSERDY: u32 = 1 << 17;
+const PLLON: u32 = 1 << 24;
+const PLLRDY: u32 = 1 << 25;
+const HSI48ON: u32 = 1 << 16;
+const HSI48RDY: u32 = 1 << 17;
+
+// RCC_CFGR system clock switch and status
+const SW: u32 = 0b11;
+const SWS_SHIFT: u32 = 2;
+
+// PWR_CSR wakeup pin enables
+const EWUP_SHIFT: u32 = 8;
+
+// The system clock after leaving Stop mode
+const HSI_HZ: u64 = 8_000_000;
+
+// USART2 RX on PA15 is connected to EXTI line 15 via port A
+const EXTI_LINE_RX: u32 = 15;
+const EXTI_LINES_4_15: u32 = 0xfff0;
+
+/// Voltage regulator setting in Stop mode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Regulator {
+    /// Main regulator on, waking up faster
+    Main,
+    /// Regulator in low-power mode, drawing less current but waking up slower
+    LowPower,
+}
+
+/// Pins which can wake the MCU up from Standby mode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WakeupPin {
+    /// WKUP1 on A0
+    Wkup1 = 1,
+}
+
+/// Report of a wakeup from Stop mode
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Wakeup {
+    /// Time from resuming execution until the clocks were restored
+    ///
+    /// Measured with [`Monotonic`], zero if it isn't running. The resolution is limited since
+    /// the counter runs slower on the HSI, e.g. to 6 µs at a system clock of 48 MHz. The wakeup
+    /// time of the regulator given in the datasheet comes on top.
+    pub latency: Duration,
+}
+
+/// Raw clock configuration, as the oscillators and the clock switch are reset by Stop mode
+struct SavedClocks {
+    cr: u32,
+    cr2: u32,
+    cfgr: u32,
+}
+
+impl SavedClocks {
+    fn save() -> Self {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        Self {
+            cr: rcc.cr.read().bits(),
+            cr2: rcc.cr2.read().bits(),
+            cfgr: rcc.cfgr.read().bits(),
+        }
+    }
+
+    /// Restart the oscillators in use and switch back to the saved system clock
+    fn restore(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        if self.cr2 & HSI48ON != 0 {
+            rcc.cr2.modify(|r, w| unsafe { w.bits(r.bits() | HSI48ON) });
+            while rcc.cr2.read().bits() & HSI48RDY == 0 {}
+        }
+
+        if self.cr & HSEON != 0 {
+            rcc.cr.modify(|r, w| unsafe { w.bits(r.bits() | HSEON) });
+            while rcc.cr.read().bits() & HSERDY == 0 {}
+        }
+
+        if self.cr & PLLON != 0 {
+            rcc.cr.modify(|r, w| unsafe { w.bits(r.bits() | PLLON) });
+            while rcc.cr.read().bits() & PLLRDY == 0 {}
+        }
+
+        let sw = self.cfgr & SW;
+        rcc.cfgr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !SW | sw) });
+        while (rcc.cfgr.read().bits() >> SWS_SHIFT) & SW != sw {}
+    }
+}
+
+/// Entry into the low-power modes
+///
+/// Sleep mode only stops the core and is left by any interrupt. Stop mode additionally stops all
+/// clocks and is left by EXTI interrupts, e.g. pins attached via [`Exti`], the RTC alarm or
+/// USART2 RX with [`LowPower::listen_usart2_rx`]. Standby mode turns off everything but the RTC
+/// and the wakeup pins and is left through a reset.
+pub struct LowPower {
+    pwr: PWR,
+    usart2_rx: Option<Line<15>>,
+}
+
+impl LowPower {
+    /// Take PWR and enable its clock
+    pub fn new(pwr: PWR, _rcc: &mut Rcc) -> Self {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb1enr
+            .modify(|r, w| unsafe { w.bits(r.bits() | PWREN) });
+
+        Self {
+            pwr,
+            usart2_rx: None,
+        }
+    }
+
+    /// Access PWR, e.g. for [`crate::rtc::Rtc::new`]
+    pub fn pwr(&mut self) -> &mut PWR {
+        &mut self.pwr
+    }
+
+    /// Whether the last reset was a wakeup from Standby mode, clearing the flag
+    pub fn woke_from_standby(&mut self) -> bool {
+        let standby = self.pwr.csr.read().sbf().bit_is_set();
+        self.pwr.cr.modify(|_, w| w.csbf().set_bit());
+        standby
+    }
+
+    /// Wake up from Standby mode on a rising edge of `pin`
+    pub fn enable_wakeup_pin(&mut self, pin: WakeupPin) {
+        let bit = 1 << (EWUP_SHIFT + pin as u32 - 1);
+        self.pwr
+            .csr
+            .modify(|r, w| unsafe { w.bits(r.bits() | bit) });
+    }
+
+    /// Return `pin` to its normal function
+    pub fn disable_wakeup_pin(&mut self, pin: WakeupPin) {
+        let bit = 1 << (EWUP_SHIFT + pin as u32 - 1);
+        self.pwr
+            .csr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !bit) });
+    }
+
+    /// Wake up from Stop mode on the start bit of a character received by USART2
+    ///
+    /// USART2 of the F042 can't wake the MCU up by itself, so the falling edge on RX (PA15) is
+    /// routed to EXTI line 15, which is only enabled while in Stop mode. The character waking
+    /// the MCU up is usually garbled since the clocks take a while to be restored, so the sender
+    /// should precede the actual data with a wakeup character.
+    pub fn listen_usart2_rx(&mut self, exti: &mut Exti, line: Line<15>) {
+        exti.route::<PA15<Alternate<AF1>>>(&line);
+        exti.set_edges(&line, false, true);
+
+        self.usart2_rx = Some(line);
+    }
+
+    /// Stop waking up on USART2 RX, returning the EXTI line
+    pub fn unlisten_usart2_rx(&mut self, exti: &mut Exti) -> Option<Line<15>> {
+        let line = self.usart2_rx.take()?;
+        exti.set_edges(&line, false, false);
+        Some(line)
+    }
+
+    /// Enter Sleep mode until the next interrupt
+    pub fn sleep(&mut self, scb: &mut SCB) {
+        scb.clear_sleepdeep();
+        cortex_m::asm::dsb();
+        cortex_m::asm::wfi();
+    }
+
+    /// Enter Stop mode until an enabled EXTI line fires and restore the clocks
+    ///
+    /// The interrupts stay disabled until the clock configuration of `rcc` has been restored,
+    /// so the handlers of the wakeup sources run at full speed. Only unmasked interrupts wake
+    /// the MCU up, with the exception of USART2 RX. TIM2 is stopped as well, so [`Monotonic`]
+    /// doesn't account for the time spent in Stop mode.
+    pub fn stop(&mut self, scb: &mut SCB, regulator: Regulator, rcc: &Rcc) -> Wakeup {
+        self.enter_stop(scb, regulator, rcc, || {
+            cortex_m::asm::dsb();
+            cortex_m::asm::wfi();
+        })
+    }
+
+    /// Stay in Stop mode until `done` returns true, woken up by EXTI events, and restore the
+    /// clocks
+    ///
+    /// This is meant for lines generating events instead of interrupts, like the BUSY pin of
+    /// [`crate::display::EinkPanel`]. `done` is checked before each `WFE`, an event occurring in
+    /// between sets the event register so it is not missed.
+    pub fn stop_until(
+        &mut self,
+        scb: &mut SCB,
+        regulator: Regulator,
+        rcc: &Rcc,
+        mut done: impl FnMut() -> bool,
+    ) -> Wakeup {
+        self.enter_stop(scb, regulator, rcc, || {
+            while !done() {
+                cortex_m::asm::dsb();
+                cortex_m::asm::wfe();
+            }
+        })
+    }
+
+    fn enter_stop(
+        &mut self,
+        scb: &mut SCB,
+        regulator: Regulator,
+        rcc: &Rcc,
+        wait: impl FnOnce(),
+    ) -> Wakeup {
+        cortex_m::interrupt::free(|_| {
+            let exti = unsafe { &(*EXTI::ptr()) };
+            let saved = SavedClocks::save();
+
+            // Wake up on USART2 RX via the EXTI interrupt, cleaned up before it gets handled
+            let rx = self.usart2_rx.is_some();
+            let unmasked = NVIC::is_enabled(Interrupt::EXTI4_15);
+            if rx {
+                exti.pr.write(|w| unsafe { w.bits(1 << EXTI_LINE_RX) });
+                exti.imr
+                    .modify(|r, w| unsafe { w.bits(r.bits() | 1 << EXTI_LINE_RX) });
+                unsafe {
+                    NVIC::unmask(Interrupt::EXTI4_15);
+                }
+            }
+
+            self.pwr.cr.modify(|_, w| {
+                w.pdds()
+                    .clear_bit()
+                    .lpds()
+                    .bit(regulator == Regulator::LowPower)
+                    .cwuf()
+                    .set_bit()
+            });
+            scb.set_sleepdeep();
+            wait();
+            scb.clear_sleepdeep();
+
+            let woken = Monotonic::now();
+            saved.restore();
+            let restored = Monotonic::now();
+
+            if rx {
+                exti.imr
+                    .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << EXTI_LINE_RX)) });
+                exti.pr.write(|w| unsafe { w.bits(1 << EXTI_LINE_RX) });
+
+                if exti.pr.read().bits() & exti.imr.read().bits() & EXTI_LINES_4_15 == 0 {
+                    NVIC::unpend(Interrupt::EXTI4_15);
+                }
+                if !unmasked {
+                    NVIC::mask(Interrupt::EXTI4_15);
+                }
+            }
+
+            // Monotonic was ticking slower by the ratio of the HSI to the system clock
+            let ticks = restored.duration_since(woken).as_micros();
+            let sysclk = u64::from(rcc.clocks.sysclk().0);
+            Wakeup {
+                latency: Duration::from_micros(ticks * sysclk / HSI_HZ),
+            }
+        })
+    }
+
+    /// Enter Standby mode, which is only left through a reset
+    ///
+    /// Wakeup sources are the enabled wakeup pins, the RTC alarm, NRST and the IWDG. Pending
+    /// wakeup events, e.g. an uncleared RTC alarm flag, cause an immediate reset.
+    pub fn standby(&mut self, scb: &mut SCB) -> ! {
+        self.pwr
+            .cr
+            .modify(|_, w| w.pdds().set_bit().cwuf().set_bit());
+        scb.set_sleepdeep();
+
+        loop {
+            cortex_m::asm::dsb();
+            cortex_m::asm::wfi();
+        }
+    }
+
+    /// Release PWR and the EXTI line used for USART2 RX
+    pub fn release(self) -> (PWR, Option<Line<15>>) {
+        (self.pwr, self.usart2_rx)
+    }
+}
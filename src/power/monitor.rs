@@ -0,0 +1,438 @@
+//! Power profiling with an INA260 on I2C1
+//!
+//! [`PowerMonitor`] samples current, voltage and power on a fixed schedule and accumulates
+//! minimum, maximum, average and energy in [`Statistics`]. [`AlertPin`] turns the ALERT output
+//! into an `EXTI4_15` interrupt, e.g. for an over-current limit set with
+//! [`PowerMonitor::set_alert`].
+
+use crate::exti::{Exti, Line};
+
+use crate::hal::{
+    gpio::{gpioa::PA12, Input, PullUp},
+    stm32::Interrupt,
+};
+
+use crate::time::{Duration, Instant, Monotonic, Periodic};
+
+use core::fmt;
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+// INA260 registers
+const REG_CONFIG: u8 = 0x00;
+const REG_CURRENT: u8 = 0x01;
+const REG_VOLTAGE: u8 = 0x02;
+const REG_POWER: u8 = 0x03;
+const REG_MASK_ENABLE: u8 = 0x06;
+const REG_ALERT_LIMIT: u8 = 0x07;
+const REG_MANUFACTURER_ID: u8 = 0xfe;
+
+const MANUFACTURER_ID: u16 = 0x5449;
+
+// Continuous conversions of current and voltage with 1.1 ms each, averaged over 16 samples
+const CONFIG: u16 = 0x6527;
+
+// Resolution of the measurement registers
+const CURRENT_LSB_UA: i32 = 1250;
+const VOLTAGE_LSB_UV: u32 = 1250;
+const POWER_LSB_UW: u32 = 10_000;
+
+// Mask/Enable register: alert functions, conversion ready flag and alert latch
+const ALERT_FUNCTION_FLAG: u16 = 1 << 4;
+const ALERT_LATCH: u16 = 1 << 0;
+
+/// Default I2C address of the INA260, with A0 and A1 tied to GND
+pub const DEFAULT_ADDRESS: u8 = 0x40;
+
+/// Errors of the power monitor
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error<E> {
+    /// Error of the I2C bus
+    I2c(E),
+    /// The device at the given address is not an INA260
+    WrongDevice,
+}
+
+/// Condition which pulls the ALERT pin low
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Alert {
+    /// Current above the limit in µA
+    OverCurrent(i32),
+    /// Current below the limit in µA
+    UnderCurrent(i32),
+    /// Bus voltage above the limit in µV
+    OverVoltage(u32),
+    /// Bus voltage below the limit in µV
+    UnderVoltage(u32),
+    /// Power above the limit in µW
+    OverPower(u32),
+}
+
+impl Alert {
+    /// Mask/Enable register bits and alert limit register value
+    fn registers(self) -> (u16, u16) {
+        match self {
+            Alert::OverCurrent(ua) => (1 << 15, (ua / CURRENT_LSB_UA) as u16),
+            Alert::UnderCurrent(ua) => (1 << 14, (ua / CURRENT_LSB_UA) as u16),
+            Alert::OverVoltage(uv) => (1 << 13, (uv / VOLTAGE_LSB_UV) as u16),
+            Alert::UnderVoltage(uv) => (1 << 12, (uv / VOLTAGE_LSB_UV) as u16),
+            Alert::OverPower(uw) => (1 << 11, (uw / POWER_LSB_UW) as u16),
+        }
+    }
+}
+
+/// A single measurement
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sample {
+    /// Bus voltage in µV
+    pub voltage: u32,
+    /// Current in µA, negative when flowing from IN- to IN+
+    pub current: i32,
+    /// Power in µW
+    pub power: u32,
+}
+
+/// Statistics over all samples since the last reset
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Statistics {
+    samples: u32,
+    min_current: i32,
+    max_current: i32,
+    current_sum: i64,
+    // Energy in nJ
+    energy: u64,
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Statistics {
+    /// Create empty statistics
+    pub const fn new() -> Self {
+        Self {
+            samples: 0,
+            min_current: i32::MAX,
+            max_current: i32::MIN,
+            current_sum: 0,
+            energy: 0,
+        }
+    }
+
+    /// Account for `sample`, with the power drawn for `elapsed` since the previous one
+    pub fn add(&mut self, sample: &Sample, elapsed: Duration) {
+        self.samples += 1;
+        self.min_current = self.min_current.min(sample.current);
+        self.max_current = self.max_current.max(sample.current);
+        self.current_sum += i64::from(sample.current);
+        // µW * µs = pJ, accumulated in nJ
+        self.energy += u64::from(sample.power) * elapsed.as_micros() / 1000;
+    }
+
+    /// Number of samples taken
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Lowest current in µA
+    pub fn min_current(&self) -> i32 {
+        if self.samples == 0 {
+            0
+        } else {
+            self.min_current
+        }
+    }
+
+    /// Highest current in µA
+    pub fn max_current(&self) -> i32 {
+        if self.samples == 0 {
+            0
+        } else {
+            self.max_current
+        }
+    }
+
+    /// Average current in µA
+    pub fn average_current(&self) -> i32 {
+        if self.samples == 0 {
+            0
+        } else {
+            (self.current_sum / i64::from(self.samples)) as i32
+        }
+    }
+
+    /// Accumulated energy in µWh
+    pub fn energy_uwh(&self) -> u64 {
+        self.energy / 3_600_000
+    }
+}
+
+/// Fixed point value with three decimals, avoiding the code size of float formatting
+struct Milli(i64);
+
+impl fmt::Display for Milli {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{}{}.{:03}", sign, abs / 1000, abs % 1000)
+    }
+}
+
+/// Renders the statistics as a single line for the VCP, with currents in mA
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} samples, current min {}mA avg {}mA max {}mA, energy {}mWh",
+            self.samples,
+            Milli(self.min_current().into()),
+            Milli(self.average_current().into()),
+            Milli(self.max_current().into()),
+            Milli(self.energy_uwh() as i64)
+        )
+    }
+}
+
+/// Power monitor polling an INA260 on a fixed schedule
+///
+/// Timing is based on [`Monotonic`], which has to be running.
+pub struct PowerMonitor<I2C> {
+    i2c: I2C,
+    address: u8,
+    schedule: Periodic,
+    last: Option<Instant>,
+    latest: Option<Sample>,
+    statistics: Statistics,
+}
+
+impl<I2C, E> PowerMonitor<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Set up the INA260 at `address` for continuous conversions and sample it every `interval`
+    ///
+    /// The INA260 averages 16 conversions of 1.1 ms each, so intervals below 35 ms return the same
+    /// values repeatedly.
+    pub fn new(i2c: I2C, address: u8, interval: Duration) -> Result<Self, Error<E>> {
+        let mut monitor = Self {
+            i2c,
+            address,
+            schedule: Periodic::new(interval),
+            last: None,
+            latest: None,
+            statistics: Statistics::new(),
+        };
+
+        if monitor.read_register(REG_MANUFACTURER_ID)? != MANUFACTURER_ID {
+            return Err(Error::WrongDevice);
+        }
+        monitor.write_register(REG_CONFIG, CONFIG)?;
+
+        Ok(monitor)
+    }
+
+    /// Release the I2C bus
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// Take a sample if it is due, returning it
+    pub fn poll(&mut self) -> Result<Option<Sample>, Error<E>> {
+        if !self.schedule.poll() {
+            return Ok(None);
+        }
+
+        let sample = self.sample()?;
+        let now = Monotonic::now();
+        let elapsed = self
+            .last
+            .map_or(Duration::ZERO, |last| now.duration_since(last));
+        self.statistics.add(&sample, elapsed);
+        self.last = Some(now);
+
+        Ok(Some(sample))
+    }
+
+    /// Read the current measurements regardless of the schedule
+    pub fn sample(&mut self) -> Result<Sample, Error<E>> {
+        let current = self.read_register(REG_CURRENT)? as i16;
+        let voltage = self.read_register(REG_VOLTAGE)?;
+        let power = self.read_register(REG_POWER)?;
+
+        let sample = Sample {
+            voltage: u32::from(voltage) * VOLTAGE_LSB_UV,
+            current: i32::from(current) * CURRENT_LSB_UA,
+            power: u32::from(power) * POWER_LSB_UW,
+        };
+        self.latest = Some(sample);
+
+        Ok(sample)
+    }
+
+    /// The most recent sample
+    pub fn latest(&self) -> Option<Sample> {
+        self.latest
+    }
+
+    /// Statistics since the start or the last reset
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    /// Restart the statistics
+    pub fn reset_statistics(&mut self) {
+        self.statistics = Statistics::new();
+        self.last = None;
+    }
+
+    /// Pull the ALERT pin low while `alert` applies, or never for `None`
+    ///
+    /// The alert is latched until [`PowerMonitor::clear_alert`] is called.
+    pub fn set_alert(&mut self, alert: Option<Alert>) -> Result<(), Error<E>> {
+        match alert {
+            Some(alert) => {
+                let (mask, limit) = alert.registers();
+                self.write_register(REG_ALERT_LIMIT, limit)?;
+                self.write_register(REG_MASK_ENABLE, mask | ALERT_LATCH)
+            }
+            None => self.write_register(REG_MASK_ENABLE, 0),
+        }
+    }
+
+    /// Release the ALERT pin, returning whether the alert condition had been met
+    pub fn clear_alert(&mut self) -> Result<bool, Error<E>> {
+        // Reading the Mask/Enable register clears the latched alert
+        Ok(self.read_register(REG_MASK_ENABLE)? & ALERT_FUNCTION_FLAG != 0)
+    }
+
+    /// Write a line with the latest sample and the statistics to `w`, e.g. the VCP
+    pub fn report(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        if let Some(sample) = self.latest {
+            write!(
+                w,
+                "{}mV {}mA {}mW, ",
+                Milli(sample.voltage.into()),
+                Milli(sample.current.into()),
+                Milli(sample.power.into())
+            )?;
+        }
+        write!(w, "{}\r\n", self.statistics)
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u16, Error<E>> {
+        let mut buffer = [0; 2];
+        self.i2c
+            .write_read(self.address, &[register], &mut buffer)
+            .map_err(Error::I2c)?;
+        Ok(u16::from_be_bytes(buffer))
+    }
+
+    fn write_register(&mut self, register: u8, value: u16) -> Result<(), Error<E>> {
+        let [msb, lsb] = value.to_be_bytes();
+        self.i2c
+            .write(self.address, &[register, msb, lsb])
+            .map_err(Error::I2c)
+    }
+}
+
+/// The ALERT output of the INA260 connected to D2 (PA12), raising the `EXTI4_15` interrupt
+///
+/// The ALERT output is open-drain, so the internal pull-up of the pin is used.
+pub struct AlertPin {
+    pin: PA12<Input<PullUp>>,
+    line: Line<12>,
+}
+
+impl AlertPin {
+    /// Route falling edges on the pin to EXTI line 12 and unmask `EXTI4_15` in the NVIC
+    pub fn new(pin: PA12<Input<PullUp>>, exti: &mut Exti, line: Line<12>) -> Self {
+        exti.route::<PA12<Input<PullUp>>>(&line);
+        exti.set_edges(&line, false, true);
+        exti.enable_interrupt(&line);
+
+        cortex_m::peripheral::NVIC::unpend(Interrupt::EXTI4_15);
+        unsafe {
+            cortex_m::peripheral::NVIC::unmask(Interrupt::EXTI4_15);
+        }
+
+        Self { pin, line }
+    }
+
+    /// Check for and clear a pending alert, to be called from the `EXTI4_15` interrupt handler
+    pub fn check(&mut self, exti: &mut Exti) -> bool {
+        let pending = exti.is_pending(&self.line);
+        if pending {
+            exti.clear_pending(&self.line);
+        }
+        pending
+    }
+
+    /// Stop listening and release the pin and the token of its EXTI line
+    pub fn release(self, exti: &mut Exti) -> (PA12<Input<PullUp>>, Line<12>) {
+        exti.disable_interrupt(&self.line);
+        exti.set_edges(&self.line, false, false);
+        (self.pin, self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(current: i32, power: u32) -> Sample {
+        Sample {
+            voltage: 5_000_000,
+            current,
+            power,
+        }
+    }
+
+    #[test]
+    fn empty_statistics_report_zero() {
+        let statistics = Statistics::new();
+
+        assert_eq!(statistics.samples(), 0);
+        assert_eq!(statistics.min_current(), 0);
+        assert_eq!(statistics.max_current(), 0);
+        assert_eq!(statistics.average_current(), 0);
+        assert_eq!(statistics.energy_uwh(), 0);
+    }
+
+    #[test]
+    fn currents_in_both_directions() {
+        let mut statistics = Statistics::new();
+        for &current in &[-100, 200, 400] {
+            statistics.add(&sample(current, 0), Duration::from_millis(1));
+        }
+
+        assert_eq!(statistics.samples(), 3);
+        assert_eq!(statistics.min_current(), -100);
+        assert_eq!(statistics.max_current(), 400);
+        assert_eq!(statistics.average_current(), 166);
+    }
+
+    #[test]
+    fn energy_is_integrated_over_time() {
+        let mut statistics = Statistics::new();
+
+        // 1 W for an hour
+        statistics.add(&sample(200_000, 1_000_000), Duration::from_secs(3600));
+        assert_eq!(statistics.energy_uwh(), 1_000_000);
+    }
+
+    #[test]
+    fn short_samples_add_up() {
+        let mut statistics = Statistics::new();
+
+        // 1 mW for 3.6 s in steps of 1 ms, 1 µJ each
+        for _ in 0..3599 {
+            statistics.add(&sample(200, 1000), Duration::from_millis(1));
+        }
+        assert_eq!(statistics.energy_uwh(), 0);
+
+        statistics.add(&sample(200, 1000), Duration::from_millis(1));
+        assert_eq!(statistics.energy_uwh(), 1);
+    }
+}
@@ -0,0 +1,505 @@
+//! PWM outputs on the header pins
+//!
+//! These header pins are connected to timer channels:
+//!
+//! | Timer | Channel 1              | Channel 2        | Channel 3  | Channel 4  |
+//! |-------|------------------------|------------------|------------|------------|
+//! | TIM1  | D9, A6 (N)             | D1, D3 (N)       | D0, D6 (N) | D10        |
+//! | TIM2  | A0, A4                 | A1, D13          | A7         | A2         |
+//! | TIM3  | A5, D12                | A6, D11          | D3         | D6         |
+//! | TIM14 | A3, A6, D6             |                  |            |            |
+//! | TIM16 | A5, D5 (N)             |                  |            |            |
+//! | TIM17 | A6, D4 (N)             |                  |            |            |
+//!
+//! Pins marked with (N) are complementary outputs, which can be combined with the normal output of
+//! the same channel and a dead-time.
+//!
+//! All channels of a timer run at the same frequency. [`Pwm`] takes ownership of the timer and
+//! sets the frequency, outputs can only be created from it with pins of the very same timer and one
+//! token per channel. So pins needing different frequencies can't end up on the same timer without
+//! the compiler noticing.
+
+use crate::hal::{
+    gpio::{gpioa, gpiob, Alternate, AF0, AF1, AF2, AF4, AF5},
+    rcc::Rcc,
+    stm32::{RCC, TIM1, TIM14, TIM16, TIM17, TIM2, TIM3},
+};
+
+use crate::time::Hertz;
+
+use core::{convert::Infallible, marker::PhantomData, ptr};
+
+// Register offsets, identical for all timers and shared with the other timer drivers
+pub(crate) const CR1: usize = 0x00;
+pub(crate) const SMCR: usize = 0x08;
+pub(crate) const DIER: usize = 0x0c;
+pub(crate) const SR: usize = 0x10;
+pub(crate) const EGR: usize = 0x14;
+pub(crate) const CCMR1: usize = 0x18;
+pub(crate) const CCER: usize = 0x20;
+pub(crate) const CNT: usize = 0x24;
+pub(crate) const PSC: usize = 0x28;
+pub(crate) const ARR: usize = 0x2c;
+pub(crate) const CCR1: usize = 0x34;
+const BDTR: usize = 0x44;
+
+// CR1, EGR and BDTR bits
+const CEN: u32 = 1 << 0;
+const ARPE: u32 = 1 << 7;
+const UG: u32 = 1 << 0;
+const MOE: u32 = 1 << 15;
+const DTG_MASK: u32 = 0xff;
+
+// PWM mode 1 with preload in the CCMR fields of a channel
+const OC_PWM1: u32 = 0b110 << 4 | 1 << 3;
+
+/// A timer which can generate PWM
+pub trait Instance {
+    #[doc(hidden)]
+    fn base() -> usize;
+    #[doc(hidden)]
+    fn enable(rcc: &RCC);
+    #[doc(hidden)]
+    fn disable(rcc: &RCC);
+    /// Number of channels
+    const CHANNELS: usize;
+    /// Whether the timer has a break and dead-time unit with a main output enable
+    const ADVANCED: bool;
+}
+
+macro_rules! instances {
+    ($($TIM:ident: ($enr:ident, $rstr:ident, $bit:expr, $channels:expr, $advanced:expr),)+) => {
+        $(
+            impl Instance for $TIM {
+                fn base() -> usize {
+                    $TIM::ptr() as usize
+                }
+
+                fn enable(rcc: &RCC) {
+                    rcc.$enr.modify(|r, w| unsafe { w.bits(r.bits() | 1 << $bit) });
+                    rcc.$rstr.modify(|r, w| unsafe { w.bits(r.bits() | 1 << $bit) });
+                    rcc.$rstr.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << $bit)) });
+                }
+
+                fn disable(rcc: &RCC) {
+                    rcc.$enr.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << $bit)) });
+                }
+
+                const CHANNELS: usize = $channels;
+                const ADVANCED: bool = $advanced;
+            }
+        )+
+    };
+}
+
+instances!(
+    TIM1: (apb2enr, apb2rstr, 11, 4, true),
+    TIM2: (apb1enr, apb1rstr, 0, 4, false),
+    TIM3: (apb1enr, apb1rstr, 1, 4, false),
+    TIM14: (apb1enr, apb1rstr, 8, 1, false),
+    TIM16: (apb2enr, apb2rstr, 17, 1, true),
+    TIM17: (apb2enr, apb2rstr, 18, 1, true),
+);
+
+/// Token of channel `C` of timer `TIM`
+pub struct Channel<TIM, const C: u8> {
+    _tim: PhantomData<TIM>,
+}
+
+/// Number of a channel token
+pub trait ChannelNumber {
+    const NUMBER: u8;
+}
+
+impl<TIM, const C: u8> ChannelNumber for Channel<TIM, C> {
+    const NUMBER: u8 = C;
+}
+
+/// Tokens of the channels of a timer; TIM14, TIM16 and TIM17 only have the first one
+pub struct Channels<TIM> {
+    pub c1: Channel<TIM, 1>,
+    pub c2: Channel<TIM, 2>,
+    pub c3: Channel<TIM, 3>,
+    pub c4: Channel<TIM, 4>,
+}
+
+/// A pin connected to a channel of `TIM`
+pub trait ChannelPin<TIM> {
+    type Channel: ChannelNumber;
+    #[doc(hidden)]
+    const COMPLEMENTARY: bool = false;
+}
+
+/// A pin connected to the complementary output of a channel of `TIM`
+pub trait ComplementaryPin<TIM> {
+    type Channel: ChannelNumber;
+}
+
+macro_rules! pins {
+    ($trait:ident: $($gpio:ident::$PXi:ident<$AF:ident> => ($TIM:ident, $c:expr),)+) => {
+        $(
+            impl $trait<$TIM> for $gpio::$PXi<Alternate<$AF>> {
+                type Channel = Channel<$TIM, $c>;
+            }
+        )+
+    };
+}
+
+pins!(ChannelPin:
+    gpioa::PA8<AF2> => (TIM1, 1),
+    gpioa::PA9<AF2> => (TIM1, 2),
+    gpioa::PA10<AF2> => (TIM1, 3),
+    gpioa::PA11<AF2> => (TIM1, 4),
+    gpioa::PA0<AF2> => (TIM2, 1),
+    gpioa::PA5<AF2> => (TIM2, 1),
+    gpioa::PA1<AF2> => (TIM2, 2),
+    gpiob::PB3<AF2> => (TIM2, 2),
+    gpioa::PA2<AF2> => (TIM2, 3),
+    gpioa::PA3<AF2> => (TIM2, 4),
+    gpioa::PA6<AF1> => (TIM3, 1),
+    gpiob::PB4<AF1> => (TIM3, 1),
+    gpioa::PA7<AF1> => (TIM3, 2),
+    gpiob::PB5<AF1> => (TIM3, 2),
+    gpiob::PB0<AF1> => (TIM3, 3),
+    gpiob::PB1<AF1> => (TIM3, 4),
+    gpioa::PA4<AF4> => (TIM14, 1),
+    gpioa::PA7<AF4> => (TIM14, 1),
+    gpiob::PB1<AF0> => (TIM14, 1),
+    gpioa::PA6<AF5> => (TIM16, 1),
+    gpioa::PA7<AF5> => (TIM17, 1),
+);
+
+/// A pin together with the complementary pin of the same channel
+impl<TIM, P, N, C> ChannelPin<TIM> for (P, N)
+where
+    P: ChannelPin<TIM, Channel = C>,
+    N: ComplementaryPin<TIM, Channel = C>,
+    C: ChannelNumber,
+{
+    type Channel = C;
+    const COMPLEMENTARY: bool = true;
+}
+
+pins!(ComplementaryPin:
+    gpioa::PA7<AF2> => (TIM1, 1),
+    gpiob::PB0<AF2> => (TIM1, 2),
+    gpiob::PB1<AF2> => (TIM1, 3),
+    gpiob::PB6<AF2> => (TIM16, 1),
+    gpiob::PB7<AF2> => (TIM17, 1),
+);
+
+pub(crate) fn register(base: usize, offset: usize) -> *mut u32 {
+    (base + offset) as *mut u32
+}
+
+pub(crate) fn read(base: usize, offset: usize) -> u32 {
+    unsafe { ptr::read_volatile(register(base, offset)) }
+}
+
+pub(crate) fn write(base: usize, offset: usize, value: u32) {
+    unsafe { ptr::write_volatile(register(base, offset), value) }
+}
+
+pub(crate) fn modify(base: usize, offset: usize, f: impl FnOnce(u32) -> u32) {
+    write(base, offset, f(read(base, offset)))
+}
+
+/// Prescaler and auto-reload values for `frequency` at a timer clock of `clock`, aiming for the
+/// finest duty cycle resolution
+pub fn prescalers(clock: u32, frequency: u32) -> (u16, u16) {
+    let ticks = (clock / frequency.max(1)).max(2);
+    // ARR stays below 0xffff so a duty cycle of 100% still fits into CCR
+    let psc = ((ticks - 1) / 0xffff).min(0xffff);
+    let arr = (ticks / (psc + 1)).clamp(2, 0xffff) - 1;
+    (psc as u16, arr as u16)
+}
+
+/// DTG field of BDTR for a dead-time of at least `ns` at a timer clock of `clock`, saturating at
+/// the longest possible dead-time
+pub fn dead_time_bits(clock: u32, ns: u32) -> u8 {
+    // Dead-time in timer clock cycles, rounded up
+    let cycles = ((u64::from(ns) * u64::from(clock) + 999_999_999) / 1_000_000_000) as u32;
+
+    match cycles {
+        0..=127 => cycles as u8,
+        128..=254 => 0b1000_0000 | ((cycles + 1) / 2 - 64) as u8,
+        255..=504 => 0b1100_0000 | ((cycles + 7) / 8 - 32) as u8,
+        505..=1008 => 0b1110_0000 | ((cycles + 15) / 16 - 32) as u8,
+        _ => 0xff,
+    }
+}
+
+/// A timer generating PWM on up to four channels
+pub struct Pwm<TIM> {
+    tim: TIM,
+    clock: u32,
+}
+
+impl<TIM: Instance> Pwm<TIM> {
+    /// Start `tim` counting at `frequency`, with all outputs still disabled
+    pub fn new<F: Into<Hertz>>(tim: TIM, frequency: F, rcc: &mut Rcc) -> (Self, Channels<TIM>) {
+        let rccr = unsafe { &(*RCC::ptr()) };
+        TIM::enable(rccr);
+
+        let mut pwm = Self {
+            tim,
+            clock: crate::timer_clock(&rcc.clocks),
+        };
+        pwm.set_frequency(frequency);
+
+        let base = TIM::base();
+        if TIM::ADVANCED {
+            modify(base, BDTR, |bdtr| bdtr | MOE);
+        }
+        modify(base, CR1, |cr1| cr1 | ARPE | CEN);
+
+        let channels = Channels {
+            c1: Channel { _tim: PhantomData },
+            c2: Channel { _tim: PhantomData },
+            c3: Channel { _tim: PhantomData },
+            c4: Channel { _tim: PhantomData },
+        };
+
+        (pwm, channels)
+    }
+
+    /// Change the frequency of all channels, keeping their duty cycles
+    pub fn set_frequency<F: Into<Hertz>>(&mut self, frequency: F) {
+        let (psc, arr) = prescalers(self.clock, frequency.into().0);
+        self.set_prescalers(psc, arr);
+    }
+
+    /// Count at `tick` with `period` ticks per PWM period, keeping the duty cycles
+    ///
+    /// This gives duty cycles in fixed units, e.g. microseconds with a tick of 1 MHz. The tick
+    /// is rounded to a fraction of the timer clock and `period` limited to 65535.
+    pub fn set_timebase<F: Into<Hertz>>(&mut self, tick: F, period: u16) {
+        let psc = (self.clock / tick.into().0.max(1)).clamp(1, 0x1_0000) - 1;
+        self.set_prescalers(psc as u16, period.clamp(2, 0xffff) - 1);
+    }
+
+    fn set_prescalers(&mut self, psc: u16, arr: u16) {
+        let base = TIM::base();
+
+        let old_period = u64::from(read(base, ARR)) + 1;
+        let new_period = u64::from(arr) + 1;
+        for offset in (0..TIM::CHANNELS).map(|c| CCR1 + 4 * c) {
+            let ccr = u64::from(read(base, offset) & 0xffff);
+            write(base, offset, (ccr * new_period / old_period) as u32);
+        }
+
+        write(base, PSC, u32::from(psc));
+        write(base, ARR, u32::from(arr));
+
+        // Apply the new values right away instead of at the next update event
+        write(base, EGR, UG);
+    }
+
+    /// The actual frequency, which can deviate from the requested one due to rounding
+    pub fn frequency(&self) -> Hertz {
+        let base = TIM::base();
+        Hertz(self.clock / ((read(base, PSC) + 1) * (read(base, ARR) + 1)))
+    }
+
+    /// Generate PWM on `pin`, which can also be a pin paired with its complementary pin
+    pub fn output<P: ChannelPin<TIM>>(&mut self, pin: P, channel: P::Channel) -> PwmOutput<TIM, P> {
+        let index = usize::from(P::Channel::NUMBER - 1);
+        let base = TIM::base();
+
+        let shift = 8 * (index % 2);
+        modify(base, CCMR1 + 4 * (index / 2), |ccmr| {
+            ccmr & !(0xff << shift) | OC_PWM1 << shift
+        });
+        write(base, CCR1 + 4 * index, 0);
+
+        PwmOutput {
+            pin,
+            channel,
+            clock: self.clock,
+            _tim: PhantomData,
+        }
+    }
+
+    /// Stop the timer and release it, all outputs have to be released before
+    pub fn release(self) -> TIM {
+        let base = TIM::base();
+        modify(base, CR1, |cr1| cr1 & !CEN);
+
+        let rccr = unsafe { &(*RCC::ptr()) };
+        TIM::disable(rccr);
+
+        self.tim
+    }
+}
+
+impl<TIM: Instance> Pwm<TIM> {
+    /// Insert at least `ns` nanoseconds between switching off one complementary output and
+    /// switching on the other, returns the actual dead-time
+    ///
+    /// Only TIM1, TIM16 and TIM17 have complementary outputs. The dead-time is shared by all
+    /// channels of the timer and limited to 1008 cycles of the timer clock, i.e. 21 µs at 48 MHz.
+    pub fn set_dead_time(&mut self, ns: u32) -> u32 {
+        let dtg = dead_time_bits(self.clock, ns);
+        modify(TIM::base(), BDTR, |bdtr| bdtr & !DTG_MASK | u32::from(dtg));
+
+        let cycles = match dtg >> 5 {
+            0b000..=0b011 => u32::from(dtg),
+            0b100 | 0b101 => (64 + u32::from(dtg & 0x3f)) * 2,
+            0b110 => (32 + u32::from(dtg & 0x1f)) * 8,
+            _ => (32 + u32::from(dtg & 0x1f)) * 16,
+        };
+        (u64::from(cycles) * 1_000_000_000 / u64::from(self.clock)) as u32
+    }
+}
+
+/// A PWM output, starting disabled with a duty cycle of 0
+pub struct PwmOutput<TIM, P: ChannelPin<TIM>> {
+    pin: P,
+    channel: P::Channel,
+    clock: u32,
+    _tim: PhantomData<TIM>,
+}
+
+impl<TIM: Instance, P: ChannelPin<TIM>> PwmOutput<TIM, P> {
+    fn index() -> usize {
+        usize::from(P::Channel::NUMBER - 1)
+    }
+
+    /// CCER enable bits of the channel
+    fn enable_bits() -> u32 {
+        // CCxE and CCxNE
+        let bits = if P::COMPLEMENTARY { 0b101 } else { 0b001 };
+        bits << (4 * Self::index())
+    }
+
+    /// Start generating the signal
+    pub fn enable(&mut self) {
+        modify(TIM::base(), CCER, |ccer| ccer | Self::enable_bits());
+    }
+
+    /// Stop generating the signal, the pin is driven low
+    pub fn disable(&mut self) {
+        modify(TIM::base(), CCER, |ccer| ccer & !Self::enable_bits());
+    }
+
+    /// The duty cycle corresponding to 100%
+    pub fn max_duty(&self) -> u16 {
+        (read(TIM::base(), ARR) + 1) as u16
+    }
+
+    /// The duty cycle in timer ticks
+    pub fn duty(&self) -> u16 {
+        read(TIM::base(), CCR1 + 4 * Self::index()) as u16
+    }
+
+    /// Set the duty cycle in timer ticks, from 0 to [`PwmOutput::max_duty`]
+    pub fn set_duty(&mut self, duty: u16) {
+        let duty = duty.min(self.max_duty());
+        write(TIM::base(), CCR1 + 4 * Self::index(), u32::from(duty));
+    }
+
+    /// Set the duty cycle in percent
+    pub fn set_duty_percent(&mut self, percent: u8) {
+        let duty = u32::from(self.max_duty()) * u32::from(percent.min(100)) / 100;
+        self.set_duty(duty as u16);
+    }
+
+    /// Set the high time in nanoseconds, saturating at the period
+    pub fn set_duty_ns(&mut self, ns: u32) {
+        let tick_hz = u64::from(self.clock / (read(TIM::base(), PSC) + 1));
+        let ticks = (u64::from(ns) * tick_hz + 500_000_000) / 1_000_000_000;
+        self.set_duty(ticks.min(0xffff) as u16);
+    }
+
+    /// The high time in nanoseconds
+    pub fn duty_ns(&self) -> u32 {
+        let tick_hz = u64::from(self.clock / (read(TIM::base(), PSC) + 1));
+        (u64::from(self.duty()) * 1_000_000_000 / tick_hz) as u32
+    }
+
+    /// Disable the output and return the pin and the channel token
+    pub fn release(mut self) -> (P, P::Channel) {
+        self.disable();
+        (self.pin, self.channel)
+    }
+}
+
+impl<TIM: Instance, P: ChannelPin<TIM>> embedded_hal::PwmPin for PwmOutput<TIM, P> {
+    type Duty = u16;
+
+    fn disable(&mut self) {
+        PwmOutput::disable(self)
+    }
+
+    fn enable(&mut self) {
+        PwmOutput::enable(self)
+    }
+
+    fn get_duty(&self) -> u16 {
+        self.duty()
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        self.max_duty()
+    }
+
+    fn set_duty(&mut self, duty: u16) {
+        PwmOutput::set_duty(self, duty)
+    }
+}
+
+impl<TIM: Instance, P: ChannelPin<TIM>> embedded_hal_1::pwm::ErrorType for PwmOutput<TIM, P> {
+    type Error = Infallible;
+}
+
+impl<TIM: Instance, P: ChannelPin<TIM>> embedded_hal_1::pwm::SetDutyCycle for PwmOutput<TIM, P> {
+    fn max_duty_cycle(&self) -> u16 {
+        self.max_duty()
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        PwmOutput::set_duty(self, duty);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prescalers_keep_finest_resolution() {
+        assert_eq!(prescalers(48_000_000, 20_000), (0, 2_399));
+        assert_eq!(prescalers(48_000_000, 50), (14, 63_999));
+        assert_eq!(prescalers(48_000_000, 1), (732, 65_483));
+    }
+
+    #[test]
+    fn prescalers_of_extreme_frequencies() {
+        assert_eq!(prescalers(48_000_000, 48_000_000), (0, 1));
+        assert_eq!(prescalers(48_000_000, 100_000_000), (0, 1));
+        assert_eq!(prescalers(8_000_000, 0), (122, 65_039));
+    }
+
+    #[test]
+    fn dead_time_rounds_up() {
+        assert_eq!(dead_time_bits(48_000_000, 0), 0);
+        assert_eq!(dead_time_bits(48_000_000, 100), 5);
+    }
+
+    #[test]
+    fn dead_time_ranges() {
+        // A 1 GHz clock makes a cycle 1 ns long
+        const CLOCK: u32 = 1_000_000_000;
+
+        assert_eq!(dead_time_bits(CLOCK, 127), 127);
+        assert_eq!(dead_time_bits(CLOCK, 128), 0x80);
+        assert_eq!(dead_time_bits(CLOCK, 129), 0x81);
+        assert_eq!(dead_time_bits(CLOCK, 254), 0xbf);
+        assert_eq!(dead_time_bits(CLOCK, 255), 0xc0);
+        assert_eq!(dead_time_bits(CLOCK, 504), 0xdf);
+        assert_eq!(dead_time_bits(CLOCK, 505), 0xe0);
+        assert_eq!(dead_time_bits(CLOCK, 1_008), 0xff);
+        assert_eq!(dead_time_bits(CLOCK, 5_000), 0xff);
+    }
+}
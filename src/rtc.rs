@@ -0,0 +1,538 @@
+//! Real-time clock with calendar and alarm
+//!
+//! The Nucleo-F042K6 does not have the LSE crystal populated so the RTC is clocked from the
+//! internal ~40 kHz LSI oscillator. The LSI is quite inaccurate (anywhere between 30 and 50 kHz),
+//! so [`Rtc::calibrate`] measures its real frequency against the HSI derived timer clock using
+//! TIM14 input capture and adjusts the prescalers accordingly.
+//!
+//! The F042 RTC has no wakeup timer, so alarm A is used to wake the MCU up from Stop mode.
+
+use crate::exti::{Exti, Line};
+use crate::power::{LowPower, Regulator, Wakeup};
+
+use crate::hal::{
+    rcc::Rcc,
+    stm32::{PWR, RCC, RTC, TIM14},
+};
+
+use cortex_m::peripheral::SCB;
+
+/// EXTI line internally connected to the RTC alarm, not connected to any GPIO
+const EXTI_LINE_ALARM: u8 = 17;
+
+/// Number of loop iterations to wait for a flag before giving up
+const TIMEOUT: u32 = 100_000;
+
+/// Nominal LSI frequency used until [`Rtc::calibrate`] is called
+pub const LSI_NOMINAL_HZ: u32 = 40_000;
+
+/// Errors reported by the RTC
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The LSI oscillator did not start up
+    LsiNotReady,
+    /// The RTC did not enter initialisation mode or did not synchronise its shadow registers
+    Timeout,
+    /// The supplied date/time or alarm cannot be represented
+    InvalidValue,
+    /// A capture of the LSI measurement was overwritten before it was read
+    MissedCapture,
+}
+
+/// Calendar date and time, valid for the years 2000 to 2099
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Create a new date/time, checking that all fields are within range
+    pub fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Result<Self, Error> {
+        let dt = Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        };
+
+        if !(2000..=2099).contains(&year)
+            || !(1..=12).contains(&month)
+            || day == 0
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 59
+        {
+            return Err(Error::InvalidValue);
+        }
+
+        Ok(dt)
+    }
+
+    /// Day of the week, 1 being Monday and 7 being Sunday as the RTC counts them
+    pub fn weekday(&self) -> u8 {
+        const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+
+        let year = if self.month < 3 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let sunday_based = (year + year / 4 - year / 100
+            + year / 400
+            + OFFSETS[usize::from(self.month - 1)]
+            + u16::from(self.day))
+            % 7;
+
+        if sunday_based == 0 {
+            7
+        } else {
+            sunday_based as u8
+        }
+    }
+}
+
+/// Number of days in the given month of the given year
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Alarm A configuration, each `None` field matches any value
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Alarm {
+    pub day: Option<u8>,
+    pub hour: Option<u8>,
+    pub minute: Option<u8>,
+    pub second: Option<u8>,
+}
+
+impl Alarm {
+    /// An alarm triggering once every second
+    pub fn every_second() -> Self {
+        Self::default()
+    }
+
+    /// An alarm triggering once every minute at the given second
+    pub fn every_minute(second: u8) -> Self {
+        Self {
+            second: Some(second),
+            ..Self::default()
+        }
+    }
+
+    /// An alarm triggering once a day at the given time
+    pub fn daily(hour: u8, minute: u8, second: u8) -> Self {
+        Self {
+            day: None,
+            hour: Some(hour),
+            minute: Some(minute),
+            second: Some(second),
+        }
+    }
+
+    fn bits(&self) -> Result<u32, Error> {
+        fn field(value: Option<u8>, max: u8, mask_bit: u32, shift: u32) -> Result<u32, Error> {
+            match value {
+                None => Ok(1 << mask_bit),
+                Some(v) if v <= max => Ok(bcd(v) << shift),
+                Some(_) => Err(Error::InvalidValue),
+            }
+        }
+
+        if self.day == Some(0) {
+            return Err(Error::InvalidValue);
+        }
+
+        Ok(field(self.day, 31, 31, 24)?
+            | field(self.hour, 23, 23, 16)?
+            | field(self.minute, 59, 15, 8)?
+            | field(self.second, 59, 7, 0)?)
+    }
+}
+
+/// Convert a binary value below 100 into its BCD representation
+fn bcd(value: u8) -> u32 {
+    u32::from(value / 10) << 4 | u32::from(value % 10)
+}
+
+/// Convert a BCD encoded field into its binary value
+fn from_bcd(value: u32) -> u8 {
+    ((value >> 4) * 10 + (value & 0xf)) as u8
+}
+
+/// Calculate asynchronous and synchronous prescaler values for a 1 Hz calendar clock
+fn prescalers(lsi_hz: u32) -> (u32, u32) {
+    // Use the largest asynchronous prescaler to minimise power consumption
+    let prediv_a = 127;
+    let prediv_s = ((lsi_hz + 64) / 128).max(1) - 1;
+    (prediv_a, prediv_s.min(0x7fff))
+}
+
+fn wait_for(mut condition: impl FnMut() -> bool) -> Result<(), Error> {
+    for _ in 0..TIMEOUT {
+        if condition() {
+            return Ok(());
+        }
+    }
+    Err(Error::Timeout)
+}
+
+/// Real-time clock driver
+pub struct Rtc {
+    rtc: RTC,
+    lsi_hz: u32,
+}
+
+impl Rtc {
+    /// Enable the LSI, select it as RTC clock and start the calendar with nominal prescalers
+    ///
+    /// The backup domain is only reset if the RTC is currently clocked from a different source,
+    /// so the calendar survives a system reset.
+    pub fn new(rtc: RTC, pwr: &mut PWR, _rcc: &mut Rcc) -> Result<Self, Error> {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // Enable write access to the backup domain
+        rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+
+        // Start the LSI and wait for it to stabilise
+        rcc.csr.modify(|_, w| w.lsion().set_bit());
+        wait_for(|| rcc.csr.read().lsirdy().bit_is_set()).map_err(|_| Error::LsiNotReady)?;
+
+        // Select LSI as RTC clock, which requires a backup domain reset if something else is set
+        if rcc.bdcr.read().rtcsel().bits() != 0b10 {
+            rcc.bdcr.modify(|_, w| w.bdrst().set_bit());
+            rcc.bdcr.modify(|_, w| w.bdrst().clear_bit());
+            rcc.bdcr.modify(|_, w| unsafe { w.rtcsel().bits(0b10) });
+        }
+        rcc.bdcr.modify(|_, w| w.rtcen().set_bit());
+
+        let mut rtc = Self {
+            rtc,
+            lsi_hz: LSI_NOMINAL_HZ,
+        };
+
+        // Only program the prescalers if the calendar has never been initialised
+        if rtc.rtc.isr.read().inits().bit_is_clear() {
+            rtc.set_prescalers(LSI_NOMINAL_HZ)?;
+        }
+
+        Ok(rtc)
+    }
+
+    /// Measure the LSI frequency against the timer clock and adjust the prescalers
+    ///
+    /// TIM14 channel 1 is internally routed to the RTC clock and captures every 8th rising edge,
+    /// the measurement is averaged over 16 captures. Since the timer clock is derived from the
+    /// HSI the result is as accurate as the (factory trimmed) HSI, i.e. about ±1%. Returns the
+    /// measured LSI frequency in Hz.
+    pub fn calibrate(&mut self, tim14: &mut TIM14, rcc: &mut Rcc) -> Result<u32, Error> {
+        const CAPTURES: u32 = 16;
+        const EDGES_PER_CAPTURE: u32 = 8;
+
+        let tim_clk = crate::timer_clock(&rcc.clocks);
+        let rccr = unsafe { &(*RCC::ptr()) };
+
+        rccr.apb1enr.modify(|_, w| w.tim14en().set_bit());
+        rccr.apb1rstr.modify(|_, w| w.tim14rst().set_bit());
+        rccr.apb1rstr.modify(|_, w| w.tim14rst().clear_bit());
+
+        // Route RTCCLK (i.e. LSI) to TI1
+        tim14.or.write(|w| unsafe { w.rmp().bits(0b01) });
+
+        // Free running timer at full timer clock
+        tim14.psc.write(|w| unsafe { w.psc().bits(0) });
+        tim14.arr.write(|w| unsafe { w.bits(0xffff) });
+
+        // CC1 as input mapped on TI1, capture every 8th event
+        tim14
+            .ccmr1_input()
+            .write(|w| unsafe { w.cc1s().bits(0b01).ic1psc().bits(0b11) });
+        tim14.ccer.write(|w| w.cc1e().set_bit());
+        tim14.cr1.modify(|_, w| w.cen().set_bit());
+
+        let capture = || -> Result<u16, Error> {
+            wait_for(|| tim14.sr.read().cc1if().bit_is_set())?;
+
+            // Reading CCR1 clears CC1IF, an overcapture means a period was lost in between
+            let value = tim14.ccr1.read().bits() as u16;
+            if tim14.sr.read().cc1of().bit_is_set() {
+                tim14.sr.modify(|_, w| w.cc1of().clear_bit());
+                return Err(Error::MissedCapture);
+            }
+            Ok(value)
+        };
+
+        let measure = || -> Result<u32, Error> {
+            // Discard the first capture which might have been taken mid-period
+            let mut last = capture()?;
+            let mut total: u32 = 0;
+            for _ in 0..CAPTURES {
+                let now = capture()?;
+                total += u32::from(now.wrapping_sub(last));
+                last = now;
+            }
+            Ok(total)
+        };
+        let total = measure();
+
+        tim14.cr1.modify(|_, w| w.cen().clear_bit());
+        tim14.ccer.reset();
+        rccr.apb1enr.modify(|_, w| w.tim14en().clear_bit());
+
+        let total = total?;
+
+        if total == 0 {
+            return Err(Error::Timeout);
+        }
+
+        let lsi_hz = (u64::from(tim_clk) * u64::from(CAPTURES * EDGES_PER_CAPTURE)
+            / u64::from(total)) as u32;
+        self.set_prescalers(lsi_hz)?;
+
+        Ok(lsi_hz)
+    }
+
+    /// The LSI frequency the prescalers are currently programmed for
+    pub fn lsi_hz(&self) -> u32 {
+        self.lsi_hz
+    }
+
+    /// Set the calendar date and time
+    pub fn set_datetime(&mut self, dt: &DateTime) -> Result<(), Error> {
+        let dt = DateTime::new(dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second)?;
+
+        let tr = bcd(dt.hour) << 16 | bcd(dt.minute) << 8 | bcd(dt.second);
+        let dr = bcd((dt.year - 2000) as u8) << 16
+            | u32::from(dt.weekday()) << 13
+            | bcd(dt.month) << 8
+            | bcd(dt.day);
+
+        self.modify(|rtc| {
+            rtc.tr.write(|w| unsafe { w.bits(tr) });
+            rtc.dr.write(|w| unsafe { w.bits(dr) });
+        })?;
+
+        self.synchronise()
+    }
+
+    /// Read the current calendar date and time
+    pub fn datetime(&mut self) -> DateTime {
+        // Reading TR locks the shadow registers until DR has been read
+        let tr = self.rtc.tr.read().bits();
+        let dr = self.rtc.dr.read().bits();
+
+        DateTime {
+            year: 2000 + u16::from(from_bcd((dr >> 16) & 0xff)),
+            month: from_bcd((dr >> 8) & 0x1f),
+            day: from_bcd(dr & 0x3f),
+            hour: from_bcd((tr >> 16) & 0x3f),
+            minute: from_bcd((tr >> 8) & 0x7f),
+            second: from_bcd(tr & 0x7f),
+        }
+    }
+
+    /// Program alarm A and enable its interrupt
+    ///
+    /// The alarm is routed to EXTI line 17 so it both raises the `RTC` interrupt and wakes the
+    /// MCU from Stop mode. The NVIC still needs to be unmasked by the caller.
+    pub fn set_alarm(&mut self, alarm: &Alarm, exti: &mut Exti) -> Result<(), Error> {
+        let bits = alarm.bits()?;
+
+        self.unlocked(|rtc| {
+            rtc.cr
+                .modify(|_, w| w.alrae().clear_bit().alraie().clear_bit());
+            wait_for(|| rtc.isr.read().alrawf().bit_is_set())?;
+            rtc.alrmar.write(|w| unsafe { w.bits(bits) });
+            rtc.isr.modify(|_, w| w.alraf().clear_bit());
+            rtc.cr.modify(|_, w| w.alrae().set_bit().alraie().set_bit());
+            Ok(())
+        })?;
+
+        let line = Line::<EXTI_LINE_ALARM>::internal();
+        exti.enable_interrupt(&line);
+        exti.set_edges(&line, true, false);
+
+        Ok(())
+    }
+
+    /// Disable alarm A and its interrupt
+    pub fn disable_alarm(&mut self, exti: &mut Exti) {
+        self.unlocked(|rtc| {
+            rtc.cr
+                .modify(|_, w| w.alrae().clear_bit().alraie().clear_bit());
+            Ok(())
+        })
+        .ok();
+
+        exti.disable_interrupt(&Line::<EXTI_LINE_ALARM>::internal());
+    }
+
+    /// Check and clear the alarm A flag, to be called from the `RTC` interrupt handler
+    pub fn check_alarm(&mut self, exti: &mut Exti) -> bool {
+        let fired = self.rtc.isr.read().alraf().bit_is_set();
+        if fired {
+            self.rtc.isr.modify(|_, w| w.alraf().clear_bit());
+        }
+        exti.clear_pending(&Line::<EXTI_LINE_ALARM>::internal());
+        fired
+    }
+
+    /// Enter Stop mode with the regulator in low-power mode until the alarm (or any other
+    /// enabled EXTI line) fires
+    ///
+    /// This is [`LowPower::stop`], which restores the clocks, followed by waiting for the calendar
+    /// to be up to date again.
+    pub fn stop_until_alarm(&mut self, power: &mut LowPower, scb: &mut SCB, rcc: &Rcc) -> Wakeup {
+        let wakeup = power.stop(scb, Regulator::LowPower, rcc);
+
+        // The calendar shadow registers are stale after leaving Stop mode
+        self.synchronise().ok();
+        wakeup
+    }
+
+    /// Release the RTC peripheral
+    pub fn release(self) -> RTC {
+        self.rtc
+    }
+
+    fn set_prescalers(&mut self, lsi_hz: u32) -> Result<(), Error> {
+        let (prediv_a, prediv_s) = prescalers(lsi_hz);
+
+        self.modify(|rtc| {
+            // The two prescalers have to be written in two separate accesses
+            rtc.prer.write(|w| unsafe { w.bits(prediv_s) });
+            rtc.prer
+                .write(|w| unsafe { w.bits(prediv_a << 16 | prediv_s) });
+        })?;
+
+        self.lsi_hz = lsi_hz;
+        Ok(())
+    }
+
+    /// Wait until the calendar shadow registers have been updated
+    fn synchronise(&mut self) -> Result<(), Error> {
+        let rtc = &self.rtc;
+        self.unlocked(|_| {
+            rtc.isr.modify(|_, w| w.rsf().clear_bit());
+            Ok(())
+        })?;
+        wait_for(|| self.rtc.isr.read().rsf().bit_is_set())
+    }
+
+    /// Run `f` with the RTC in initialisation mode
+    fn modify(&mut self, f: impl FnOnce(&RTC)) -> Result<(), Error> {
+        self.unlocked(|rtc| {
+            rtc.isr.modify(|_, w| w.init().set_bit());
+            let res = wait_for(|| rtc.isr.read().initf().bit_is_set());
+            if res.is_ok() {
+                f(rtc);
+            }
+            rtc.isr.modify(|_, w| w.init().clear_bit());
+            res
+        })
+    }
+
+    /// Run `f` with the RTC write protection disabled
+    fn unlocked<T>(&self, f: impl FnOnce(&RTC) -> Result<T, Error>) -> Result<T, Error> {
+        self.rtc.wpr.write(|w| unsafe { w.bits(0xca) });
+        self.rtc.wpr.write(|w| unsafe { w.bits(0x53) });
+        let res = f(&self.rtc);
+        self.rtc.wpr.write(|w| unsafe { w.bits(0xff) });
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: u16, month: u8, day: u8) -> DateTime {
+        DateTime::new(year, month, day, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn weekday() {
+        assert_eq!(date(2000, 1, 1).weekday(), 6);
+        assert_eq!(date(2020, 6, 1).weekday(), 1);
+        assert_eq!(date(2023, 1, 1).weekday(), 7);
+        assert_eq!(date(2024, 2, 29).weekday(), 4);
+        assert_eq!(date(2024, 3, 1).weekday(), 5);
+        assert_eq!(date(2099, 12, 31).weekday(), 4);
+    }
+
+    #[test]
+    fn month_lengths() {
+        assert_eq!(days_in_month(2021, 1), 31);
+        assert_eq!(days_in_month(2021, 2), 28);
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2000, 2), 29);
+        assert_eq!(days_in_month(2100, 2), 28);
+        assert_eq!(days_in_month(2021, 4), 30);
+        assert_eq!(days_in_month(2021, 12), 31);
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        assert_eq!(
+            DateTime::new(2021, 2, 29, 0, 0, 0),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            DateTime::new(1999, 12, 31, 0, 0, 0),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            DateTime::new(2021, 13, 1, 0, 0, 0),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(DateTime::new(2021, 1, 0, 0, 0, 0), Err(Error::InvalidValue));
+        assert_eq!(
+            DateTime::new(2021, 1, 1, 24, 0, 0),
+            Err(Error::InvalidValue)
+        );
+        assert!(DateTime::new(2024, 2, 29, 23, 59, 59).is_ok());
+    }
+
+    #[test]
+    fn bcd_round_trip() {
+        for value in 0..100 {
+            assert_eq!(from_bcd(bcd(value)), value);
+        }
+        assert_eq!(bcd(59), 0x59);
+    }
+
+    #[test]
+    fn alarm_bits() {
+        assert_eq!(Alarm::every_second().bits(), Ok(0x8080_8080));
+        assert_eq!(Alarm::every_minute(30).bits(), Ok(0x8080_8030));
+        assert_eq!(Alarm::daily(23, 59, 0).bits(), Ok(0x8023_5900));
+        assert_eq!(Alarm::every_minute(60).bits(), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn prescalers_for_1hz() {
+        assert_eq!(prescalers(40_000), (127, 312));
+        assert_eq!(prescalers(32_768), (127, 255));
+        assert_eq!(prescalers(0), (127, 0));
+    }
+}
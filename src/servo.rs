@@ -0,0 +1,373 @@
+//! Hobby servos and ESCs driven by timer PWM
+//!
+//! RC servos and electronic speed controllers expect a pulse of about 1 to 2 ms every 2.5 to
+//! 20 ms. [`timer`] sets up a [`Pwm`] counting in microseconds at 50 to 400 Hz, so up to four
+//! [`Servo`]s can be driven by the channels of one timer with a resolution of 1 µs.
+//!
+//! Each servo maps angles or throttle settings to pulse widths using its own [`Calibration`] and
+//! can approach its target with a limited slew rate, which is applied by calling
+//! [`Servo::update`] regularly, e.g. every 20 ms.
+
+use crate::hal::rcc::Rcc;
+
+use crate::pwm::{ChannelPin, Channels, Instance, Pwm, PwmOutput};
+use crate::time::{Duration, Hertz};
+
+/// Lowest supported pulse frequency
+pub const MIN_FREQUENCY: u32 = 50;
+
+/// Highest supported pulse frequency, as used by digital servos
+pub const MAX_FREQUENCY: u32 = 400;
+
+/// Set up `tim` for servo pulses at `frequency`, which is limited to 50 to 400 Hz
+pub fn timer<TIM: Instance, F: Into<Hertz>>(
+    tim: TIM,
+    frequency: F,
+    rcc: &mut Rcc,
+) -> (Pwm<TIM>, Channels<TIM>) {
+    let frequency = frequency.into().0.clamp(MIN_FREQUENCY, MAX_FREQUENCY);
+
+    let (mut pwm, channels) = Pwm::new(tim, Hertz(frequency), rcc);
+    pwm.set_timebase(Hertz(1_000_000), (1_000_000 / frequency) as u16);
+
+    (pwm, channels)
+}
+
+/// Pulse widths of a servo or ESC
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Calibration {
+    /// Pulse for the minimum angle or throttle in µs
+    pub min_us: u16,
+    /// Pulse for the maximum angle or throttle in µs
+    pub max_us: u16,
+    /// Offset added to all pulses in µs, e.g. to center a servo horn
+    ///
+    /// The trim shifts the whole range, so trimmed pulses may lie outside `min_us..=max_us`.
+    pub trim_us: i16,
+    /// Angle covered between the minimum and the maximum pulse in degrees
+    pub range_deg: u16,
+}
+
+impl Default for Calibration {
+    /// 1 to 2 ms for 180°
+    fn default() -> Self {
+        Self::new(1000, 2000)
+    }
+}
+
+impl Calibration {
+    /// Move between `min_us` and `max_us` over 180° without trim
+    pub const fn new(min_us: u16, max_us: u16) -> Self {
+        Self {
+            min_us,
+            max_us,
+            trim_us: 0,
+            range_deg: 180,
+        }
+    }
+
+    /// Add `trim_us` to all pulses
+    pub const fn with_trim(self, trim_us: i16) -> Self {
+        Self { trim_us, ..self }
+    }
+
+    /// Cover `range_deg` degrees between the minimum and the maximum pulse
+    pub const fn with_range(self, range_deg: u16) -> Self {
+        Self { range_deg, ..self }
+    }
+
+    /// Pulse for `numerator / denominator` of the way from the minimum to the maximum pulse,
+    /// limited to the calibrated range and then trimmed
+    fn pulse(&self, numerator: u32, denominator: u32) -> u16 {
+        let span = u32::from(self.max_us.saturating_sub(self.min_us));
+        let pulse = u32::from(self.min_us) + span * numerator.min(denominator) / denominator.max(1);
+        (pulse as i32 + i32::from(self.trim_us)).clamp(0, i32::from(u16::MAX)) as u16
+    }
+
+    /// Pulse for an angle in degrees from 0 to the range
+    pub fn angle(&self, deg: u16) -> u16 {
+        self.pulse(u32::from(deg), u32::from(self.range_deg))
+    }
+
+    /// Pulse for an ESC throttle in per mille, from 0 at the minimum to 1000 at the maximum pulse
+    pub fn throttle(&self, per_mille: u16) -> u16 {
+        self.pulse(u32::from(per_mille), 1000)
+    }
+
+    /// Pulse for a bidirectional ESC or continuous rotation servo in per mille, from -1000 at the
+    /// minimum through 0 at the center to 1000 at the maximum pulse
+    pub fn speed(&self, per_mille: i16) -> u16 {
+        let per_mille = per_mille.clamp(-1000, 1000);
+        self.pulse((i32::from(per_mille) + 1000) as u32, 2000)
+    }
+}
+
+/// Moves a pulse width towards its target with a limited slew rate
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ramp {
+    current_us: u16,
+    target_us: u16,
+    rate: Option<u32>,
+    remainder: u64,
+}
+
+impl Ramp {
+    /// Start at `pulse_us`, moving at most `rate` µs per second or instantly for `None`
+    pub const fn new(pulse_us: u16, rate: Option<u32>) -> Self {
+        Self {
+            current_us: pulse_us,
+            target_us: pulse_us,
+            rate,
+            remainder: 0,
+        }
+    }
+
+    /// The current pulse width
+    pub fn current(&self) -> u16 {
+        self.current_us
+    }
+
+    /// The pulse width moved towards
+    pub fn target(&self) -> u16 {
+        self.target_us
+    }
+
+    /// Whether the target has been reached
+    pub fn is_settled(&self) -> bool {
+        self.current_us == self.target_us
+    }
+
+    /// Move towards `pulse_us` from now on
+    pub fn set_target(&mut self, pulse_us: u16) {
+        self.target_us = pulse_us;
+        if self.rate.is_none() {
+            self.current_us = pulse_us;
+        }
+    }
+
+    /// Jump to `pulse_us` right away
+    pub fn jump(&mut self, pulse_us: u16) {
+        self.current_us = pulse_us;
+        self.target_us = pulse_us;
+        self.remainder = 0;
+    }
+
+    /// Change the slew rate in µs per second, `None` for no limit
+    pub fn set_rate(&mut self, rate: Option<u32>) {
+        self.rate = rate;
+        self.remainder = 0;
+        if rate.is_none() {
+            self.current_us = self.target_us;
+        }
+    }
+
+    /// Advance by `elapsed` and return the new pulse width
+    pub fn step(&mut self, elapsed: Duration) -> u16 {
+        let rate = match self.rate {
+            Some(rate) => u64::from(rate),
+            None => {
+                self.current_us = self.target_us;
+                return self.current_us;
+            }
+        };
+
+        if self.is_settled() {
+            self.remainder = 0;
+            return self.current_us;
+        }
+
+        // Keep the fraction of a µs so slow rates still make progress with short steps
+        let travel = elapsed.as_micros() * rate + self.remainder;
+        let step = (travel / 1_000_000).min(u64::from(u16::MAX)) as u16;
+        self.remainder = travel % 1_000_000;
+
+        self.current_us = if self.target_us > self.current_us {
+            self.current_us.saturating_add(step).min(self.target_us)
+        } else {
+            self.current_us.saturating_sub(step).max(self.target_us)
+        };
+        if self.is_settled() {
+            self.remainder = 0;
+        }
+
+        self.current_us
+    }
+}
+
+/// A servo or ESC on a channel of a timer set up via [`timer`]
+///
+/// No pulses are generated until the first position is set, which the servo jumps to directly.
+pub struct Servo<TIM: Instance, P: ChannelPin<TIM>> {
+    output: PwmOutput<TIM, P>,
+    calibration: Calibration,
+    ramp: Ramp,
+    enabled: bool,
+}
+
+impl<TIM: Instance, P: ChannelPin<TIM>> Servo<TIM, P> {
+    /// Drive a servo on `output` with the given calibration
+    pub fn new(mut output: PwmOutput<TIM, P>, calibration: Calibration) -> Self {
+        output.disable();
+
+        Self {
+            output,
+            calibration,
+            ramp: Ramp::new(calibration.min_us, None),
+            enabled: false,
+        }
+    }
+
+    /// Move at most `rate` µs per second
+    pub fn with_slew_rate(mut self, rate: u32) -> Self {
+        self.ramp.set_rate(Some(rate));
+        self
+    }
+
+    /// The calibration
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    /// Replace the calibration, which applies to the next position set
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    /// Change the slew rate in µs per second, `None` for moving instantly
+    pub fn set_slew_rate(&mut self, rate: Option<u32>) {
+        self.ramp.set_rate(rate);
+        self.apply();
+    }
+
+    /// Move to a pulse width of `pulse_us`, regardless of the calibration
+    pub fn set_pulse_us(&mut self, pulse_us: u16) {
+        if self.enabled {
+            self.ramp.set_target(pulse_us);
+        } else {
+            self.ramp.jump(pulse_us);
+            self.enabled = true;
+            self.output.enable();
+        }
+        self.apply();
+    }
+
+    /// Move to an angle in degrees
+    pub fn set_angle(&mut self, deg: u16) {
+        self.set_pulse_us(self.calibration.angle(deg));
+    }
+
+    /// Set the throttle of an ESC in per mille
+    pub fn set_throttle(&mut self, per_mille: u16) {
+        self.set_pulse_us(self.calibration.throttle(per_mille));
+    }
+
+    /// Set the speed of a bidirectional ESC or continuous rotation servo in per mille
+    pub fn set_speed(&mut self, per_mille: i16) {
+        self.set_pulse_us(self.calibration.speed(per_mille));
+    }
+
+    /// The pulse width currently generated
+    pub fn pulse_us(&self) -> u16 {
+        self.ramp.current()
+    }
+
+    /// Whether the target position has been reached
+    pub fn is_settled(&self) -> bool {
+        self.ramp.is_settled()
+    }
+
+    /// Advance the ramp by the time `elapsed` since the last call
+    pub fn update(&mut self, elapsed: Duration) {
+        self.ramp.step(elapsed);
+        self.apply();
+    }
+
+    /// Stop generating pulses, which lets most servos go limp
+    ///
+    /// The next position set is jumped to directly.
+    pub fn disable(&mut self) {
+        self.output.disable();
+        self.enabled = false;
+    }
+
+    /// Stop generating pulses and release the output
+    pub fn release(mut self) -> PwmOutput<TIM, P> {
+        self.disable();
+        self.output
+    }
+
+    fn apply(&mut self) {
+        self.output.set_duty(self.ramp.current());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trim_shifts_the_whole_range() {
+        let calibration = Calibration::new(1000, 2000).with_trim(-20);
+
+        assert_eq!(calibration.angle(0), 980);
+        assert_eq!(calibration.angle(90), 1480);
+        assert_eq!(calibration.angle(180), 1980);
+    }
+
+    #[test]
+    fn limits_before_trimming() {
+        let calibration = Calibration::new(1000, 2000).with_trim(15);
+
+        assert_eq!(calibration.angle(200), 2015);
+        assert_eq!(calibration.throttle(2000), 2015);
+        assert_eq!(calibration.speed(-2000), 1015);
+    }
+
+    #[test]
+    fn ramp_carries_fractions_of_a_microsecond() {
+        let mut ramp = Ramp::new(1000, Some(100));
+        ramp.set_target(2000);
+
+        // 0.3 µs per step
+        for _ in 0..3 {
+            assert_eq!(ramp.step(Duration::from_millis(3)), 1000);
+        }
+        assert_eq!(ramp.step(Duration::from_millis(3)), 1001);
+    }
+
+    #[test]
+    fn ramp_stops_at_target() {
+        let mut ramp = Ramp::new(1000, Some(1000));
+
+        ramp.set_target(1010);
+        assert_eq!(ramp.step(Duration::from_secs(1)), 1010);
+        assert!(ramp.is_settled());
+
+        ramp.set_target(900);
+        assert_eq!(ramp.step(Duration::from_millis(50)), 960);
+        assert_eq!(ramp.step(Duration::from_secs(1)), 900);
+        assert!(ramp.is_settled());
+    }
+
+    #[test]
+    fn ramp_without_rate_jumps() {
+        let mut ramp = Ramp::new(1000, None);
+
+        ramp.set_target(1500);
+        assert_eq!(ramp.current(), 1500);
+        assert_eq!(ramp.step(Duration::from_micros(0)), 1500);
+    }
+
+    #[test]
+    fn changing_rate_drops_fraction() {
+        let mut ramp = Ramp::new(1000, Some(100));
+        ramp.set_target(2000);
+
+        assert_eq!(ramp.step(Duration::from_millis(9)), 1000);
+        ramp.set_rate(Some(100));
+        assert_eq!(ramp.step(Duration::from_millis(9)), 1000);
+
+        ramp.set_rate(None);
+        assert_eq!(ramp.current(), 2000);
+    }
+}
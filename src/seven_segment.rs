@@ -0,0 +1,370 @@
+//! Driver for multiplexed 7-segment displays
+//!
+//! The segment lines of all digits are connected in parallel and each digit has its own enable
+//! line, so only one digit is lit at a time. Calling [`Multiplexed::refresh`] periodically, e.g.
+//! from a timer interrupt, cycles through the digits fast enough for the eye to see all of them.
+//!
+//! Digits are indexed from the left, i.e. index 0 is the most significant digit. Glyphs are
+//! encoded with segment `a` in bit 0 up to segment `g` in bit 6 and the decimal point in bit 7.
+
+use embedded_hal::digital::v2::OutputPin;
+
+/// Encoded glyph with all segments off
+pub const BLANK: u8 = 0;
+/// Encoded glyph of a minus sign, i.e. only segment `g`
+pub const MINUS: u8 = 1 << 6;
+/// Bit of the decimal point in an encoded glyph
+pub const DECIMAL_POINT: u8 = 1 << 7;
+
+/// Number of refreshes each digit is selected for, which is also the number of brightness steps
+pub const BRIGHTNESS_MAX: u8 = 8;
+
+// Hexadecimal digits 0-F
+const GLYPHS: [u8; 16] = [
+    0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f, 0x77, 0x7c, 0x39, 0x5e, 0x79, 0x71,
+];
+
+/// Formatting errors
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The value has more digits than the display
+    Overflow,
+}
+
+/// Encode a single hexadecimal digit, only the lower four bits of `digit` are used
+pub fn glyph(digit: u8) -> u8 {
+    GLYPHS[usize::from(digit & 0xf)]
+}
+
+/// Format `value` as hexadecimal number with leading zeros
+pub fn hex<const N: usize>(value: u32) -> Result<[u8; N], Error> {
+    let mut glyphs = [BLANK; N];
+    let mut rest = value;
+
+    for slot in glyphs.iter_mut().rev() {
+        *slot = glyph(rest as u8);
+        rest >>= 4;
+    }
+
+    if rest == 0 {
+        Ok(glyphs)
+    } else {
+        Err(Error::Overflow)
+    }
+}
+
+/// Format `value` as right aligned decimal number without leading zeros
+pub fn decimal<const N: usize>(value: u32) -> Result<[u8; N], Error> {
+    let mut glyphs = [BLANK; N];
+    let used = right_align(&mut glyphs, value)?;
+
+    if used == 0 && N > 0 {
+        glyphs[N - 1] = glyph(0);
+    }
+
+    Ok(glyphs)
+}
+
+/// Format `value` as right aligned decimal number with a leading minus sign if negative
+pub fn signed<const N: usize>(value: i32) -> Result<[u8; N], Error> {
+    if value >= 0 {
+        return decimal(value as u32);
+    }
+
+    let mut glyphs = [BLANK; N];
+    let used = right_align(&mut glyphs, value.unsigned_abs())?;
+
+    if used == N {
+        return Err(Error::Overflow);
+    }
+    glyphs[N - 1 - used] = MINUS;
+
+    Ok(glyphs)
+}
+
+/// Write the decimal digits of `value` right aligned into `glyphs`, returning their number
+fn right_align(glyphs: &mut [u8], mut value: u32) -> Result<usize, Error> {
+    let mut used = 0;
+
+    while value != 0 {
+        if used == glyphs.len() {
+            return Err(Error::Overflow);
+        }
+        glyphs[glyphs.len() - 1 - used] = glyph((value % 10) as u8);
+        value /= 10;
+        used += 1;
+    }
+
+    Ok(used)
+}
+
+/// Level which turns a segment or digit on
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Active {
+    High,
+    Low,
+}
+
+/// Multiplexed display with `N` digits
+pub struct Multiplexed<SEG, DIG, const N: usize> {
+    segments: [SEG; 7],
+    decimal_point: Option<SEG>,
+    digits: [DIG; N],
+    glyphs: [u8; N],
+    segment_level: Active,
+    digit_level: Active,
+    brightness: u8,
+    current: usize,
+    slot: u8,
+}
+
+impl<SEG, DIG, const N: usize> Multiplexed<SEG, DIG, N>
+where
+    SEG: OutputPin,
+    DIG: OutputPin,
+{
+    /// Create a display from the segment pins `a` to `g` and the digit enable pins
+    ///
+    /// Segments and digits are active high and the display starts out blank at full brightness.
+    pub fn new(segments: [SEG; 7], digits: [DIG; N]) -> Self {
+        let mut display = Self {
+            segments,
+            decimal_point: None,
+            digits,
+            glyphs: [BLANK; N],
+            segment_level: Active::High,
+            digit_level: Active::High,
+            brightness: BRIGHTNESS_MAX,
+            current: 0,
+            slot: 0,
+        };
+        display.blank_all();
+        display
+    }
+
+    /// Add the pin of the decimal point segment
+    pub fn with_decimal_point(mut self, pin: SEG) -> Self {
+        self.decimal_point = Some(pin);
+        self.blank_all();
+        self
+    }
+
+    /// Set the active levels, e.g. for common anode displays or inverting digit drivers
+    pub fn with_active_levels(mut self, segments: Active, digits: Active) -> Self {
+        self.segment_level = segments;
+        self.digit_level = digits;
+        self.blank_all();
+        self
+    }
+
+    /// Set the brightness from 0 (off) to [`BRIGHTNESS_MAX`]
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness.min(BRIGHTNESS_MAX);
+    }
+
+    /// Current brightness
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Show encoded glyphs, keeping the decimal points which are already set
+    pub fn set_glyphs(&mut self, glyphs: [u8; N]) {
+        for (current, new) in self.glyphs.iter_mut().zip(glyphs.iter()) {
+            *current = (*current & DECIMAL_POINT) | new;
+        }
+    }
+
+    /// Currently shown glyphs
+    pub fn glyphs(&self) -> [u8; N] {
+        self.glyphs
+    }
+
+    /// Show `value` as hexadecimal number
+    pub fn show_hex(&mut self, value: u32) -> Result<(), Error> {
+        self.set_glyphs(hex(value)?);
+        Ok(())
+    }
+
+    /// Show `value` as decimal number
+    pub fn show_decimal(&mut self, value: u32) -> Result<(), Error> {
+        self.set_glyphs(decimal(value)?);
+        Ok(())
+    }
+
+    /// Show `value` as signed decimal number
+    pub fn show_signed(&mut self, value: i32) -> Result<(), Error> {
+        self.set_glyphs(signed(value)?);
+        Ok(())
+    }
+
+    /// Turn the decimal point of digit `index` on or off
+    pub fn set_decimal_point(&mut self, index: usize, on: bool) {
+        if let Some(glyph) = self.glyphs.get_mut(index) {
+            if on {
+                *glyph |= DECIMAL_POINT;
+            } else {
+                *glyph &= !DECIMAL_POINT;
+            }
+        }
+    }
+
+    /// Turn all segments and decimal points off
+    pub fn clear(&mut self) {
+        self.glyphs = [BLANK; N];
+    }
+
+    /// Advance the multiplexing, to be called periodically from a timer interrupt
+    ///
+    /// Each digit is selected for [`BRIGHTNESS_MAX`] calls and lit for as many of them as the
+    /// brightness, so the calls need to happen at `N * BRIGHTNESS_MAX` times the desired refresh
+    /// rate, e.g. 2.4 kHz for 3 digits at 100 Hz.
+    pub fn refresh(&mut self) {
+        if N == 0 {
+            return;
+        }
+
+        if self.slot == 0 {
+            // Switch the digit off before changing the segments so they don't ghost into it
+            self.set_digit(self.current, false);
+            self.current = (self.current + 1) % N;
+            self.set_segments(self.glyphs[self.current]);
+            if self.brightness > 0 {
+                self.set_digit(self.current, true);
+            }
+        } else if self.slot == self.brightness {
+            self.set_digit(self.current, false);
+        }
+
+        self.slot = (self.slot + 1) % BRIGHTNESS_MAX;
+    }
+
+    /// Switch the display off and release the pins
+    pub fn release(mut self) -> ([SEG; 7], Option<SEG>, [DIG; N]) {
+        self.blank_all();
+        (self.segments, self.decimal_point, self.digits)
+    }
+
+    fn blank_all(&mut self) {
+        for index in 0..N {
+            self.set_digit(index, false);
+        }
+        self.set_segments(BLANK);
+    }
+
+    fn set_digit(&mut self, index: usize, on: bool) {
+        let level = self.digit_level;
+        set(&mut self.digits[index], level, on);
+    }
+
+    fn set_segments(&mut self, glyph: u8) {
+        let level = self.segment_level;
+        for (bit, pin) in self.segments.iter_mut().enumerate() {
+            set(pin, level, glyph & (1 << bit) != 0);
+        }
+        if let Some(pin) = self.decimal_point.as_mut() {
+            set(pin, level, glyph & DECIMAL_POINT != 0);
+        }
+    }
+}
+
+fn set<P: OutputPin>(pin: &mut P, level: Active, on: bool) {
+    if on == (level == Active::High) {
+        pin.set_high().ok();
+    } else {
+        pin.set_low().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::convert::Infallible;
+
+    #[derive(Default)]
+    struct Pin(bool);
+
+    impl OutputPin for Pin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0 = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0 = true;
+            Ok(())
+        }
+    }
+
+    fn display() -> Multiplexed<Pin, Pin, 4> {
+        Multiplexed::new(Default::default(), Default::default())
+    }
+
+    #[test]
+    fn hex_has_leading_zeros() {
+        assert_eq!(
+            hex::<4>(0x2f),
+            Ok([glyph(0), glyph(0), glyph(2), glyph(0xf)])
+        );
+        assert_eq!(hex::<2>(0xff), Ok([glyph(0xf), glyph(0xf)]));
+        assert_eq!(hex::<2>(0x100), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn decimal_is_right_aligned() {
+        assert_eq!(decimal::<4>(42), Ok([BLANK, BLANK, glyph(4), glyph(2)]));
+        assert_eq!(decimal::<4>(0), Ok([BLANK, BLANK, BLANK, glyph(0)]));
+        assert_eq!(decimal::<4>(9999), Ok([glyph(9); 4]));
+    }
+
+    #[test]
+    fn decimal_with_too_many_digits_overflows() {
+        assert_eq!(decimal::<4>(10_000), Err(Error::Overflow));
+        assert_eq!(decimal::<0>(1), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn negative_gets_minus_sign() {
+        assert_eq!(signed::<4>(-42), Ok([BLANK, MINUS, glyph(4), glyph(2)]));
+        assert_eq!(signed::<4>(-999), Ok([MINUS, glyph(9), glyph(9), glyph(9)]));
+        assert_eq!(signed::<4>(17), Ok([BLANK, BLANK, glyph(1), glyph(7)]));
+        assert_eq!(signed::<4>(i32::MIN), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn negative_without_room_for_minus_overflows() {
+        assert_eq!(signed::<4>(-1000), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn decimal_point_survives_new_value() {
+        let mut display = display();
+        display.set_decimal_point(1, true);
+        display.show_decimal(1234).unwrap();
+        assert_eq!(
+            display.glyphs(),
+            [glyph(1), glyph(2) | DECIMAL_POINT, glyph(3), glyph(4)]
+        );
+
+        display.set_decimal_point(1, false);
+        display.set_decimal_point(7, true);
+        assert_eq!(display.glyphs(), [glyph(1), glyph(2), glyph(3), glyph(4)]);
+    }
+
+    #[test]
+    fn refresh_shows_decimal_point() {
+        let mut display = display().with_decimal_point(Pin::default());
+        display.show_hex(0x0000).unwrap();
+        display.set_decimal_point(1, true);
+
+        // The first refresh moves on from digit 0 to digit 1
+        display.refresh();
+        let lit: Vec<bool> = display.segments.iter().map(|pin| pin.0).collect();
+        assert_eq!(lit, [true, true, true, true, true, true, false]);
+        assert!(display.decimal_point.as_ref().unwrap().0);
+        let selected: Vec<bool> = display.digits.iter().map(|pin| pin.0).collect();
+        assert_eq!(selected, [false, true, false, false]);
+    }
+}
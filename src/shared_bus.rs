@@ -0,0 +1,501 @@
+//! Sharing I2C1 and SPI1 between several drivers
+//!
+//! The bus is moved into a [`SharedBus`] which hands out proxies, one per driver. Every access
+//! through a proxy runs inside a critical section, so proxies can be used from the main loop and
+//! interrupt handlers alike. For SPI each proxy owns the chip select pin of its device and
+//! asserts it for the duration of an access.
+//!
+//! Proxies implement the blocking `embedded-hal` 0.2 traits as well as the `I2c` and `SpiDevice`
+//! traits of `embedded-hal` 1.0, so drivers for either version can share a bus.
+//!
+//! Keep in mind that interrupts are disabled for the whole access, e.g. about 1 ms for writing 100
+//! bytes at 1 MHz on I2C.
+
+use crate::hal::rcc::Clocks;
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use embedded_hal::{blocking::i2c, blocking::spi, digital::v2::OutputPin};
+use embedded_hal_1::{i2c as i2c1, spi as spi1};
+
+/// A bus shared between several drivers
+pub struct SharedBus<BUS> {
+    bus: Mutex<RefCell<BUS>>,
+}
+
+impl<BUS> SharedBus<BUS> {
+    /// Take over a bus, e.g. I2C1 set up via `I2c::i2c1` or SPI1 set up via `Spi::spi1`
+    pub const fn new(bus: BUS) -> Self {
+        Self {
+            bus: Mutex::new(RefCell::new(bus)),
+        }
+    }
+
+    /// Run `f` with exclusive access to the bus
+    pub fn lock<R>(&self, f: impl FnOnce(&mut BUS) -> R) -> R {
+        cortex_m::interrupt::free(|cs| f(&mut *self.bus.borrow(cs).borrow_mut()))
+    }
+
+    /// Proxy for a driver of an I2C device
+    pub fn i2c(&self) -> I2cProxy<'_, BUS> {
+        I2cProxy { bus: self }
+    }
+
+    /// Proxy for a driver of an SPI device selected by `cs`, which is driven high right away
+    ///
+    /// The clocks are used to time delays requested via `embedded-hal` 1.0 `SpiDevice`.
+    pub fn spi_device<CS: OutputPin>(
+        &self,
+        mut cs: CS,
+        clocks: &Clocks,
+    ) -> SpiDeviceProxy<'_, BUS, CS> {
+        cs.set_high().ok();
+
+        SpiDeviceProxy {
+            bus: self,
+            cs,
+            cycles_per_us: clocks.sysclk().0 / 1_000_000,
+        }
+    }
+}
+
+/// Largest combined length of adjacent reads or writes in an `embedded-hal` 1.0 transaction
+pub const MERGE_BUFFER: usize = 32;
+
+/// Errors of `embedded-hal` 0.2 I2C buses which tell what went wrong
+pub trait BusError: core::fmt::Debug {
+    /// The `embedded-hal` 1.0 equivalent
+    fn kind(&self) -> i2c1::ErrorKind;
+}
+
+impl BusError for crate::hal::i2c::Error {
+    fn kind(&self) -> i2c1::ErrorKind {
+        match self {
+            crate::hal::i2c::Error::NACK => {
+                i2c1::ErrorKind::NoAcknowledge(i2c1::NoAcknowledgeSource::Unknown)
+            }
+            crate::hal::i2c::Error::BUS => i2c1::ErrorKind::Bus,
+            _ => i2c1::ErrorKind::Other,
+        }
+    }
+}
+
+impl BusError for crate::i2c_timeout::Error {
+    fn kind(&self) -> i2c1::ErrorKind {
+        use crate::i2c_timeout::Error;
+
+        match self {
+            Error::Nack => i2c1::ErrorKind::NoAcknowledge(i2c1::NoAcknowledgeSource::Unknown),
+            Error::Bus => i2c1::ErrorKind::Bus,
+            Error::ArbitrationLoss => i2c1::ErrorKind::ArbitrationLoss,
+            Error::Timeout => i2c1::ErrorKind::Other,
+        }
+    }
+}
+
+/// Error of an `embedded-hal` 1.0 access through an [`I2cProxy`]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum I2cError<E> {
+    /// Error of the bus
+    Bus(E),
+    /// The transaction can't be done with the `embedded-hal` 0.2 operations of the bus, i.e. it
+    /// writes after reading or adjacent operations exceed [`MERGE_BUFFER`]
+    Unsupported,
+}
+
+impl<E: BusError> i2c1::Error for I2cError<E> {
+    fn kind(&self) -> i2c1::ErrorKind {
+        match self {
+            I2cError::Bus(e) => e.kind(),
+            I2cError::Unsupported => i2c1::ErrorKind::Other,
+        }
+    }
+}
+
+/// Handle to a shared I2C bus
+pub struct I2cProxy<'a, BUS> {
+    bus: &'a SharedBus<BUS>,
+}
+
+impl<BUS: i2c::Write> i2c::Write for I2cProxy<'_, BUS> {
+    type Error = BUS::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.bus.lock(|bus| bus.write(address, bytes))
+    }
+}
+
+impl<BUS: i2c::Read> i2c::Read for I2cProxy<'_, BUS> {
+    type Error = BUS::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus.lock(|bus| bus.read(address, buffer))
+    }
+}
+
+impl<BUS: i2c::WriteRead> i2c::WriteRead for I2cProxy<'_, BUS> {
+    type Error = BUS::Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.bus.lock(|bus| bus.write_read(address, bytes, buffer))
+    }
+}
+
+impl<BUS, E> i2c1::ErrorType for I2cProxy<'_, BUS>
+where
+    BUS: i2c::Write<Error = E> + i2c::Read<Error = E> + i2c::WriteRead<Error = E>,
+    E: BusError,
+{
+    type Error = I2cError<E>;
+}
+
+/// Split a transaction into the writes and the reads following them
+fn split_directions<'o, 'a, E>(
+    operations: &'o mut [i2c1::Operation<'a>],
+) -> Result<(&'o mut [i2c1::Operation<'a>], &'o mut [i2c1::Operation<'a>]), I2cError<E>> {
+    let first_read = operations
+        .iter()
+        .position(|operation| matches!(operation, i2c1::Operation::Read(_)))
+        .unwrap_or(operations.len());
+    let (writes, reads) = operations.split_at_mut(first_read);
+
+    if reads
+        .iter()
+        .any(|operation| matches!(operation, i2c1::Operation::Write(_)))
+    {
+        Err(I2cError::Unsupported)
+    } else {
+        Ok((writes, reads))
+    }
+}
+
+/// Bytes of adjacent writes, copied into `buffer` if there is more than one
+fn merge_writes<'b, E>(
+    writes: &'b [i2c1::Operation<'_>],
+    buffer: &'b mut [u8; MERGE_BUFFER],
+) -> Result<&'b [u8], I2cError<E>> {
+    if let [i2c1::Operation::Write(bytes)] = writes {
+        return Ok(*bytes);
+    }
+
+    let mut len = 0;
+    for operation in writes {
+        if let i2c1::Operation::Write(bytes) = operation {
+            buffer
+                .get_mut(len..len + bytes.len())
+                .ok_or(I2cError::Unsupported)?
+                .copy_from_slice(bytes);
+            len += bytes.len();
+        }
+    }
+
+    Ok(&buffer[..len])
+}
+
+/// Run `read` on the buffer of a single read or on a merged one, which is then distributed
+fn merge_reads<E>(
+    reads: &mut [i2c1::Operation<'_>],
+    read: impl FnOnce(&mut [u8]) -> Result<(), E>,
+) -> Result<(), I2cError<E>> {
+    if let [i2c1::Operation::Read(buffer)] = reads {
+        return read(&mut **buffer).map_err(I2cError::Bus);
+    }
+
+    let len = reads
+        .iter()
+        .map(|operation| match operation {
+            i2c1::Operation::Read(buffer) => buffer.len(),
+            i2c1::Operation::Write(_) => 0,
+        })
+        .sum();
+    let mut merged = [0; MERGE_BUFFER];
+    let merged = merged.get_mut(..len).ok_or(I2cError::Unsupported)?;
+    read(merged).map_err(I2cError::Bus)?;
+
+    let mut rest = &merged[..];
+    for operation in reads.iter_mut() {
+        if let i2c1::Operation::Read(buffer) = operation {
+            let (head, tail) = rest.split_at(buffer.len());
+            buffer.copy_from_slice(head);
+            rest = tail;
+        }
+    }
+
+    Ok(())
+}
+
+/// Transactions are mapped onto the `embedded-hal` 0.2 operations: writes followed by reads
+/// become a `write_read` with a repeated start, writes or reads alone a `write` or `read`.
+/// Adjacent operations of the same direction are merged into one transfer, which limits their
+/// combined length to [`MERGE_BUFFER`]. Transactions writing after a read aren't supported.
+impl<BUS, E> i2c1::I2c for I2cProxy<'_, BUS>
+where
+    BUS: i2c::Write<Error = E> + i2c::Read<Error = E> + i2c::WriteRead<Error = E>,
+    E: BusError,
+{
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [i2c1::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let (writes, reads) = split_directions(operations)?;
+        let mut buffer = [0; MERGE_BUFFER];
+        let bytes = merge_writes(writes, &mut buffer)?;
+
+        self.bus
+            .lock(|bus| match (writes.is_empty(), reads.is_empty()) {
+                (true, true) => Ok(()),
+                (false, true) => bus.write(address, bytes).map_err(I2cError::Bus),
+                (true, false) => merge_reads(reads, |buffer| bus.read(address, buffer)),
+                (false, false) => {
+                    merge_reads(reads, |buffer| bus.write_read(address, bytes, buffer))
+                }
+            })
+    }
+}
+
+/// Errors of an access through a [`SpiDeviceProxy`]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpiDeviceError<E, PINE> {
+    /// Error of the bus
+    Spi(E),
+    /// Error driving the chip select pin
+    ChipSelect(PINE),
+}
+
+impl<E: core::fmt::Debug, PINE: core::fmt::Debug> spi1::Error for SpiDeviceError<E, PINE> {
+    fn kind(&self) -> spi1::ErrorKind {
+        match self {
+            SpiDeviceError::Spi(_) => spi1::ErrorKind::Other,
+            SpiDeviceError::ChipSelect(_) => spi1::ErrorKind::ChipSelectFault,
+        }
+    }
+}
+
+/// Handle to a device on a shared SPI bus
+pub struct SpiDeviceProxy<'a, BUS, CS> {
+    bus: &'a SharedBus<BUS>,
+    cs: CS,
+    cycles_per_us: u32,
+}
+
+impl<BUS, CS> SpiDeviceProxy<'_, BUS, CS>
+where
+    BUS: spi::Transfer<u8>,
+    CS: OutputPin,
+{
+    /// Release the chip select pin
+    pub fn release(self) -> CS {
+        self.cs
+    }
+
+    /// Run `f` with the bus locked and the device selected
+    fn selected<R>(
+        &mut self,
+        f: impl FnOnce(&mut BUS, u32) -> Result<R, BUS::Error>,
+    ) -> Result<R, SpiDeviceError<BUS::Error, CS::Error>> {
+        let cs = &mut self.cs;
+        let cycles_per_us = self.cycles_per_us;
+
+        self.bus.lock(|bus| {
+            cs.set_low().map_err(SpiDeviceError::ChipSelect)?;
+            let res = f(bus, cycles_per_us).map_err(SpiDeviceError::Spi);
+            cs.set_high().map_err(SpiDeviceError::ChipSelect)?;
+            res
+        })
+    }
+}
+
+/// Write `bytes` via transfers, which only return once every byte has been clocked out, so the
+/// device is not deselected too early
+fn write<BUS: spi::Transfer<u8>>(bus: &mut BUS, bytes: &[u8]) -> Result<(), BUS::Error> {
+    let mut chunk = [0; 16];
+
+    for bytes in bytes.chunks(chunk.len()) {
+        let chunk = &mut chunk[..bytes.len()];
+        chunk.copy_from_slice(bytes);
+        bus.transfer(chunk)?;
+    }
+
+    Ok(())
+}
+
+impl<BUS, CS> spi::Transfer<u8> for SpiDeviceProxy<'_, BUS, CS>
+where
+    BUS: spi::Transfer<u8>,
+    CS: OutputPin,
+{
+    type Error = SpiDeviceError<BUS::Error, CS::Error>;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        self.selected(|bus, _| bus.transfer(words).map(|_| ()))?;
+        Ok(words)
+    }
+}
+
+impl<BUS, CS> spi::Write<u8> for SpiDeviceProxy<'_, BUS, CS>
+where
+    BUS: spi::Transfer<u8>,
+    CS: OutputPin,
+{
+    type Error = SpiDeviceError<BUS::Error, CS::Error>;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.selected(|bus, _| write(bus, words))
+    }
+}
+
+impl<BUS, CS> spi1::ErrorType for SpiDeviceProxy<'_, BUS, CS>
+where
+    BUS: spi::Transfer<u8>,
+    BUS::Error: core::fmt::Debug,
+    CS: OutputPin,
+    CS::Error: core::fmt::Debug,
+{
+    type Error = SpiDeviceError<BUS::Error, CS::Error>;
+}
+
+impl<BUS, CS> spi1::SpiDevice for SpiDeviceProxy<'_, BUS, CS>
+where
+    BUS: spi::Transfer<u8>,
+    BUS::Error: core::fmt::Debug,
+    CS: OutputPin,
+    CS::Error: core::fmt::Debug,
+{
+    fn transaction(
+        &mut self,
+        operations: &mut [spi1::Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        self.selected(|bus, cycles_per_us| {
+            for operation in operations.iter_mut() {
+                match operation {
+                    spi1::Operation::Read(buffer) => {
+                        buffer.iter_mut().for_each(|byte| *byte = 0);
+                        bus.transfer(buffer)?;
+                    }
+                    spi1::Operation::Write(bytes) => write(bus, bytes)?,
+                    spi1::Operation::Transfer(read, bytes) => {
+                        let common = read.len().min(bytes.len());
+                        read[..common].copy_from_slice(&bytes[..common]);
+                        read[common..].iter_mut().for_each(|byte| *byte = 0);
+                        bus.transfer(read)?;
+                        write(bus, &bytes[common..])?;
+                    }
+                    spi1::Operation::TransferInPlace(words) => {
+                        bus.transfer(words)?;
+                    }
+                    spi1::Operation::DelayNs(ns) => {
+                        let cycles = u64::from(*ns) * u64::from(cycles_per_us) / 1000;
+                        cortex_m::asm::delay(cycles as u32 + 1);
+                    }
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use embedded_hal_1::i2c::Operation;
+
+    type Result<T> = core::result::Result<T, I2cError<()>>;
+
+    /// Fill `buffer` with incrementing bytes like a target would send them
+    fn fill(buffer: &mut [u8]) -> core::result::Result<(), ()> {
+        for (index, byte) in buffer.iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn writes_precede_reads() {
+        let mut buffer = [0; 2];
+        let mut operations = [Operation::Write(&[1]), Operation::Read(&mut buffer)];
+        let (writes, reads) = split_directions::<()>(&mut operations).unwrap();
+        assert_eq!((writes.len(), reads.len()), (1, 1));
+
+        let mut operations = [Operation::Write(&[1]), Operation::Write(&[2])];
+        let (writes, reads) = split_directions::<()>(&mut operations).unwrap();
+        assert_eq!((writes.len(), reads.len()), (2, 0));
+    }
+
+    #[test]
+    fn write_after_read_is_unsupported() {
+        let mut buffer = [0; 1];
+        let mut operations = [Operation::Read(&mut buffer), Operation::Write(&[1])];
+        assert!(split_directions::<()>(&mut operations).is_err());
+    }
+
+    #[test]
+    fn adjacent_writes_are_merged() {
+        let mut buffer = [0; MERGE_BUFFER];
+        let operations = [Operation::Write(&[1, 2]), Operation::Write(&[3])];
+        let bytes: Result<&[u8]> = merge_writes(&operations, &mut buffer);
+        assert_eq!(bytes, Ok(&[1, 2, 3][..]));
+    }
+
+    #[test]
+    fn single_write_has_no_limit() {
+        let mut buffer = [0; MERGE_BUFFER];
+        let long = [0xa5; 2 * MERGE_BUFFER];
+        let operations = [Operation::Write(&long)];
+        let bytes: Result<&[u8]> = merge_writes(&operations, &mut buffer);
+        assert_eq!(bytes, Ok(&long[..]));
+
+        let operations = [
+            Operation::Write(&long[..MERGE_BUFFER]),
+            Operation::Write(&[1]),
+        ];
+        let bytes: Result<&[u8]> = merge_writes(&operations, &mut buffer);
+        assert_eq!(bytes, Err(I2cError::Unsupported));
+    }
+
+    #[test]
+    fn adjacent_reads_are_merged() {
+        let (mut first, mut second) = ([0; 2], [0; 3]);
+        let mut operations = [Operation::Read(&mut first), Operation::Read(&mut second)];
+
+        let mut length = 0;
+        let result: Result<()> = merge_reads(&mut operations, |buffer| {
+            length = buffer.len();
+            fill(buffer)
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(length, 5);
+        assert_eq!((first, second), ([0, 1], [2, 3, 4]));
+    }
+
+    #[test]
+    fn too_long_reads_are_unsupported() {
+        let (mut first, mut second) = ([0; MERGE_BUFFER], [0; 1]);
+        let mut operations = [Operation::Read(&mut first), Operation::Read(&mut second)];
+        let result: Result<()> = merge_reads(&mut operations, fill);
+        assert_eq!(result, Err(I2cError::Unsupported));
+    }
+
+    #[test]
+    fn nack_is_reported() {
+        use crate::i2c_timeout::Error;
+        use i2c1::Error as _;
+
+        assert_eq!(
+            I2cError::Bus(Error::Nack).kind(),
+            i2c1::ErrorKind::NoAcknowledge(i2c1::NoAcknowledgeSource::Unknown)
+        );
+        assert_eq!(
+            I2cError::<Error>::Unsupported.kind(),
+            i2c1::ErrorKind::Other
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use super::{Args, Command, Context, Error};
+
+use crate::hal::stm32::RCC;
+
+use crate::header::Pin;
+use crate::i2c_tools;
+use crate::signature;
+use crate::time::{Duration, Monotonic};
+
+use core::fmt::Write;
+
+use cortex_m::peripheral::SCB;
+
+// RCC_CSR reset flags with their names
+const RESET_FLAGS: [(u32, &str); 8] = [
+    (1 << 31, "low-power"),
+    (1 << 30, "window watchdog"),
+    (1 << 29, "independent watchdog"),
+    (1 << 28, "software"),
+    (1 << 27, "power-on"),
+    (1 << 26, "pin"),
+    (1 << 25, "option byte loader"),
+    (1 << 23, "1.8 V domain"),
+];
+
+// Reference voltage of the board in mV
+const VDDA_MV: u32 = 3300;
+
+/// The built-in commands besides `help`, which is handled by the shell itself
+pub(super) fn commands<C: Context>() -> [Command<C>; 5] {
+    [
+        Command {
+            name: "info",
+            args: "",
+            help: "Show firmware, chip and reset information",
+            run: info::<C>,
+        },
+        Command {
+            name: "reset",
+            args: "",
+            help: "Reset the MCU",
+            run: reset::<C>,
+        },
+        Command {
+            name: "i2c scan",
+            args: "",
+            help: "List the devices on I2C1",
+            run: i2c_scan::<C>,
+        },
+        Command {
+            name: "adc read",
+            args: "<pin>",
+            help: "Convert the voltage at an analog pin",
+            run: adc_read::<C>,
+        },
+        Command {
+            name: "gpio set",
+            args: "<pin> <high|low>",
+            help: "Drive an output pin",
+            run: gpio_set::<C>,
+        },
+    ]
+}
+
+fn info<C: Context>(args: &mut Args, out: &mut dyn Write, _: &mut C) -> Result<(), Error> {
+    args.finish()?;
+
+    let uid = signature::uid();
+    let flash_kib = signature::flash_size_kib();
+    let uptime = Duration::from_micros(Monotonic::now().as_micros());
+
+    write!(
+        out,
+        "Firmware: {} {}\r\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )?;
+    write!(
+        out,
+        "UID:      {:08x}{:08x}{:08x}\r\n",
+        uid[2], uid[1], uid[0]
+    )?;
+    write!(out, "Flash:    {} KiB\r\n", flash_kib)?;
+    write!(
+        out,
+        "Uptime:   {}.{:03} s\r\n",
+        uptime.as_secs(),
+        uptime.subsec_millis()
+    )?;
+
+    out.write_str("Reset:   ")?;
+    let csr = unsafe { &(*RCC::ptr()) }.csr.read().bits();
+    for (flag, name) in RESET_FLAGS.iter() {
+        if csr & flag != 0 {
+            write!(out, " {}", name)?;
+        }
+    }
+    out.write_str("\r\n")?;
+
+    Ok(())
+}
+
+fn reset<C: Context>(args: &mut Args, out: &mut dyn Write, _: &mut C) -> Result<(), Error> {
+    args.finish()?;
+
+    out.write_str("Resetting\r\n")?;
+    SCB::sys_reset()
+}
+
+fn i2c_scan<C: Context>(
+    args: &mut Args,
+    out: &mut dyn Write,
+    context: &mut C,
+) -> Result<(), Error> {
+    args.finish()?;
+
+    let i2c = context.i2c().ok_or(Error::Unavailable)?;
+    match i2c_tools::scan(i2c) {
+        Ok(result) => {
+            write!(out, "{}", result)?;
+            write!(out, "{} device(s) found\r\n", result.count())?;
+        }
+        Err(e) => write!(out, "Scan failed: {:?}\r\n", e)?,
+    }
+
+    Ok(())
+}
+
+fn adc_read<C: Context>(
+    args: &mut Args,
+    out: &mut dyn Write,
+    context: &mut C,
+) -> Result<(), Error> {
+    let pin: Pin = args.parse()?;
+    args.finish()?;
+
+    let value = context.adc_read(pin).ok_or(Error::Unavailable)?;
+    let mv = u32::from(value) * VDDA_MV / 4095;
+    write!(out, "{}: {} ({} mV)\r\n", pin, value, mv)?;
+
+    Ok(())
+}
+
+fn gpio_set<C: Context>(
+    args: &mut Args,
+    out: &mut dyn Write,
+    context: &mut C,
+) -> Result<(), Error> {
+    let pin: Pin = args.parse()?;
+    let high: bool = args.parse()?;
+    args.finish()?;
+
+    let output = context.output(pin).ok_or(Error::Unavailable)?;
+    if high {
+        output.set_high().ok();
+    } else {
+        output.set_low().ok();
+    }
+    write!(out, "{}: {}\r\n", pin, if high { "high" } else { "low" })?;
+
+    Ok(())
+}
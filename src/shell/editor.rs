@@ -0,0 +1,295 @@
+//! Line editing for the shell
+//!
+//! Bytes are echoed as they are typed, backspace and Ctrl-C edit the line and the up and down
+//! arrows browse the history. A line ends with CR, LF or CR LF.
+
+use core::fmt::{self, Write};
+
+// Control characters
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const TAB: u8 = b'\t';
+const ESC: u8 = 0x1b;
+const DELETE: u8 = 0x7f;
+const BELL: char = '\x07';
+
+/// What the caller has to do after a byte was handled
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) enum Event {
+    None,
+    /// A line was entered
+    Line,
+    /// Tab was pressed
+    Complete,
+    /// The line was discarded with Ctrl-C
+    Cancel,
+}
+
+/// Position within an ANSI escape sequence
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Escape {
+    None,
+    Esc,
+    Csi,
+}
+
+/// Editor of a line of up to `N` ASCII characters with a history of `H` lines
+pub(super) struct Editor<const N: usize, const H: usize> {
+    prompt: &'static str,
+    line: [u8; N],
+    len: usize,
+    history: [([u8; N], usize); H],
+    newest: usize,
+    stored: usize,
+    browsing: Option<usize>,
+    escape: Escape,
+    after_cr: bool,
+}
+
+impl<const N: usize, const H: usize> Editor<N, H> {
+    pub(super) const fn new(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            line: [0; N],
+            len: 0,
+            history: [([0; N], 0); H],
+            newest: 0,
+            stored: 0,
+            browsing: None,
+            escape: Escape::None,
+            after_cr: false,
+        }
+    }
+
+    pub(super) fn set_prompt(&mut self, prompt: &'static str) {
+        self.prompt = prompt;
+    }
+
+    /// The current line
+    pub(super) fn line(&self) -> &str {
+        // Only printable ASCII characters are accepted
+        core::str::from_utf8(&self.line[..self.len]).unwrap_or("")
+    }
+
+    /// Print the prompt and the current line
+    pub(super) fn redraw(&self, out: &mut dyn Write) -> fmt::Result {
+        write!(out, "\r\x1b[K{}{}", self.prompt, self.line())
+    }
+
+    /// Handle an input byte, echoing it as needed
+    pub(super) fn input(&mut self, byte: u8, out: &mut dyn Write) -> Result<Event, fmt::Error> {
+        let after_cr = self.after_cr;
+        self.after_cr = false;
+
+        match (self.escape, byte) {
+            (Escape::Esc, b'[') => {
+                self.escape = Escape::Csi;
+                return Ok(Event::None);
+            }
+            (Escape::Csi, b'A') => {
+                self.escape = Escape::None;
+                self.browse(true, out)?;
+                return Ok(Event::None);
+            }
+            (Escape::Csi, b'B') => {
+                self.escape = Escape::None;
+                self.browse(false, out)?;
+                return Ok(Event::None);
+            }
+            // Parameters of other sequences, which are ignored
+            (Escape::Csi, b'0'..=b'9') | (Escape::Csi, b';') => return Ok(Event::None),
+            (Escape::Esc, _) | (Escape::Csi, _) => {
+                self.escape = Escape::None;
+                return Ok(Event::None);
+            }
+            (Escape::None, _) => {}
+        }
+
+        match byte {
+            ESC => self.escape = Escape::Esc,
+            b'\r' => {
+                self.after_cr = true;
+                return Ok(Event::Line);
+            }
+            // Terminals sending CR LF only enter one line
+            b'\n' if after_cr => {}
+            b'\n' => return Ok(Event::Line),
+            TAB => return Ok(Event::Complete),
+            BACKSPACE | DELETE => {
+                if self.len > 0 {
+                    self.len -= 1;
+                    out.write_str("\x08 \x08")?;
+                }
+            }
+            CTRL_C => {
+                self.clear();
+                out.write_str("^C\r\n")?;
+                return Ok(Event::Cancel);
+            }
+            0x20..=0x7e => {
+                if self.len < N {
+                    self.line[self.len] = byte;
+                    self.len += 1;
+                    out.write_char(char::from(byte))?;
+                } else {
+                    out.write_char(BELL)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(Event::None)
+    }
+
+    /// Append `text` to the line and echo it
+    pub(super) fn insert(&mut self, text: &str, out: &mut dyn Write) -> fmt::Result {
+        for byte in text.bytes() {
+            if self.len == N {
+                return out.write_char(BELL);
+            }
+            self.line[self.len] = byte;
+            self.len += 1;
+            out.write_char(char::from(byte))?;
+        }
+        Ok(())
+    }
+
+    /// Store the current line in the history and start a new one
+    pub(super) fn commit(&mut self) {
+        if H > 0 && self.len > 0 {
+            // Entering the same line repeatedly only stores it once
+            let (newest, newest_len) = &self.history[self.newest];
+            let repeated = self.stored > 0 && newest[..*newest_len] == self.line[..self.len];
+
+            if !repeated {
+                self.newest = (self.newest + 1) % H;
+                self.history[self.newest] = (self.line, self.len);
+                self.stored = (self.stored + 1).min(H);
+            }
+        }
+
+        self.clear();
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.browsing = None;
+        self.escape = Escape::None;
+    }
+
+    /// Replace the line with an older or newer one from the history
+    fn browse(&mut self, older: bool, out: &mut dyn Write) -> fmt::Result {
+        let browsing = match (self.browsing, older) {
+            (None, true) if self.stored > 0 => Some(0),
+            (Some(age), true) if age + 1 < self.stored => Some(age + 1),
+            (Some(0), false) => None,
+            (Some(age), false) => Some(age - 1),
+            _ => return out.write_char(BELL),
+        };
+
+        self.browsing = browsing;
+        match browsing {
+            Some(age) => {
+                let (line, len) = self.history[(self.newest + H - age) % H];
+                self.line = line;
+                self.len = len;
+            }
+            None => self.len = 0,
+        }
+
+        self.redraw(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_str<const N: usize, const H: usize>(
+        editor: &mut Editor<N, H>,
+        text: &[u8],
+        out: &mut String,
+    ) -> Vec<Event> {
+        text.iter()
+            .map(|&byte| editor.input(byte, out).unwrap())
+            .filter(|event| *event != Event::None)
+            .collect()
+    }
+
+    #[test]
+    fn backspace_removes_last_character() {
+        let mut editor: Editor<16, 0> = Editor::new("> ");
+        let mut out = String::new();
+
+        type_str(&mut editor, b"lex\x08d\x7f\x7fed", &mut out);
+        assert_eq!(editor.line(), "led");
+        assert_eq!(out, "lex\x08 \x08d\x08 \x08\x08 \x08ed");
+
+        // Nothing left to delete
+        let mut editor: Editor<16, 0> = Editor::new("> ");
+        out.clear();
+        type_str(&mut editor, b"\x08", &mut out);
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    fn full_line_rings_bell() {
+        let mut editor: Editor<3, 0> = Editor::new("> ");
+        let mut out = String::new();
+
+        type_str(&mut editor, b"abcd", &mut out);
+        assert_eq!(editor.line(), "abc");
+        assert_eq!(out, "abc\x07");
+    }
+
+    #[test]
+    fn line_endings() {
+        let mut editor: Editor<16, 0> = Editor::new("> ");
+        let mut out = String::new();
+
+        assert_eq!(type_str(&mut editor, b"a\r\n", &mut out), [Event::Line]);
+        assert_eq!(type_str(&mut editor, b"\n", &mut out), [Event::Line]);
+        assert_eq!(
+            type_str(&mut editor, b"\r\r", &mut out),
+            [Event::Line, Event::Line]
+        );
+        assert_eq!(type_str(&mut editor, b"\n\n", &mut out), [Event::Line]);
+    }
+
+    #[test]
+    fn tab_and_ctrl_c() {
+        let mut editor: Editor<16, 0> = Editor::new("> ");
+        let mut out = String::new();
+
+        assert_eq!(type_str(&mut editor, b"he\t", &mut out), [Event::Complete]);
+        assert_eq!(editor.line(), "he");
+
+        assert_eq!(type_str(&mut editor, b"\x03", &mut out), [Event::Cancel]);
+        assert_eq!(editor.line(), "");
+    }
+
+    #[test]
+    fn history_wraps() {
+        let mut editor: Editor<16, 2> = Editor::new("> ");
+        let mut out = String::new();
+
+        for line in [&b"one"[..], b"two", b"two", b"three"].iter() {
+            type_str(&mut editor, line, &mut out);
+            editor.commit();
+        }
+
+        // "one" was pushed out, "two" is only stored once
+        type_str(&mut editor, b"\x1b[A", &mut out);
+        assert_eq!(editor.line(), "three");
+        type_str(&mut editor, b"\x1b[A", &mut out);
+        assert_eq!(editor.line(), "two");
+
+        out.clear();
+        type_str(&mut editor, b"\x1b[A", &mut out);
+        assert_eq!(editor.line(), "two");
+        assert_eq!(out, "\x07");
+
+        type_str(&mut editor, b"\x1b[B\x1b[B", &mut out);
+        assert_eq!(editor.line(), "");
+    }
+}
@@ -0,0 +1,501 @@
+//! Interactive command line for debugging, e.g. over the ST-Link virtual COM port
+//!
+//! [`Shell`] edits a line from the received bytes and echoes it to any [`fmt::Write`], with
+//! backspace, a history browsed with the arrow keys and tab completion of command names. Entered
+//! lines are dispatched to a static table of [`Command`]s, which parse their arguments via
+//! [`Args`]. No memory is allocated, the line and the history are part of the shell.
+//!
+//! Built-in commands are `help`, `info`, `reset`, `i2c scan`, `adc read <pin>` and
+//! `gpio set <pin> <high|low>`. The hardware they access is handed out by the [`Context`]
+//! passed to the shell along with each byte, which is also available to the application's own
+//! commands:
+//!
+//! ```ignore
+//! static COMMANDS: &[Command<Board>] = &[Command {
+//!     name: "led",
+//!     args: "<on|off>",
+//!     help: "Switch the user LED",
+//!     run: led,
+//! }];
+//!
+//! let mut shell: Shell<Board, 64, 4> = Shell::new(COMMANDS);
+//! shell.start(&mut tx).ok();
+//! loop {
+//!     if let Ok(byte) = rx.read() {
+//!         shell.input(byte, &mut tx, &mut board).ok();
+//!     }
+//! }
+//! ```
+
+mod builtins;
+mod editor;
+
+use crate::header::Pin;
+use crate::i2c_tools::BoardI2c;
+
+use core::{convert::Infallible, fmt, str::SplitAsciiWhitespace};
+
+use embedded_hal::digital::v2::OutputPin;
+
+use editor::{Editor, Event};
+
+/// Errors of commands
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The argument at the given position, starting at 1, is missing
+    MissingArgument(usize),
+    /// The argument at the given position, starting at 1, couldn't be parsed
+    InvalidArgument(usize),
+    /// More arguments were given than expected
+    TooManyArguments,
+    /// The hardware needed isn't provided by the context
+    Unavailable,
+    /// The command failed
+    Failed,
+    /// Writing the output failed
+    Output,
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Self {
+        Error::Output
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingArgument(position) => write!(f, "argument {} missing", position),
+            Error::InvalidArgument(position) => write!(f, "argument {} invalid", position),
+            Error::TooManyArguments => f.write_str("too many arguments"),
+            Error::Unavailable => f.write_str("not available"),
+            Error::Failed => f.write_str("failed"),
+            Error::Output => f.write_str("output failed"),
+        }
+    }
+}
+
+/// Types which can be parsed from an argument
+pub trait Arg: Sized {
+    /// Parse a single argument, returns `None` if it isn't valid
+    fn parse(arg: &str) -> Option<Self>;
+}
+
+macro_rules! unsigned_arg {
+    ($($type:ty),*) => {
+        $(
+            /// Decimal or hexadecimal with `0x` prefix
+            impl Arg for $type {
+                fn parse(arg: &str) -> Option<Self> {
+                    match arg.strip_prefix("0x") {
+                        Some(hex) => <$type>::from_str_radix(hex, 16).ok(),
+                        None => arg.parse().ok(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+unsigned_arg!(u8, u16, u32);
+
+impl Arg for i32 {
+    fn parse(arg: &str) -> Option<Self> {
+        arg.parse().ok()
+    }
+}
+
+/// `high`, `on`, `true` or `1` and `low`, `off`, `false` or `0`
+impl Arg for bool {
+    fn parse(arg: &str) -> Option<Self> {
+        const TRUE: [&str; 4] = ["high", "on", "true", "1"];
+        const FALSE: [&str; 4] = ["low", "off", "false", "0"];
+
+        if TRUE.iter().any(|word| word.eq_ignore_ascii_case(arg)) {
+            Some(true)
+        } else if FALSE.iter().any(|word| word.eq_ignore_ascii_case(arg)) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// The name of a header pin, e.g. `D3` or `a0`
+impl Arg for Pin {
+    fn parse(arg: &str) -> Option<Self> {
+        Pin::from_name(arg)
+    }
+}
+
+/// The arguments following the command name
+pub struct Args<'a> {
+    tokens: SplitAsciiWhitespace<'a>,
+    position: usize,
+}
+
+impl<'a> Args<'a> {
+    /// The next argument as it was entered
+    pub fn string(&mut self) -> Result<&'a str, Error> {
+        self.position += 1;
+        self.tokens
+            .next()
+            .ok_or(Error::MissingArgument(self.position))
+    }
+
+    /// Parse the next argument
+    pub fn parse<T: Arg>(&mut self) -> Result<T, Error> {
+        let arg = self.string()?;
+        T::parse(arg).ok_or(Error::InvalidArgument(self.position))
+    }
+
+    /// Parse the next argument if there is one
+    pub fn optional<T: Arg>(&mut self) -> Result<Option<T>, Error> {
+        match self.tokens.clone().next() {
+            Some(_) => self.parse().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Make sure all arguments have been used
+    pub fn finish(&mut self) -> Result<(), Error> {
+        match self.tokens.clone().next() {
+            Some(_) => Err(Error::TooManyArguments),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The hardware used by the built-in commands
+///
+/// All methods default to the hardware not being available.
+pub trait Context {
+    /// I2C1 for `i2c scan`
+    fn i2c(&mut self) -> Option<&mut BoardI2c> {
+        None
+    }
+
+    /// Convert the voltage at `pin` for `adc read`
+    fn adc_read(&mut self, _pin: Pin) -> Option<u16> {
+        None
+    }
+
+    /// The output at `pin` for `gpio set`
+    fn output(&mut self, _pin: Pin) -> Option<&mut dyn OutputPin<Error = Infallible>> {
+        None
+    }
+}
+
+/// A context with only the commands not needing any hardware
+impl Context for () {}
+
+/// Function running a command
+pub type Run<C> = fn(&mut Args, &mut dyn fmt::Write, &mut C) -> Result<(), Error>;
+
+/// An entry of the command table
+pub struct Command<C> {
+    /// Name of the command, may consist of several words like `i2c scan`
+    pub name: &'static str,
+    /// Description of the arguments for the help, e.g. `<pin> <high|low>`
+    pub args: &'static str,
+    /// One line describing the command
+    pub help: &'static str,
+    /// Parses the arguments, writes the output and reports failures as [`Error`], which the
+    /// shell prints after the name of the command
+    pub run: Run<C>,
+}
+
+impl<C> Command<C> {
+    /// Number of words of the name matched by `line`, if all of them are
+    fn matches(&self, line: &str) -> Option<usize> {
+        let mut tokens = line.split_ascii_whitespace();
+        let mut words = 0;
+
+        for word in self.name.split(' ') {
+            if tokens.next()? != word {
+                return None;
+            }
+            words += 1;
+        }
+
+        Some(words)
+    }
+}
+
+/// Command line with a line length of `N` and a history of `H` lines, using the context `C`
+pub struct Shell<C: 'static, const N: usize, const H: usize> {
+    editor: Editor<N, H>,
+    commands: &'static [Command<C>],
+}
+
+impl<C: Context, const N: usize, const H: usize> Shell<C, N, H> {
+    /// Create a shell providing `commands` in addition to the built-in ones
+    pub fn new(commands: &'static [Command<C>]) -> Self {
+        Self {
+            editor: Editor::new("> "),
+            commands,
+        }
+    }
+
+    /// Use `prompt` instead of `> `
+    pub fn with_prompt(mut self, prompt: &'static str) -> Self {
+        self.editor.set_prompt(prompt);
+        self
+    }
+
+    /// Print the prompt
+    pub fn start(&mut self, out: &mut dyn fmt::Write) -> fmt::Result {
+        out.write_str("\r\n")?;
+        self.editor.redraw(out)
+    }
+
+    /// Handle a received byte, running the command once a line is complete
+    pub fn input(&mut self, byte: u8, out: &mut dyn fmt::Write, context: &mut C) -> fmt::Result {
+        match self.editor.input(byte, out)? {
+            Event::None => Ok(()),
+            Event::Line => {
+                out.write_str("\r\n")?;
+                self.run(out, context)?;
+                self.editor.commit();
+                self.editor.redraw(out)
+            }
+            Event::Complete => self.complete(out),
+            Event::Cancel => self.editor.redraw(out),
+        }
+    }
+
+    fn run(&self, out: &mut dyn fmt::Write, context: &mut C) -> fmt::Result {
+        let line = self.editor.line();
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+
+        // The command matching the most words wins
+        let builtins = builtins::commands::<C>();
+        let command = self
+            .commands
+            .iter()
+            .chain(builtins.iter())
+            .filter_map(|command| Some((command.matches(line)?, command)))
+            .max_by_key(|(words, _)| *words);
+
+        let (words, command) = match command {
+            Some(found) => found,
+            None if line.split_ascii_whitespace().next() == Some("help") => {
+                return self.help(out);
+            }
+            None => {
+                let name = line.split_ascii_whitespace().next().unwrap_or("");
+                return write!(out, "Unknown command '{}', try 'help'\r\n", name);
+            }
+        };
+
+        let mut tokens = line.split_ascii_whitespace();
+        for _ in 0..words {
+            tokens.next();
+        }
+        let mut args = Args {
+            tokens,
+            position: 0,
+        };
+
+        match (command.run)(&mut args, out, context) {
+            Ok(()) => Ok(()),
+            Err(Error::Output) => Err(fmt::Error),
+            Err(e) => write!(out, "{}: {}\r\n", command.name, e),
+        }
+    }
+
+    fn help(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        let builtins = builtins::commands::<C>();
+        let commands = self.commands.iter().chain(builtins.iter());
+
+        for command in commands {
+            let width = command.name.len() + command.args.len() + 1;
+            write!(
+                out,
+                "{} {}{:pad$}  {}\r\n",
+                command.name,
+                command.args,
+                "",
+                command.help,
+                pad = 28_usize.saturating_sub(width)
+            )?;
+        }
+        write!(out, "{:30}List the commands\r\n", "help")
+    }
+
+    /// Complete the command name as far as it is unambiguous, list the candidates otherwise
+    fn complete(&mut self, out: &mut dyn fmt::Write) -> fmt::Result {
+        let line = self.editor.line();
+        let builtins = builtins::commands::<C>();
+        let candidates = self
+            .commands
+            .iter()
+            .chain(builtins.iter())
+            .map(|command| command.name)
+            .chain(core::iter::once("help"))
+            .filter(|name| name.starts_with(line));
+
+        // Length of the common prefix of all candidates
+        let mut count = 0;
+        let mut first = "";
+        let mut common = 0;
+        for name in candidates.clone() {
+            if count == 0 {
+                first = name;
+                common = name.len();
+            } else {
+                common = first
+                    .bytes()
+                    .zip(name.bytes())
+                    .take(common)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            }
+            count += 1;
+        }
+
+        match count {
+            0 => out.write_char('\x07'),
+            1 => {
+                let rest = &first[line.len()..];
+                self.editor.insert(rest, out)?;
+                self.editor.insert(" ", out)
+            }
+            _ if common > line.len() => {
+                let rest = &first[line.len()..common];
+                self.editor.insert(rest, out)
+            }
+            _ => {
+                out.write_str("\r\n")?;
+                for name in candidates {
+                    write!(out, "{}  ", name)?;
+                }
+                out.write_str("\r\n")?;
+                self.editor.redraw(out)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static COMMANDS: &[Command<()>] = &[
+        Command {
+            name: "led",
+            args: "<on|off>",
+            help: "Switch the user LED",
+            run: led,
+        },
+        Command {
+            name: "led blink",
+            args: "<ms>",
+            help: "Blink the user LED",
+            run: blink,
+        },
+    ];
+
+    fn led(args: &mut Args, out: &mut dyn fmt::Write, _: &mut ()) -> Result<(), Error> {
+        let on: bool = args.parse()?;
+        args.finish()?;
+        write!(out, "led {}\r\n", on)?;
+        Ok(())
+    }
+
+    fn blink(args: &mut Args, out: &mut dyn fmt::Write, _: &mut ()) -> Result<(), Error> {
+        let ms: u16 = args.parse()?;
+        let count: Option<u8> = args.optional()?;
+        args.finish()?;
+        write!(out, "blink {} {:?}\r\n", ms, count)?;
+        Ok(())
+    }
+
+    fn enter(shell: &mut Shell<(), 32, 2>, text: &str) -> String {
+        let mut out = String::new();
+        for byte in text.bytes() {
+            shell.input(byte, &mut out, &mut ()).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn unsigned_args() {
+        assert_eq!(u8::parse("42"), Some(42));
+        assert_eq!(u8::parse("0x2a"), Some(42));
+        assert_eq!(u16::parse("0xFFFF"), Some(0xffff));
+        assert_eq!(u32::parse("0x"), None);
+        assert_eq!(u8::parse("256"), None);
+        assert_eq!(u8::parse("0x100"), None);
+        assert_eq!(u8::parse("-1"), None);
+        assert_eq!(i32::parse("-1"), Some(-1));
+    }
+
+    #[test]
+    fn bool_args() {
+        for arg in ["high", "ON", "True", "1"].iter() {
+            assert_eq!(bool::parse(arg), Some(true));
+        }
+        for arg in ["low", "off", "FALSE", "0"].iter() {
+            assert_eq!(bool::parse(arg), Some(false));
+        }
+        assert_eq!(bool::parse("yes"), None);
+    }
+
+    #[test]
+    fn pin_args() {
+        assert_eq!(Pin::parse("D3"), Some(Pin::D3));
+        assert_eq!(Pin::parse("a0"), Some(Pin::A0));
+        assert_eq!(Pin::parse("D99"), None);
+    }
+
+    #[test]
+    fn longest_name_wins() {
+        let mut shell = Shell::new(COMMANDS);
+
+        assert!(enter(&mut shell, "led on\r").contains("led true\r\n"));
+        assert!(enter(&mut shell, "led blink 0x10 3\r").contains("blink 16 Some(3)\r\n"));
+        assert!(enter(&mut shell, "led blink 100\r").contains("blink 100 None\r\n"));
+    }
+
+    #[test]
+    fn argument_errors() {
+        let mut shell = Shell::new(COMMANDS);
+
+        assert!(enter(&mut shell, "led\r").contains("led: argument 1 missing\r\n"));
+        assert!(enter(&mut shell, "led maybe\r").contains("led: argument 1 invalid\r\n"));
+        assert!(enter(&mut shell, "led on off\r").contains("led: too many arguments\r\n"));
+        assert!(enter(&mut shell, "lamp\r").contains("Unknown command 'lamp', try 'help'\r\n"));
+    }
+
+    #[test]
+    fn completes_unambiguous_prefix() {
+        let mut shell = Shell::new(COMMANDS);
+
+        assert_eq!(enter(&mut shell, "he\t"), "help ");
+        assert_eq!(shell.editor.line(), "help ");
+    }
+
+    #[test]
+    fn completes_common_prefix() {
+        let mut shell = Shell::new(COMMANDS);
+
+        // "led" and "led blink"
+        assert_eq!(enter(&mut shell, "l\t"), "led");
+        assert_eq!(shell.editor.line(), "led");
+    }
+
+    #[test]
+    fn lists_candidates() {
+        let mut shell = Shell::new(COMMANDS);
+
+        let out = enter(&mut shell, "i\t");
+        assert!(out.contains("\r\ninfo  i2c scan  \r\n"));
+        assert_eq!(shell.editor.line(), "i");
+
+        // Nothing to complete
+        assert_eq!(enter(&mut shell, "x\t"), "x\x07");
+    }
+}
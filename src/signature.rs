@@ -0,0 +1,25 @@
+//! Device electronic signature
+//!
+//! The unique device ID and the flash size are programmed into the system memory during
+//! production, see the reference manual RM0091.
+
+use core::ptr;
+
+const UID: *const u32 = 0x1fff_f7ac as *const u32;
+const FLASH_SIZE: *const u16 = 0x1fff_f7cc as *const u16;
+
+/// The 96 bit unique device ID, lowest word first
+pub fn uid() -> [u32; 3] {
+    unsafe {
+        [
+            ptr::read_volatile(UID),
+            ptr::read_volatile(UID.add(1)),
+            ptr::read_volatile(UID.add(2)),
+        ]
+    }
+}
+
+/// Size of the flash memory in KiB
+pub fn flash_size_kib() -> u16 {
+    unsafe { ptr::read_volatile(FLASH_SIZE) }
+}
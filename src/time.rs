@@ -0,0 +1,410 @@
+//! Monotonic system clock with microsecond resolution
+//!
+//! TIM2 is the only 32-bit timer of the F042, here it is used as a free running counter ticking
+//! at 1 MHz. Its update interrupt extends the count to 64 bits, so [`Instant`]s never wrap in
+//! practice. The `TIM2` interrupt handler has to call [`Monotonic::on_interrupt`]:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn TIM2() {
+//!     Monotonic::on_interrupt();
+//! }
+//! ```
+//!
+//! The units and extension traits of the HAL are re-exported so this module can be used in place
+//! of `hal::time`.
+
+pub use crate::hal::time::*;
+
+use crate::hal::{
+    rcc::Rcc,
+    stm32::{Interrupt, RCC, TIM2},
+};
+
+use core::{
+    ops::{Add, AddAssign, Sub},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Number of TIM2 overflows, i.e. the upper 32 bits of the microsecond counter
+static OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+
+/// A span of time with microsecond resolution
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Duration {
+    micros: u64,
+}
+
+impl Duration {
+    pub const ZERO: Duration = Duration { micros: 0 };
+
+    pub const fn from_micros(micros: u64) -> Self {
+        Self { micros }
+    }
+
+    pub const fn from_millis(millis: u32) -> Self {
+        Self {
+            micros: millis as u64 * 1_000,
+        }
+    }
+
+    pub const fn from_secs(secs: u32) -> Self {
+        Self {
+            micros: secs as u64 * 1_000_000,
+        }
+    }
+
+    pub const fn as_micros(&self) -> u64 {
+        self.micros
+    }
+
+    pub const fn as_millis(&self) -> u64 {
+        self.micros / 1_000
+    }
+
+    pub const fn as_secs(&self) -> u64 {
+        self.micros / 1_000_000
+    }
+
+    /// Sub-second part of the duration in milliseconds
+    pub const fn subsec_millis(&self) -> u32 {
+        ((self.micros % 1_000_000) / 1_000) as u32
+    }
+
+    pub fn checked_add(self, rhs: Duration) -> Option<Duration> {
+        self.micros
+            .checked_add(rhs.micros)
+            .map(Duration::from_micros)
+    }
+
+    pub fn checked_sub(self, rhs: Duration) -> Option<Duration> {
+        self.micros
+            .checked_sub(rhs.micros)
+            .map(Duration::from_micros)
+    }
+
+    pub fn saturating_sub(self, rhs: Duration) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(rhs.micros))
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration::from_micros(self.micros.saturating_add(rhs.micros))
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        self.saturating_sub(rhs)
+    }
+}
+
+/// A point in time as measured by [`Monotonic`], in microseconds since it was started
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant {
+    micros: u64,
+}
+
+impl Instant {
+    pub const fn from_micros(micros: u64) -> Self {
+        Self { micros }
+    }
+
+    /// Microseconds since the clock was started
+    pub const fn as_micros(&self) -> u64 {
+        self.micros
+    }
+
+    /// Time elapsed from `earlier` to `self`, or zero if `earlier` is actually later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(earlier.micros))
+    }
+
+    /// Time elapsed from `earlier` to `self` if `earlier` is not later than `self`
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.micros
+            .checked_sub(earlier.micros)
+            .map(Duration::from_micros)
+    }
+
+    /// Time elapsed since this instant
+    pub fn elapsed(&self) -> Duration {
+        Monotonic::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.micros
+            .checked_add(duration.micros)
+            .map(Instant::from_micros)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant::from_micros(self.micros.saturating_add(rhs.micros))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant::from_micros(self.micros.saturating_sub(rhs.micros))
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// Monotonic clock based on TIM2
+pub struct Monotonic {
+    tim: TIM2,
+}
+
+impl Monotonic {
+    /// Start TIM2 as free running 1 MHz counter and enable its update interrupt
+    ///
+    /// The timer clock needs to be a non-zero multiple of 1 MHz, which is the case for all the
+    /// usual system clock settings, otherwise this panics. The `TIM2` interrupt is unmasked in
+    /// the NVIC.
+    pub fn tim2(tim: TIM2, rcc: &mut Rcc) -> Self {
+        let tim_clk = crate::timer_clock(&rcc.clocks);
+        assert!(
+            tim_clk >= 1_000_000 && tim_clk % 1_000_000 == 0,
+            "the TIM2 clock must be a multiple of 1 MHz"
+        );
+        let rccr = unsafe { &(*RCC::ptr()) };
+
+        rccr.apb1enr.modify(|_, w| w.tim2en().set_bit());
+        rccr.apb1rstr.modify(|_, w| w.tim2rst().set_bit());
+        rccr.apb1rstr.modify(|_, w| w.tim2rst().clear_bit());
+
+        tim.psc
+            .write(|w| unsafe { w.bits(tim_clk / 1_000_000 - 1) });
+        tim.arr.write(|w| unsafe { w.bits(0xffff_ffff) });
+
+        // Only counter overflows should cause an update interrupt, not the UG event below
+        tim.cr1.modify(|_, w| w.urs().set_bit());
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.modify(|_, w| w.uif().clear_bit());
+
+        OVERFLOWS.store(0, Ordering::Relaxed);
+
+        tim.dier.modify(|_, w| w.uie().set_bit());
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+
+        cortex_m::peripheral::NVIC::unpend(Interrupt::TIM2);
+        unsafe {
+            cortex_m::peripheral::NVIC::unmask(Interrupt::TIM2);
+        }
+
+        Self { tim }
+    }
+
+    /// The current time, callable from thread and interrupt context alike
+    ///
+    /// Returns a zero [`Instant`] until the clock has been started.
+    pub fn now() -> Instant {
+        let tim = unsafe { &(*TIM2::ptr()) };
+
+        cortex_m::interrupt::free(|_| {
+            let mut high = OVERFLOWS.load(Ordering::Relaxed);
+            let low = tim.cnt.read().bits();
+
+            // An overflow might have happened without its interrupt being serviced yet, in which
+            // case a small count belongs to the next period
+            if tim.sr.read().uif().bit_is_set() && low < 0x8000_0000 {
+                high += 1;
+            }
+
+            Instant::from_micros(u64::from(high) << 32 | u64::from(low))
+        })
+    }
+
+    /// Account for a counter overflow, to be called from the `TIM2` interrupt handler
+    pub fn on_interrupt() {
+        let tim = unsafe { &(*TIM2::ptr()) };
+
+        cortex_m::interrupt::free(|_| {
+            if tim.sr.read().uif().bit_is_set() {
+                // Write ones to all other flags so a concurrently set one isn't lost
+                tim.sr.write(|w| unsafe { w.bits(!1) });
+                OVERFLOWS.store(
+                    OVERFLOWS.load(Ordering::Relaxed).wrapping_add(1),
+                    Ordering::Relaxed,
+                );
+            }
+        });
+    }
+
+    /// Stop the clock and release the timer
+    pub fn release(self) -> TIM2 {
+        cortex_m::peripheral::NVIC::mask(Interrupt::TIM2);
+        self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+        self.tim.dier.modify(|_, w| w.uie().clear_bit());
+        self.tim
+    }
+}
+
+/// A non-blocking timeout
+#[derive(Debug, Copy, Clone)]
+pub struct Timeout {
+    deadline: Instant,
+}
+
+impl Timeout {
+    /// Start a timeout expiring `duration` from now
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            deadline: Monotonic::now() + duration,
+        }
+    }
+
+    /// Whether the deadline has passed
+    pub fn expired(&self) -> bool {
+        Monotonic::now() >= self.deadline
+    }
+
+    /// Time left until the deadline, zero if it has already passed
+    pub fn remaining(&self) -> Duration {
+        self.deadline.duration_since(Monotonic::now())
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+/// A non-blocking periodic event without cumulative drift
+#[derive(Debug, Copy, Clone)]
+pub struct Periodic {
+    next: Instant,
+    period: Duration,
+}
+
+impl Periodic {
+    /// Create a periodic event first due one `period` from now
+    pub fn new(period: Duration) -> Self {
+        Self {
+            next: Monotonic::now() + period,
+            period,
+        }
+    }
+
+    /// Returns `true` once for every elapsed period
+    ///
+    /// If the caller falls behind by more than one period the missed events are skipped rather
+    /// than reported in a burst.
+    pub fn poll(&mut self) -> bool {
+        self.poll_at(Monotonic::now())
+    }
+
+    fn poll_at(&mut self, now: Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+
+        self.next += self.period;
+        if self.next <= now {
+            let behind = now.duration_since(self.next).as_micros();
+            let period = self.period.as_micros().max(1);
+            self.next += Duration::from_micros((behind / period + 1) * period);
+        }
+
+        true
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(millis: u64) -> Instant {
+        Instant::from_micros(millis * 1000)
+    }
+
+    #[test]
+    fn duration_units() {
+        let duration = Duration::from_millis(2_345);
+
+        assert_eq!(duration.as_micros(), 2_345_000);
+        assert_eq!(duration.as_millis(), 2_345);
+        assert_eq!(duration.as_secs(), 2);
+        assert_eq!(duration.subsec_millis(), 345);
+        assert_eq!(Duration::from_secs(3), Duration::from_millis(3_000));
+    }
+
+    #[test]
+    fn duration_arithmetic() {
+        let one = Duration::from_secs(1);
+        let two = Duration::from_secs(2);
+
+        assert_eq!(one + one, two);
+        assert_eq!(two - one, one);
+        assert_eq!(one - two, Duration::ZERO);
+        assert_eq!(one.checked_sub(two), None);
+        assert_eq!(Duration::from_micros(u64::MAX).checked_add(one), None);
+        assert_eq!(
+            Duration::from_micros(u64::MAX) + one,
+            Duration::from_micros(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn instant_arithmetic() {
+        assert_eq!(at(30) - at(10), Duration::from_millis(20));
+        assert_eq!(at(10) - at(30), Duration::ZERO);
+        assert_eq!(at(10).checked_duration_since(at(30)), None);
+        assert_eq!(at(10) + Duration::from_millis(5), at(15));
+        assert_eq!(at(10) - Duration::from_millis(20), at(0));
+    }
+
+    #[test]
+    fn periodic_without_drift() {
+        let mut periodic = Periodic {
+            next: at(10),
+            period: Duration::from_millis(10),
+        };
+
+        assert!(!periodic.poll_at(at(9)));
+        assert!(periodic.poll_at(at(12)));
+        assert!(!periodic.poll_at(at(19)));
+        assert!(periodic.poll_at(at(20)));
+        assert_eq!(periodic.next, at(30));
+    }
+
+    #[test]
+    fn periodic_skips_missed_periods() {
+        let mut periodic = Periodic {
+            next: at(10),
+            period: Duration::from_millis(10),
+        };
+
+        assert!(periodic.poll_at(at(45)));
+        assert!(!periodic.poll_at(at(49)));
+        assert_eq!(periodic.next, at(50));
+        assert!(periodic.poll_at(at(50)));
+    }
+}
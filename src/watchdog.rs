@@ -0,0 +1,309 @@
+//! Independent watchdog supervising several tasks
+//!
+//! The plain IWDG driver of the HAL is re-exported. On top of it [`Supervisor`] only feeds the
+//! watchdog while every registered task has checked in within its own deadline, so a single hung
+//! task resets the MCU even if the main loop keeps running. The task which starved is logged
+//! into RAM which isn't initialised at startup and can be read back after the reset:
+//!
+//! ```ignore
+//! let mut supervisor: Supervisor<2> =
+//!     Supervisor::new(p.IWDG, &mut p.DBGMCU, Duration::from_millis(500), &mut rcc);
+//! if let Some(starved) = supervisor.starved_before_reset() {
+//!     // Report `starved.task`
+//! }
+//! let sensor = supervisor.register(Duration::from_millis(100)).unwrap();
+//! ```
+//!
+//! Deadlines are checked with [`crate::time::Monotonic`], which needs to be running.
+
+pub use crate::hal::watchdog::*;
+
+use crate::hal::{
+    rcc::Rcc,
+    stm32::{DBGMCU, IWDG, RCC},
+};
+
+use crate::time::{Duration, Instant, Monotonic};
+
+use core::{mem::MaybeUninit, ptr};
+
+// IWDG keys
+const KEY_START: u32 = 0xcccc;
+const KEY_ACCESS: u32 = 0x5555;
+const KEY_RELOAD: u32 = 0xaaaa;
+
+// Largest reload value and prescaler setting (divider 256)
+const MAX_RELOAD: u32 = 0xfff;
+const MAX_PRESCALER: u32 = 6;
+
+// Nominal frequency of the LSI clocking the IWDG, the actual one may be 30 to 50 kHz
+const LSI_HZ: u64 = 40_000;
+
+// RCC bits: DBGMCU clock enable and the IWDG reset flag
+const DBGMCUEN: u32 = 1 << 22;
+const IWDGRSTF: u32 = 1 << 29;
+
+// Marks a valid log entry in uninitialised RAM
+const LOG_MAGIC: u32 = 0x5741_5443;
+
+/// Errors of the supervisor
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// All task slots are taken
+    TooManyTasks,
+}
+
+/// Handle of a supervised task
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Task(usize);
+
+impl Task {
+    /// Index of the task in the order of registration
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// A task which missed its deadline
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Starved {
+    /// Index of the task in the order of registration
+    pub task: usize,
+    /// Time since the last check-in of the task in ms
+    pub silent_ms: u32,
+}
+
+#[derive(Copy, Clone)]
+struct Slot {
+    deadline: Duration,
+    last: Instant,
+}
+
+/// Log entry surviving the watchdog reset
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Log {
+    magic: u32,
+    task: u32,
+    silent_ms: u32,
+    check: u32,
+}
+
+impl Log {
+    fn new(starved: Starved) -> Self {
+        let task = starved.task as u32;
+        Self {
+            magic: LOG_MAGIC,
+            task,
+            silent_ms: starved.silent_ms,
+            check: !(task ^ starved.silent_ms),
+        }
+    }
+
+    fn starved(&self) -> Option<Starved> {
+        if self.magic == LOG_MAGIC && self.check == !(self.task ^ self.silent_ms) {
+            Some(Starved {
+                task: self.task as usize,
+                silent_ms: self.silent_ms,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[link_section = ".uninit.WATCHDOG_LOG"]
+static mut LOG: MaybeUninit<Log> = MaybeUninit::uninit();
+
+fn read_log() -> Log {
+    unsafe { ptr::read_volatile(LOG.as_ptr()) }
+}
+
+fn write_log(log: Log) {
+    unsafe { ptr::write_volatile(LOG.as_mut_ptr(), log) }
+}
+
+/// Prescaler setting and reload value for `timeout`, clamped to the range of the IWDG
+fn prescaler(timeout: Duration) -> (u32, u32) {
+    let ticks = timeout.as_micros() * LSI_HZ / 1_000_000;
+
+    // The smallest divider is 4, doubling with each setting
+    let mut prescaler = 0;
+    while prescaler < MAX_PRESCALER && ticks > (u64::from(MAX_RELOAD) + 1) << (prescaler + 2) {
+        prescaler += 1;
+    }
+
+    let reload = (ticks >> (prescaler + 2)).clamp(1, u64::from(MAX_RELOAD) + 1) - 1;
+    (prescaler, reload as u32)
+}
+
+/// Owner of the IWDG, feeding it only while all tasks are alive
+///
+/// Up to `N` tasks can be registered.
+pub struct Supervisor<const N: usize> {
+    iwdg: IWDG,
+    timeout: Duration,
+    slots: [Option<Slot>; N],
+    starved: Option<Starved>,
+    previous: Option<Starved>,
+}
+
+impl<const N: usize> Supervisor<N> {
+    /// Start the IWDG with `timeout`, which is limited to about 0.1 ms to 26 s
+    ///
+    /// The watchdog is stopped while the core is halted by a debugger. The Cortex-M0 can't tell
+    /// whether a debugger is attached, but the freeze only takes effect when one halts the core,
+    /// so it is always enabled. Once started, the IWDG can only be stopped by a reset.
+    pub fn new(iwdg: IWDG, dbgmcu: &mut DBGMCU, timeout: Duration, _rcc: &mut Rcc) -> Self {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // Take over the log of the previous run if it ended with a watchdog reset
+        let previous = if rcc.csr.read().bits() & IWDGRSTF != 0 {
+            read_log().starved()
+        } else {
+            None
+        };
+        write_log(Log {
+            magic: 0,
+            task: 0,
+            silent_ms: 0,
+            check: 0,
+        });
+
+        rcc.apb2enr
+            .modify(|r, w| unsafe { w.bits(r.bits() | DBGMCUEN) });
+        dbgmcu.apb1_fz.modify(|_, w| w.dbg_iwdg_stop().set_bit());
+
+        let (prescaler, reload) = prescaler(timeout);
+        iwdg.kr.write(|w| unsafe { w.bits(KEY_START) });
+        iwdg.kr.write(|w| unsafe { w.bits(KEY_ACCESS) });
+        iwdg.pr.write(|w| unsafe { w.bits(prescaler) });
+        iwdg.rlr.write(|w| unsafe { w.bits(reload) });
+        while iwdg.sr.read().bits() != 0 {}
+        iwdg.kr.write(|w| unsafe { w.bits(KEY_RELOAD) });
+
+        let timeout =
+            Duration::from_micros((u64::from(reload) + 1) * (4 << prescaler) * 1_000_000 / LSI_HZ);
+
+        Self {
+            iwdg,
+            timeout,
+            slots: [None; N],
+            starved: None,
+            previous,
+        }
+    }
+
+    /// The timeout of the IWDG at the nominal LSI frequency
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// The task which starved if the last reset was caused by the watchdog
+    pub fn starved_before_reset(&self) -> Option<Starved> {
+        self.previous
+    }
+
+    /// Supervise a task which has to check in at least every `deadline`
+    ///
+    /// The deadline starts running right away.
+    pub fn register(&mut self, deadline: Duration) -> Result<Task, Error> {
+        let index = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::TooManyTasks)?;
+
+        self.slots[index] = Some(Slot {
+            deadline,
+            last: Monotonic::now(),
+        });
+
+        Ok(Task(index))
+    }
+
+    /// Signal that `task` is alive
+    pub fn check_in(&mut self, task: Task) {
+        if let Some(slot) = self.slots[task.0].as_mut() {
+            slot.last = Monotonic::now();
+        }
+    }
+
+    /// Feed the IWDG if all tasks are alive, to be called more often than the timeout
+    ///
+    /// A task missing its deadline is logged and the IWDG is never fed again, so the MCU is
+    /// reset once the timeout expires.
+    pub fn service(&mut self) -> Result<(), Starved> {
+        if let Some(starved) = self.starved {
+            return Err(starved);
+        }
+
+        let now = Monotonic::now();
+
+        // Blame the task which has been silent for the longest time
+        let starved = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(task, slot)| slot.map(|slot| (task, slot)))
+            .filter_map(|(task, slot)| {
+                let silent = now.checked_duration_since(slot.last)?;
+                if silent > slot.deadline {
+                    Some(Starved {
+                        task,
+                        silent_ms: silent.as_millis() as u32,
+                    })
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|starved| starved.silent_ms);
+
+        match starved {
+            Some(starved) => {
+                write_log(Log::new(starved));
+                self.starved = Some(starved);
+                Err(starved)
+            }
+            None => {
+                self.iwdg.kr.write(|w| unsafe { w.bits(KEY_RELOAD) });
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smallest_prescaler_that_fits() {
+        assert_eq!(prescaler(Duration::from_millis(100)), (0, 999));
+        assert_eq!(prescaler(Duration::from_secs(1)), (2, 2_499));
+        assert_eq!(prescaler(Duration::from_secs(26)), (6, 4_061));
+    }
+
+    #[test]
+    fn timeouts_out_of_range_are_clamped() {
+        assert_eq!(prescaler(Duration::ZERO), (0, 0));
+        assert_eq!(prescaler(Duration::from_secs(100)), (6, 4_095));
+    }
+
+    #[test]
+    fn log_survives_only_intact() {
+        let starved = Starved {
+            task: 2,
+            silent_ms: 1_234,
+        };
+        assert_eq!(Log::new(starved).starved(), Some(starved));
+
+        let mut log = Log::new(starved);
+        log.silent_ms += 1;
+        assert_eq!(log.starved(), None);
+
+        let mut log = Log::new(starved);
+        log.magic = 0;
+        assert_eq!(log.starved(), None);
+    }
+}
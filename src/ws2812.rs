@@ -0,0 +1,221 @@
+//! WS2812 and compatible LED strips driven by SPI1 and DMA
+//!
+//! Each bit of the GRB data is sent as three bits on MOSI, `100` for a 0 and `110` for a 1. At an
+//! SPI clock of 3 MHz this gives high times of 333 and 667 ns within a bit period of 1.0 µs.
+//! The SPI has to be set up with 8-bit frames at exactly 3 MHz, i.e. with a PCLK of 48, 24, 12 or
+//! 6 MHz. Only MOSI is connected to the strip, D11 or A6.
+//!
+//! Encoding the whole strip up front would take 9 bytes per LED out of the 6 KiB of RAM, so the
+//! colors are encoded on the fly into a small double buffer which is sent by circular DMA. Each
+//! half has to be refilled while the other one is sent, at 2.67 µs per byte, so interrupts
+//! mustn't block for longer than sending a half takes while writing. Halves of 36 bytes, i.e.
+//! four LEDs, leave almost 100 µs.
+
+use crate::dma::{Channel, Error, SpiTxDma};
+
+use crate::hal::{
+    spi::{EightBit, Spi},
+    stm32::SPI1,
+};
+
+use smart_leds_trait::SmartLedsWrite;
+
+pub use smart_leds_trait::RGB8;
+
+/// Encoded bytes per LED
+pub const BYTES_PER_LED: usize = 9;
+
+// Encoded bits for a 0 and a 1
+const ZERO: u32 = 0b100;
+const ONE: u32 = 0b110;
+
+/// Zero bytes latching the data, taking longer than the 280 µs required by newer LEDs
+const RESET_BYTES: usize = 113;
+
+fn encode(color: RGB8) -> [u8; BYTES_PER_LED] {
+    let mut bytes = [0; BYTES_PER_LED];
+
+    for (chunk, &value) in bytes.chunks_exact_mut(3).zip(&[color.g, color.r, color.b]) {
+        let bits = (0..8).rev().fold(0_u32, |bits, i| {
+            bits << 3 | if value >> i & 1 != 0 { ONE } else { ZERO }
+        });
+        chunk.copy_from_slice(&bits.to_be_bytes()[1..]);
+    }
+
+    bytes
+}
+
+/// Encodes colors into halves of the buffer, followed by zeros
+struct Encoder<I> {
+    colors: I,
+    pending: [u8; BYTES_PER_LED],
+    position: usize,
+    trailing: usize,
+}
+
+impl<I: Iterator<Item = RGB8>> Encoder<I> {
+    fn new(colors: I) -> Self {
+        Self {
+            colors,
+            pending: [0; BYTES_PER_LED],
+            position: BYTES_PER_LED,
+            trailing: 0,
+        }
+    }
+
+    fn fill(&mut self, half: &mut [u8]) {
+        for slot in half.iter_mut() {
+            if self.position == BYTES_PER_LED {
+                if let Some(color) = self.colors.next() {
+                    self.pending = encode(color);
+                    self.position = 0;
+                }
+            }
+
+            if self.position < BYTES_PER_LED {
+                *slot = self.pending[self.position];
+                self.position += 1;
+                self.trailing = 0;
+            } else {
+                *slot = 0;
+                self.trailing += 1;
+            }
+        }
+    }
+}
+
+/// A WS2812 strip on the MOSI pin of SPI1, with a double buffer of `L` bytes per half
+pub struct Ws2812<SCKPIN, MISOPIN, MOSIPIN, const L: usize> {
+    bus: Option<(
+        SpiTxDma<SCKPIN, MISOPIN, MOSIPIN, EightBit>,
+        &'static mut [[u8; L]; 2],
+    )>,
+}
+
+impl<SCKPIN, MISOPIN, MOSIPIN, const L: usize> Ws2812<SCKPIN, MISOPIN, MOSIPIN, L> {
+    /// Create the driver from SPI1 set up at 3 MHz, DMA channel 3 and the double buffer
+    pub fn new(
+        spi: Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, EightBit>,
+        channel: Channel<3>,
+        buffer: &'static mut [[u8; L]; 2],
+    ) -> Self {
+        Self {
+            bus: Some((SpiTxDma::new(spi, channel), buffer)),
+        }
+    }
+
+    /// Release the SPI, the DMA channel and the buffer
+    pub fn release(
+        mut self,
+    ) -> (
+        Spi<SPI1, SCKPIN, MISOPIN, MOSIPIN, EightBit>,
+        Channel<3>,
+        &'static mut [[u8; L]; 2],
+    ) {
+        let (spi, buffer) = self.bus.take().unwrap();
+        let (spi, channel) = spi.release();
+        (spi, channel, buffer)
+    }
+}
+
+impl<SCKPIN, MISOPIN, MOSIPIN, const L: usize> SmartLedsWrite
+    for Ws2812<SCKPIN, MISOPIN, MOSIPIN, L>
+{
+    type Error = Error;
+    type Color = RGB8;
+
+    /// Send the colors and latch them, blocking until done
+    ///
+    /// Returns [`Error::Overrun`] if a half of the buffer wasn't refilled in time, in which case
+    /// the strip may show wrong colors until the next write.
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<RGB8>,
+    {
+        let (spi, buffer) = self.bus.take().unwrap();
+
+        let mut encoder = Encoder::new(iterator.into_iter().map(Into::into).fuse());
+        encoder.fill(&mut buffer[0]);
+        encoder.fill(&mut buffer[1]);
+
+        let mut transfer = spi.circ_write(buffer);
+        let result = loop {
+            match transfer.readable_half() {
+                Ok(Some(_)) => {}
+                Ok(None) => continue,
+                Err(e) => break Err(e),
+            }
+
+            // Stop once the reset has been sent and the DMA is in a half of zeros
+            if encoder.trailing >= RESET_BYTES + L {
+                break Ok(());
+            }
+
+            if let Err(e) = transfer.poke(|half, _| encoder.fill(half)) {
+                break Err(e);
+            }
+        };
+
+        let (buffer, spi) = transfer.stop();
+        self.bus = Some((spi, buffer));
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFF: [u8; 3] = [0x92, 0x49, 0x24];
+    const FULL: [u8; 3] = [0xdb, 0x6d, 0xb6];
+    // 0xa5, i.e. 1010_0101
+    const MIXED: [u8; 3] = [0xd3, 0x49, 0xa6];
+
+    #[test]
+    fn bits_are_encoded_msb_first() {
+        assert_eq!(encode(RGB8::new(0, 0x00, 0))[..3], OFF);
+        assert_eq!(encode(RGB8::new(0, 0xff, 0))[..3], FULL);
+        assert_eq!(encode(RGB8::new(0, 0xa5, 0))[..3], MIXED);
+    }
+
+    #[test]
+    fn colors_are_sent_green_red_blue() {
+        let bytes = encode(RGB8::new(0xff, 0x00, 0xa5));
+        assert_eq!(bytes[..3], OFF);
+        assert_eq!(bytes[3..6], FULL);
+        assert_eq!(bytes[6..], MIXED);
+    }
+
+    #[test]
+    fn leds_span_halves() {
+        let colors = [RGB8::new(0xff, 0, 0), RGB8::new(0, 0, 0xa5)];
+        let mut encoder = Encoder::new(colors.iter().copied());
+        let mut sent = [0; 2 * BYTES_PER_LED];
+
+        for half in sent.chunks_exact_mut(6) {
+            encoder.fill(half);
+            assert_eq!(encoder.trailing, 0);
+        }
+
+        assert_eq!(sent[..BYTES_PER_LED], encode(colors[0]));
+        assert_eq!(sent[BYTES_PER_LED..], encode(colors[1]));
+    }
+
+    #[test]
+    fn zeros_are_counted_once_colors_run_out() {
+        let mut encoder = Encoder::new(core::iter::once(RGB8::new(1, 2, 3)));
+        let mut half = [0xff; 6];
+
+        encoder.fill(&mut half);
+        encoder.fill(&mut half);
+        assert_eq!(half[..3], encode(RGB8::new(1, 2, 3))[6..]);
+        assert_eq!(half[3..], [0; 3]);
+        assert_eq!(encoder.trailing, 3);
+
+        encoder.fill(&mut half);
+        assert_eq!(half, [0; 6]);
+        assert_eq!(encoder.trailing, 9);
+    }
+}
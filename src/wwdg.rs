@@ -0,0 +1,338 @@
+//! Window watchdog
+//!
+//! The WWDG resets the MCU both if it isn't refreshed in time and if it is refreshed too early,
+//! which catches code running away in a tight loop as well as hanging. It counts down in ticks of
+//! 4096 PCLK cycles times a prescaler of 1 to 8, [`Wwdg::new`] derives the settings from the
+//! earliest and latest time a refresh should happen and rejects ranges the hardware can't
+//! represent.
+//!
+//! One tick before the reset the early wakeup interrupt fires, which saves the last
+//! [`checkpoint`] passed by the firmware into RAM which isn't initialised at startup. The `WWDG`
+//! interrupt handler has to call [`Wwdg::on_interrupt`]:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn WWDG() {
+//!     Wwdg::on_interrupt();
+//! }
+//! ```
+
+use crate::hal::{
+    rcc::Rcc,
+    stm32::{Interrupt, DBGMCU, RCC, WWDG},
+};
+
+use crate::time::{Duration, Monotonic};
+
+use core::{
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+// CR bits, the counter resets the MCU when passing from 0x40 to 0x3f
+const WDGA: u32 = 1 << 7;
+const COUNTER_MIN: u32 = 0x40;
+const MAX_TICKS: u64 = 64;
+
+// CFR bits
+const WDGTB_SHIFT: u32 = 7;
+const EWI: u32 = 1 << 9;
+
+// SR bits
+const EWIF: u32 = 1 << 0;
+
+// Largest prescaler setting (divider 8)
+const MAX_PRESCALER: u32 = 3;
+
+// RCC bits: WWDG and DBGMCU clock enables and the WWDG reset flag
+const WWDGEN: u32 = 1 << 11;
+const DBGMCUEN: u32 = 1 << 22;
+const WWDGRSTF: u32 = 1 << 30;
+
+// Marks valid diagnostics in uninitialised RAM
+const DIAGNOSTICS_MAGIC: u32 = 0x5757_4447;
+
+/// Errors of the window watchdog configuration
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The latest refresh time is shorter than one tick at the smallest prescaler
+    TimeoutTooShort,
+    /// The latest refresh time is longer than 64 ticks at the largest prescaler
+    TimeoutTooLong,
+    /// The earliest refresh time is not before the latest one, after rounding to ticks
+    WindowTooNarrow,
+}
+
+/// Settings of the WWDG for a refresh window
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Timing {
+    /// Prescaler setting, dividing by 1, 2, 4 or 8
+    pub prescaler: u32,
+    /// Value the counter is reloaded with
+    pub counter: u32,
+    /// Counter value below which refreshes are allowed
+    pub window: u32,
+    /// Length of a tick in ns
+    pub tick_ns: u64,
+}
+
+impl Timing {
+    /// Find the settings with the finest resolution for refreshes between `min_us` and `max_us`
+    /// after the previous one
+    ///
+    /// The earliest time is rounded up and the latest rounded down to whole ticks.
+    pub fn new(pclk_hz: u32, min_us: u32, max_us: u32) -> Result<Self, Error> {
+        let max_ns = u64::from(max_us) * 1000;
+        let min_ns = u64::from(min_us) * 1000;
+
+        let mut too_short = false;
+        for prescaler in 0..=MAX_PRESCALER {
+            let tick_ns = (4096_u64 << prescaler) * 1_000_000_000 / u64::from(pclk_hz.max(1));
+            let ticks = max_ns / tick_ns;
+
+            if ticks == 0 {
+                too_short = true;
+                break;
+            }
+            if ticks > MAX_TICKS {
+                continue;
+            }
+
+            let closed = (min_ns + tick_ns - 1) / tick_ns;
+            if closed >= ticks {
+                return Err(Error::WindowTooNarrow);
+            }
+
+            let counter = COUNTER_MIN - 1 + ticks as u32;
+            return Ok(Self {
+                prescaler,
+                counter,
+                window: counter - closed as u32,
+                tick_ns,
+            });
+        }
+
+        if too_short {
+            Err(Error::TimeoutTooShort)
+        } else {
+            Err(Error::TimeoutTooLong)
+        }
+    }
+
+    /// Earliest allowed refresh after the previous one in µs
+    pub fn min_us(&self) -> u32 {
+        (u64::from(self.counter - self.window) * self.tick_ns / 1000) as u32
+    }
+
+    /// Latest allowed refresh after the previous one in µs
+    pub fn max_us(&self) -> u32 {
+        (u64::from(self.counter - (COUNTER_MIN - 1)) * self.tick_ns / 1000) as u32
+    }
+}
+
+/// State of the firmware when the WWDG was about to reset the MCU
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Diagnostics {
+    /// The last value passed to [`checkpoint`]
+    pub checkpoint: u32,
+    /// Time since [`Monotonic`] was started, zero if it wasn't running
+    pub uptime: Duration,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Log {
+    magic: u32,
+    checkpoint: u32,
+    uptime_low: u32,
+    uptime_high: u32,
+    check: u32,
+}
+
+impl Log {
+    fn new(diagnostics: Diagnostics) -> Self {
+        let uptime = diagnostics.uptime.as_micros();
+        let (low, high) = (uptime as u32, (uptime >> 32) as u32);
+
+        Self {
+            magic: DIAGNOSTICS_MAGIC,
+            checkpoint: diagnostics.checkpoint,
+            uptime_low: low,
+            uptime_high: high,
+            check: !(diagnostics.checkpoint ^ low ^ high),
+        }
+    }
+
+    fn diagnostics(&self) -> Option<Diagnostics> {
+        let check = !(self.checkpoint ^ self.uptime_low ^ self.uptime_high);
+        if self.magic == DIAGNOSTICS_MAGIC && self.check == check {
+            Some(Diagnostics {
+                checkpoint: self.checkpoint,
+                uptime: Duration::from_micros(
+                    u64::from(self.uptime_high) << 32 | u64::from(self.uptime_low),
+                ),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[link_section = ".uninit.WWDG_LOG"]
+static mut LOG: MaybeUninit<Log> = MaybeUninit::uninit();
+
+static CHECKPOINT: AtomicU32 = AtomicU32::new(0);
+
+/// Record the progress of the firmware, e.g. an identifier of the current state or function
+pub fn checkpoint(code: u32) {
+    CHECKPOINT.store(code, Ordering::Relaxed);
+}
+
+/// The running window watchdog
+pub struct Wwdg {
+    wwdg: WWDG,
+    timing: Timing,
+    previous: Option<Diagnostics>,
+}
+
+impl Wwdg {
+    /// Start the WWDG, expecting refreshes between `min_us` and `max_us` apart
+    ///
+    /// The watchdog is stopped while the core is halted by a debugger. Once started, the WWDG can
+    /// only be stopped by a reset.
+    pub fn new(
+        wwdg: WWDG,
+        dbgmcu: &mut DBGMCU,
+        min_us: u32,
+        max_us: u32,
+        rcc: &mut Rcc,
+    ) -> Result<Self, Error> {
+        let timing = Timing::new(rcc.clocks.pclk().0, min_us, max_us)?;
+        let rccr = unsafe { &(*RCC::ptr()) };
+
+        // Take over the diagnostics of the previous run if it ended with a WWDG reset
+        let previous = if rccr.csr.read().bits() & WWDGRSTF != 0 {
+            unsafe { ptr::read_volatile(LOG.as_ptr()) }.diagnostics()
+        } else {
+            None
+        };
+        unsafe {
+            ptr::write_volatile(
+                LOG.as_mut_ptr(),
+                Log {
+                    magic: 0,
+                    checkpoint: 0,
+                    uptime_low: 0,
+                    uptime_high: 0,
+                    check: 0,
+                },
+            )
+        };
+
+        rccr.apb1enr
+            .modify(|r, w| unsafe { w.bits(r.bits() | WWDGEN) });
+        rccr.apb2enr
+            .modify(|r, w| unsafe { w.bits(r.bits() | DBGMCUEN) });
+        dbgmcu.apb1_fz.modify(|_, w| w.dbg_wwdg_stop().set_bit());
+
+        wwdg.cfr
+            .write(|w| unsafe { w.bits(timing.window | timing.prescaler << WDGTB_SHIFT | EWI) });
+        wwdg.sr.write(|w| unsafe { w.bits(0) });
+        wwdg.cr.write(|w| unsafe { w.bits(WDGA | timing.counter) });
+
+        cortex_m::peripheral::NVIC::unpend(Interrupt::WWDG);
+        unsafe {
+            cortex_m::peripheral::NVIC::unmask(Interrupt::WWDG);
+        }
+
+        Ok(Self {
+            wwdg,
+            timing,
+            previous,
+        })
+    }
+
+    /// The settings in use
+    pub fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    /// The diagnostics saved if the last reset was caused by the WWDG
+    pub fn diagnostics_before_reset(&self) -> Option<Diagnostics> {
+        self.previous
+    }
+
+    /// Whether a refresh is allowed now
+    pub fn is_window_open(&self) -> bool {
+        self.wwdg.cr.read().bits() & 0x7f <= self.timing.window
+    }
+
+    /// Reload the counter, which resets the MCU if the window isn't open yet
+    pub fn feed(&mut self) {
+        self.wwdg
+            .cr
+            .write(|w| unsafe { w.bits(WDGA | self.timing.counter) });
+    }
+
+    /// Save the diagnostics, to be called from the `WWDG` interrupt handler
+    ///
+    /// The reset follows one tick later, which leaves at least 85 µs at a PCLK of 48 MHz.
+    pub fn on_interrupt() {
+        let wwdg = unsafe { &(*WWDG::ptr()) };
+        if wwdg.sr.read().bits() & EWIF == 0 {
+            return;
+        }
+        wwdg.sr.write(|w| unsafe { w.bits(0) });
+
+        let log = Log::new(Diagnostics {
+            checkpoint: CHECKPOINT.load(Ordering::Relaxed),
+            uptime: Duration::from_micros(Monotonic::now().as_micros()),
+        });
+        unsafe { ptr::write_volatile(LOG.as_mut_ptr(), log) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PCLK_HZ: u32 = 8_000_000;
+
+    #[test]
+    fn finest_prescaler_is_chosen() {
+        let timing = Timing::new(PCLK_HZ, 10_000, 30_000).unwrap();
+        assert_eq!(
+            timing,
+            Timing {
+                prescaler: 0,
+                counter: 121,
+                window: 101,
+                tick_ns: 512_000,
+            }
+        );
+
+        let timing = Timing::new(PCLK_HZ, 0, 50_000).unwrap();
+        assert_eq!(
+            (timing.prescaler, timing.counter, timing.window),
+            (1, 111, 111)
+        );
+    }
+
+    #[test]
+    fn window_is_rounded_inwards() {
+        let timing = Timing::new(PCLK_HZ, 10_000, 30_000).unwrap();
+        assert_eq!(timing.min_us(), 10_240);
+        assert_eq!(timing.max_us(), 29_696);
+    }
+
+    #[test]
+    fn unrepresentable_ranges_are_rejected() {
+        assert_eq!(Timing::new(PCLK_HZ, 0, 500), Err(Error::TimeoutTooShort));
+        assert_eq!(Timing::new(PCLK_HZ, 0, 300_000), Err(Error::TimeoutTooLong));
+        assert_eq!(
+            Timing::new(PCLK_HZ, 29_000, 29_500),
+            Err(Error::WindowTooNarrow)
+        );
+    }
+}